@@ -79,8 +79,8 @@ void main() {
         //vec4 quad_scale = vec4(vs_out[0].dims, 1.0, 1.0);
         vec4 quad_scale = vec4(dims, 1.0, 1.0) * vec4(vs_out[0].scale, 1.0, 1.0);
         // The translation vector to move it back to a final origin.
-        vec4 quad_transl = vec4((dims.x-1.0) * tile_dims.x/2.0,
-                                (dims.y-1.0 + vs_out[0].scale.y/2.0) * tile_dims.y/2.0, 
+        vec4 quad_transl = vec4((dims.x-1.0) * vs_out[0].scale.x * tile_dims.x/2.0,
+                                ((dims.y-1.0) + 0.5) * vs_out[0].scale.y * tile_dims.y/2.0, 
                                 0.0, 0.0);
         
         // Finally calculate the vertex position.
@@ -94,10 +94,11 @@ void main() {
                            float(sprite_id / spr_per_row));
         // Scale that position by the width of the tiles in uv-space
         vec2 uv_offset = uv_id * sheet_ratio;
-        // Flip the uv unit square vertically (to flip the texture)
+        // Flip the uv unit square vertically (to flip the texture), and
+        // stretch it over the sprite's tiles, which sit above its root tile
         gs_out.tex_coord = unit_uv_verts[i] * dims 
                             + uv_offset        
-                            - uv_offset*(dims - vec2(1.0, 1.0));
+                            - vec2(0.0, (dims.y - 1.0) * sheet_ratio);
         gs_out.color_adj = vs_out[0].color;
         
         EmitVertex();
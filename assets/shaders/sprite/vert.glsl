@@ -17,19 +17,39 @@ layout (location = 4) in uint sprite_id;
 //              ...
 //
 layout (location = 5) in uint sprite_data;
-// Sprite data must be packed as:
-// [ 0x00 0x00 0x00 0x00 ]
-//   |--| |--| |-------|
-//  flags dims reserved
+// Sprite data must be packed as (most significant byte first):
+// [ 0x00 0x00   0x00   0x00 ]
+//   |--| |----| |--|   |--|
+//   anim frames flags  dims
 // Where:
-//  dims  - How many tiles wide and tall the sprite is with the right half
-//          of the byte being x and the left being y. Zero defaults to 1x1.
+//  dims   - How many tiles wide and tall the sprite is with the low half
+//           of the byte being x and the high half y. Zero defaults to 1.
 //
 //  flags  - Flags applying directly to this sprite
+//
+//  frames - How many frames the sprite is animated through, from sprite_id,
+//           each the sprite's width along from the last. Zero is unanimated.
+//
+//  anim   - The animation mode (low 3 bits), and the phase it starts at in
+//           32nds of the animation (high 5 bits)
+layout (location = 6) in float frame_time;
+// frame_time - How many seconds each frame of the animation is shown for
 
 uniform mat4 view_projection;
 uniform int sheet_width;
 uniform int sheet_tile_w;
+// Applied to every sprite of a draw, e.g. a tilemap layer
+uniform vec4 batch_tint;
+uniform vec3 batch_offset;
+// The clock, in seconds, animations are played by
+uniform float time;
+
+// Animation modes, as AnimMode
+const uint ONCE         = 0u;
+const uint ONCE_PERSIST = 1u;
+const uint LOOP         = 2u;
+const uint LOOP_REVERSE = 3u;
+const uint REVERSE      = 4u;
 
 
 out VS_OUT {
@@ -39,16 +59,44 @@ out VS_OUT {
     vec2 dims;
 } vs_out;
 
+// The frame of an animation shown at the current time
+uint animation_frame(uint frames, uint mode, uint phase) {
+    // Playing back and forth shows the end frames once each cycle
+    bool reverses = mode == LOOP_REVERSE || mode == REVERSE;
+    uint cycle = reverses ? max(frames * 2u - 2u, 1u) : frames;
+    uint played = uint(time / frame_time + float(phase * cycle) / 32.0);
+
+    if (mode == LOOP) {
+        return played % frames;
+    }
+    if (mode == LOOP_REVERSE) {
+        played %= cycle;
+    } else if (mode == REVERSE) {
+        played = min(played, cycle);
+    } else {
+        // Once and OncePersist hold their final frame
+        return min(played, frames - 1u);
+    }
+    return played < frames ? played : cycle - played;
+}
+
 void main() {
-    vs_out.id = sprite_id;
     // Unpack sprite data
-    vs_out.dims = vec2(float((sprite_data >> 24) & 0xF) + 1.0,
-                       float((sprite_data >> 28) & 0xF) + 1.0);
+    vs_out.dims = max(vec2(float(sprite_data & 0xFu), float((sprite_data >> 4) & 0xFu)),
+                      vec2(1.0));
+    uint frames = (sprite_data >> 16) & 0xFFu;
+    uint anim = sprite_data >> 24;
+
+    vs_out.id = sprite_id;
+    if (frames > 1u && frame_time > 0.0) {
+        uint frame = animation_frame(frames, anim & 0x7u, anim >> 3);
+        vs_out.id += frame * uint(vs_out.dims.x);
+    }
     
     // Forward attributes to geometry shader
     vs_out.scale = scale; 
-    vs_out.color = color;
+    vs_out.color = color * batch_tint;
 
-    gl_Position = vec4(pos, 1.0);
+    gl_Position = vec4(pos + batch_offset, 1.0);
     // TODO rotation
 }
@@ -0,0 +1,82 @@
+//! Times CollisionSys against a brute-force pass over the same colliders.
+//!
+//! Run with `cargo bench --bench collision`.
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use specs::{Builder, World, WorldExt, RunNow, System, Join};
+use stoneng::ecs::{
+    component::{Position, Collider},
+    resource::CollisionEvents,
+    system::collision::CollisionSys,
+};
+
+const COLLIDERS: usize = 10_000;
+const ITERATIONS: u32 = 10;
+/// The world is sized so that colliders are spread like a large horde
+const WORLD_SIZE: f32 = 10_000.0;
+
+fn build_world() -> World {
+    let mut world = World::new();
+    // Registers the system's storages and inserts the CollisionEvents channel
+    System::setup(&mut CollisionSys::default(), &mut world);
+
+    let mut rng = StdRng::seed_from_u64(0);
+    for _ in 0..COLLIDERS {
+        let (x, y) = (rng.gen_range(0.0..WORLD_SIZE), rng.gen_range(0.0..WORLD_SIZE));
+        world.create_entity()
+            .with(Position::new(x, y, 0.0))
+            .with(Collider::new(40.0, 40.0))
+            .build();
+    }
+    world
+}
+
+/// The previous all-against-all overlap test, counting the events it would emit
+fn brute_force(world: &World) -> usize {
+    let entities = world.entities();
+    let positions = world.read_storage::<Position>();
+    let colliders = world.read_storage::<Collider>();
+
+    let group = (&entities, &positions, &colliders).join();
+    let mut count = 0;
+    for (ent, pos, coll) in group.clone() {
+        for (ent_b, pos_b, coll_b) in group.clone() {
+            if ent == ent_b { continue; }
+            let shape = coll.to_world((pos.x, pos.y), 0.0);
+            let shape_b = coll_b.to_world((pos_b.x, pos_b.y), 0.0);
+            if shape.contact(&shape_b).is_some() {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+fn time<F: FnMut()>(name: &str, iterations: u32, mut f: F) -> Duration {
+    let start = Instant::now();
+    for _ in 0..iterations { f(); }
+    let avg = start.elapsed() / iterations;
+    println!("{:<24} {:>10.2?} / iter", name, avg);
+    avg
+}
+
+fn main() {
+    let mut world = build_world();
+    let mut reader = world.write_resource::<CollisionEvents>().register_reader();
+
+    println!("{} colliders", COLLIDERS);
+    let mut sys = CollisionSys::default();
+    let hashed = time("CollisionSys (grid)", ITERATIONS, || {
+        sys.run_now(&world);
+        world.maintain();
+    });
+    let grid_events = world.read_resource::<CollisionEvents>().read(&mut reader).count();
+
+    let mut brute_events = 0;
+    let brute = time("brute force", 1, || brute_events = brute_force(&world));
+
+    // The brute force finds each pair twice, once from each collider
+    assert_eq!(grid_events / ITERATIONS as usize * 2, brute_events, "contact counts differ");
+    println!("speedup: {:.1}x", brute.as_secs_f64() / hashed.as_secs_f64());
+}
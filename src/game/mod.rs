@@ -5,24 +5,42 @@ use std::sync::Arc;
 use nalgebra_glm::{Vec2, Vec3, Vec4, vec2};
 
 use rand::Rng;
-use specs::{Builder, World, WorldExt, Entity, RunNow, DispatcherBuilder, Dispatcher};
+use specs::{Builder, World, WorldExt, Entity, Join, RunNow, DispatcherBuilder, Dispatcher};
 use stoneng::ecs::component::Scale;
 use stoneng::ecs::{
     resource,
     system,
     component,
+    query::PhysicsQuery,
 };
 use stoneng::event::{KeyEvent, KeyCode, ElementState, MouseButton};
 use stoneng::{
     self, 
     audio::AudioEngine,
     model::spritesheet::SpriteSheet,
+    model::layer::RenderLayers,
+    model::tilemap::{Tilemap, TileDef, CHUNK_SIZE},
+    model::pathfinding::FlowField,
+    model::streaming::{ChunkData, ChunkStreaming, GeneratedChunks},
+    procgen::{self, Generator},
     controller::player,
     event,
 };
 
 mod animation;
 
+/// Collision layers used by the game's colliders
+mod layers {
+    pub const ACTORS:   u32 = 1 << 0;
+    pub const CURSOR:   u32 = 1 << 1;
+    /// Things that can be picked with the cursor
+    pub const PICKABLE: u32 = 1 << 2;
+    pub const BULLETS:  u32 = 1 << 3;
+}
+
+/// How fast bullets leave the gun, fast enough to need continuous collision
+const BULLET_SPEED: f32 = 1500.0;
+
 // A quick macro to return from a function on a failed Option unwrap
 macro_rules! unwrap_or_return {
     ($e: expr) => {
@@ -36,15 +54,17 @@ macro_rules! unwrap_or_return {
 pub struct GameState<'a> {
     audio:              Option<AudioEngine>,
 
-    spritesheet:        SpriteSheet,
+    spritesheet:        Arc<SpriteSheet>,
     world:              Option<World>,
     dispatcher:         Option<Dispatcher<'a, 'a>>,
     time:               std::time::Instant,
     collision_reader:   Option<shrev::ReaderId<resource::CollisionEvent>>,
+    tile_hit_reader:    Option<shrev::ReaderId<resource::TileHit>>,
 
     cursor:             Option<Entity>,
     cursor_pos:         (f64, f64),
     player_contr:       Option<player::PlayerController>,
+    zombie:             Option<Entity>,
 }
 
 impl<'a> GameState<'a> {
@@ -55,15 +75,17 @@ impl<'a> GameState<'a> {
         Self {
             audio: None,
 
-            spritesheet: SpriteSheet::new(spritesheet_layout, spritesheet_data).unwrap(),
+            spritesheet: Arc::new(SpriteSheet::new(spritesheet_layout, spritesheet_data).unwrap()),
             world: None,
             dispatcher: None,
             time: std::time::Instant::now(),
             collision_reader: None,
+            tile_hit_reader: None,
 
             cursor: None,
             cursor_pos: (0.0, 0.0),
             player_contr: None,
+            zombie: None,
         }
     }
 }
@@ -80,15 +102,47 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
         world.insert(resource::DeltaTime(0.0));
         world.insert(resource::WindowSize(800.0, 600.0));
         world.insert(resource::View(0.0 ,0.0, 0.0));
+        world.insert(RenderLayers::default());
+        world.insert(resource::PhysicsMode::default());
         let mut collision_events = resource::CollisionEvents::new();
         self.collision_reader = Some(collision_events.register_reader());
         world.insert(collision_events);
+        let mut tile_hits = resource::TileHits::new();
+        self.tile_hit_reader = Some(tile_hits.register_reader());
+        world.insert(tile_hits);
+
+        // An endless meadow, with patches of long grass over the plain grass
+        let meadow = procgen::TerrainGenerator::default()
+            .with_scale(6.0)
+            .with_bands(vec![(0.6, procgen::FLOOR), (1.0, '"')]);
+        let meadow_seed = rand::thread_rng().gen();
+        let meadow_chunks = GeneratedChunks::new(meadow_seed, move |coord, rng| {
+            let mut chunk = ChunkData::default();
+            for x in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let cell = meadow.cell((coord.0 * CHUNK_SIZE + x, coord.1 * CHUNK_SIZE + y), meadow_seed);
+                    let var = if cell == '"' { rng.gen_range(1..8) } else { 0 };
+                    chunk.add_tile((x, y), &TileDef::new("grass", "floor").with_variant(&var.to_string()));
+                }
+            }
+            chunk
+        });
+        // Chunks are about a screen across
+        world.insert(ChunkStreaming::default().with_radius(1.5, 2.5));
 
         // Creates the system dispatcher, the order here is important
         let mut dispatcher = DispatcherBuilder::new()
-            .with(system::collision::CollisionSys, "collision", &[])
+            .with(system::streaming::ChunkStreamSys::new(meadow_chunks), "chunk_stream", &[])
+            .with(system::tilemap::TileEventSys, "tile_events", &["chunk_stream"])
             .with(system::particle::ParticleSys, "particle", &[])
-            .with(system::movement::VelocitySys, "velocity", &[])
+            .with(system::pathfinding::NavGridSys::default(), "nav_grid", &["tile_events"])
+            .with(system::pathfinding::PathFollowSys, "path_follow", &["nav_grid"])
+            .with(system::pathfinding::FlowFieldSys, "flow_field", &["nav_grid"])
+            .with(system::pathfinding::FlowAgentSys::default(), "flow_agents", &["flow_field"])
+            .with(system::movement::RigidBodySys, "rigid_body", &[])
+            .with(system::movement::VelocitySys, "velocity", &["rigid_body", "path_follow", "flow_agents"])
+            .with(system::collision::CollisionSys::default(), "collision", &["velocity", "tile_events"])
+            .with(system::fov::FovSys, "fov", &["collision"])
             .with(system::sprite::AnimSpriteSys, "anim_sprite", &[])
             // thread_local must be used with OpenGL systems as OpenGL only runs on main thread
             .with_thread_local(system::RenderSys::default())
@@ -109,14 +163,41 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
                 .get("zombie").unwrap()
                 .clone();
 
-        let mut pos = component::Position { x: 0.0, y: 0.0, z: -5.0 };
+        let mut pos = component::Position { x: 0.0, y: 0.0, z: 0.0 };
         let scale = component::Scale { x: 5.0, y: 5.0 };
-        world.create_entity()
+        let zombie_size = (self.spritesheet.tile_width-2) as f32 * scale.x;
+        let zombie = world.create_entity()
             .with(pos.clone())
             .with(scale.clone())
             .with(component::Color::default())
             .with(component::Sprite::from(zombie_tile.clone())) 
+            .with(component::RenderLayer::new("actors"))
+            .with(component::Velocity::new(0.0, 0.0))
+            .with(component::FogOfWar::Hide)
+            .with(component::PathFollower::new(60.0).with_arrive_distance(zombie_size / 2.0))
+            .with(component::Collider::new(zombie_size, zombie_size)
+                .with_body(component::BodyType::Kinematic)
+                .with_layers(layers::ACTORS | layers::PICKABLE, component::Collider::ALL_LAYERS))
             .build();
+        self.zombie = Some(zombie);
+
+        // A horde following the flow field to the player
+        for i in 0..12 {
+            let (x, y) = (-400.0 + (i % 4) as f32 * 40.0, -300.0 + (i / 4) as f32 * 40.0);
+            world.create_entity()
+                .with(component::Position { x, y, z: 0.0 })
+                .with(scale.clone())
+                .with(component::Color::default())
+                .with(component::Sprite::from(zombie_tile.clone()))
+                .with(component::RenderLayer::new("actors"))
+                .with(component::Velocity::new(0.0, 0.0))
+                .with(component::FlowAgent::new(45.0, zombie_size * 1.2))
+                .with(component::FogOfWar::Hide)
+                .with(component::Collider::new(zombie_size, zombie_size)
+                    .with_body(component::BodyType::Dynamic)
+                    .with_layers(layers::ACTORS, component::Collider::ALL_LAYERS))
+                .build();
+        }
 
         pos.x = 100.0;
         pos.y = 100.0;
@@ -128,9 +209,15 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
                 .with(component::Color::default())
                 .with(component::Sprite::from(player_tile.clone()))
                 .with(component::Animation::from(player_anim))
+                .with(component::RenderLayer::with_order("actors", 1))
+                .with(component::RevealsOverhead)
+                .with(component::Viewer::new(12))
                 .with(component::PointLight::new_scaled(50.0))
                 .with(component::Velocity::new(0.0, 0.0))
-                .with(component::Collider::new(player_size, player_size))
+                .with(component::RigidBody::new(1.0))
+                .with(component::Collider::new(player_size, player_size)
+                    .with_body(component::BodyType::Dynamic)
+                    .with_layers(layers::ACTORS, layers::ACTORS | PhysicsQuery::WALL_LAYER))
                 .with(component::Text{ 
                     content: String::from("Bobert"), size: 2.0, offset: (-25.0, 45.0) 
                 })
@@ -139,7 +226,7 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
         self.player_contr = Some(
             player::PlayerController::new(
                 player_entity, 
-                player::MovementType::Instant(250.0)
+                player::MovementType::Velocity(player::MovementInfo::new(250.0, 2500.0, 2000.0))
                 )
             );
 
@@ -150,34 +237,19 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
         let cursor_size = (self.spritesheet.tile_width-1) as f32 * 3.0;
         self.cursor = Some(
             world.create_entity()
-                .with(component::Position::new(0.0, 0.0, 1.0))
+                .with(component::Position::new(0.0, 0.0, 0.0))
                 .with(component::Scale::new(3.0, 3.0))
-                .with(component::Collider::new(cursor_size, cursor_size))
+                .with(component::RenderLayer::new("ui"))
+                .with(component::Collider::new(cursor_size, cursor_size)
+                    .with_layers(layers::CURSOR, layers::PICKABLE)
+                    .as_trigger())
                 .with(component::Sprite::from(cursor_sprite))
                 .with(component::Color::default())
                 .build()
         );
         
        
-        let grass_sprite = self.spritesheet.sprites.get("grass").unwrap().clone();
-        let mut rng = rand::thread_rng();
-        for i in -25..25 {
-            for j in -25..25 {
-                let var;
-                if rng.gen_bool(0.1) {
-                    var = rng.gen_range(1..8);
-                } else {
-                    var = 0;
-                }
-                world.create_entity()
-                    .with(component::Tile { pos: (i,j) })
-                    .with(component::Floor { 
-                        schema: grass_sprite.variants.get(&var.to_string()[..]).unwrap().clone()
-                    })
-                    .with(component::Color::default())
-                    .build();
-            }
-        }
+        world.insert(Tilemap::new(self.spritesheet.clone()));
         
         world.maintain();
 
@@ -226,6 +298,14 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
         let player_pos = positions.get(player_contr.player).unwrap();
         let player_vec = vec2(player_pos.x, player_pos.y);
         let aim_dir = (cursor_vec-player_vec).normalize();
+
+        // The zombie shambles after the player, around anything in the way
+        let mut followers = world.write_component::<component::PathFollower>();
+        if let Some(follower) = self.zombie.and_then(|zombie| followers.get_mut(zombie)) {
+            follower.go_to((player_pos.x, player_pos.y));
+        }
+        let player_tile = world.read_resource::<Tilemap>().world_to_tile((player_pos.x, player_pos.y));
+        world.write_resource::<FlowField>().set_goals([player_tile]);
         
         // Determine walking/idle
         let vels = world.read_component::<component::Velocity>();
@@ -262,10 +342,24 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
         let player_name = unwrap_or_return!(texts.get_mut(player_contr.player));
         player_name.content = "Bobert".into();
 
+        let colliders = world.read_component::<component::Collider>();
+        let is_bullet = |e: Entity| colliders.get(e).is_some_and(|c| c.layer == layers::BULLETS);
         for collision in collision_events {
-            if collision.collider_a == player_contr.player {
+            let contact = collision.contact();
+            if collision.is_touching() && contact.involves(player_contr.player) {
                 player_name.content = "Boop".into();
             }
+
+            // Bullets are spent on whatever they hit first
+            if let resource::CollisionEvent::Started(contact) = collision {
+                for entity in [contact.collider_a, contact.collider_b] {
+                    if is_bullet(entity) { let _ = world.entities().delete(entity); }
+                }
+            }
+        }
+        let tile_hits = world.read_resource::<resource::TileHits>();
+        for hit in tile_hits.read(self.tile_hit_reader.as_mut().unwrap()) {
+            if is_bullet(hit.collider) { let _ = world.entities().delete(hit.collider); }
         }
 
     }
@@ -324,8 +418,10 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
             let world = unwrap_or_return!(&mut self.world);
             let view = *world.read_resource::<resource::View>();
             let cursor = unwrap_or_return!(&self.cursor);
+            let player = unwrap_or_return!(&self.player_contr).player;
             let positions = world.read_component::<component::Position>();
             let pos = positions.get(*cursor).unwrap().clone();
+            let player_pos = *positions.get(player).unwrap();
             std::mem::drop(positions);
 
             let sprite = self.spritesheet.sprites
@@ -340,12 +436,52 @@ impl<'a> stoneng::EngineCore for GameState<'a> {
                 .with(component::Scale { x: 3.0, y: 3.0 })
                 .with(component::Sprite::from(sprite.clone()))
                 .with(component::Animation::from(flash_anim))
+                .with(component::RenderLayer::new("fx"))
                 .with(component::PointLight::new_scaled(50.0))
                 .build();
 
+            // Fire a bullet from the player towards the cursor
+            let aim = (vec2(pos.x, pos.y) - vec2(player_pos.x, player_pos.y)).normalize() * BULLET_SPEED;
+            if !aim.x.is_nan() {
+                world.create_entity()
+                    .with(player_pos)
+                    .with(component::Color::default())
+                    .with(component::Scale { x: 1.0, y: 1.0 })
+                    .with(component::Sprite::from(sprite.clone()))
+                    .with(component::RenderLayer::new("fx"))
+                    .with(component::Velocity::new(aim.x, aim.y))
+                    .with(component::Lifetime { remaining: 2.0 })
+                    .with(component::Collider::circle(2.0)
+                        .with_body(component::BodyType::Dynamic)
+                        .with_layers(layers::BULLETS, layers::ACTORS | PhysicsQuery::WALL_LAYER))
+                    .with(component::Ccd)
+                    .build();
+            }
+
             self.audio.as_ref().unwrap().play_sfx();
         }
         if event.button == MouseButton::Right && event.state == ElementState::Pressed {
+            // Knock every body near the cursor away from it
+            let world = unwrap_or_return!(&mut self.world);
+            let cursor = unwrap_or_return!(&self.cursor);
+            let positions = world.read_component::<component::Position>();
+            let mut bodies = world.write_component::<component::RigidBody>();
+            let center = positions.get(*cursor).map(|p| (p.x, p.y)).unwrap();
+            for (pos, body) in (&positions, &mut bodies).join() {
+                body.add_explosion((pos.x, pos.y), center, 1000.0, 150.0);
+            }
+        }
+        if event.button == MouseButton::Middle && event.state == ElementState::Pressed {
+            // Scuff, or clear, the ground under the cursor
+            let world = unwrap_or_return!(&mut self.world);
+            let cursor = unwrap_or_return!(&self.cursor);
+            let center = world.read_component::<component::Position>().get(*cursor).map(|p| (p.x, p.y)).unwrap();
+            let mut tilemap = world.write_resource::<Tilemap>();
+            let tile = tilemap.world_to_tile(center);
+            if tilemap.remove_tile(tile, "decals").is_none() {
+                let dirt = self.spritesheet.sprites["grass"].variants["dirt-trdl"].clone();
+                tilemap.set_tile(tile, "decals", dirt);
+            }
         }
     }
 
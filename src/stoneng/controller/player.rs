@@ -26,6 +26,9 @@ pub struct MovementInfo {
     accel:      f32,
     deccel:     f32,
 }
+impl MovementInfo {
+    pub fn new(max_speed: f32, accel: f32, deccel: f32) -> Self { Self { max_speed, accel, deccel } }
+}
 
 /// PlayerController are used to translate raw input into player actions such
 /// as movement.
@@ -65,7 +68,20 @@ impl PlayerController {
                 player_vel.x = self.move_vec.x * max_speed;
                 player_vel.y = self.move_vec.y * max_speed;
             },
-            MovementType::Velocity(move_info) => todo!(),
+            // Accelerate towards the defined max_speed, or slow to a stop
+            MovementType::Velocity(move_info) => {
+                let target = self.move_vec * move_info.max_speed;
+                let current = vec2(player_vel.x, player_vel.y);
+                // Moving faster than max_speed (e.g. from knockback) slows down gradually
+                let moving = self.move_vec.norm_squared() > 0.0 && current.norm() <= move_info.max_speed;
+                let rate = if moving { move_info.accel } else { move_info.deccel };
+
+                let diff = target - current;
+                let step = rate * dt as f32;
+                let change = if diff.norm() <= step { diff } else { diff.normalize() * step };
+                player_vel.x += change.x;
+                player_vel.y += change.y;
+            },
         }
     }
     
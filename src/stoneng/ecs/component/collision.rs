@@ -1,15 +1,143 @@
-use specs::{Component, DenseVecStorage, Entity};
+use specs::{Component, DenseVecStorage, NullStorage, Entity};
 
+use crate::EngineError;
+use crate::model::{Rect, shape::{ColliderShape, Convex}};
+
+/// How a collider responds to overlapping other colliders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyType {
+    /// Never moves, dynamic bodies are pushed out of it.
+    Static,
+    /// Moved only by game code, pushes dynamic bodies but is never pushed itself.
+    Kinematic,
+    /// Pushed out of any body it overlaps.
+    Dynamic,
+}
+
+/// A collision shape, centered on the entity's position plus an offset.
+///
+/// Two colliders only interact when each one's layer is within the other's mask.
+/// Solid tiles are on the `PhysicsQuery::WALL_LAYER`, the highest bit, so only
+/// colliders whose mask includes it are kept out of them.
+/// Triggers report overlaps but are never pushed, nor push others.
 #[allow(dead_code)]
 #[derive(Debug, Component, Clone)]
 #[storage(DenseVecStorage)]
 pub struct Collider {
-    pub width:  f32,
-    pub height: f32,
+    pub shape:      ColliderShape,
+    /// The shape's offset from the entity's position, before rotation
+    pub offset:     (f32, f32),
+    pub body:       BodyType,
+
+    /// The collision layer bits this collider occupies
+    pub layer:      u32,
+    /// The collision layer bits this collider can hit
+    pub mask:       u32,
+    /// Report overlaps without physically resolving them
+    pub trigger:    bool,
 }
 impl Collider {
+    /// The layer colliders are placed on when none is given
+    pub const DEFAULT_LAYER: u32 = 1;
+    /// A mask which hits every layer
+    pub const ALL_LAYERS: u32 = u32::MAX;
+
+    /// Creates a kinematic box collider, which reports overlaps without being pushed.
     pub fn new(width: f32, height: f32) -> Self {
-        Self { width, height }
+        Self::from_shape(ColliderShape::Aabb { half_extents: (width/2.0, height/2.0) })
+    }
+    pub fn circle(radius: f32) -> Self { Self::from_shape(ColliderShape::Circle { radius }) }
+    pub fn capsule(half_height: f32, radius: f32) -> Self {
+        Self::from_shape(ColliderShape::Capsule { half_height, radius })
+    }
+    /// Creates a kinematic convex polygon collider, see `ColliderShape::polygon`.
+    pub fn polygon(points: Vec<(f32, f32)>) -> Result<Self, EngineError> {
+        Ok(Self::from_shape(ColliderShape::polygon(points)?))
+    }
+
+    /// Creates a kinematic collider of any shape.
+    pub fn from_shape(shape: ColliderShape) -> Self {
+        Self { 
+            shape,
+            offset: (0.0, 0.0),
+            body: BodyType::Kinematic,
+            layer: Self::DEFAULT_LAYER,
+            mask: Self::ALL_LAYERS,
+            trigger: false,
+        }
+    }
+    pub fn with_body(self, body: BodyType) -> Self { Self { body, ..self } }
+    pub fn with_offset(self, x: f32, y: f32) -> Self { Self { offset: (x, y), ..self } }
+    pub fn with_layers(self, layer: u32, mask: u32) -> Self { Self { layer, mask, ..self } }
+    pub fn as_trigger(self) -> Self { Self { trigger: true, ..self } }
+
+    /// Places the collider's shape in the world, for an entity at `position`
+    /// rotated by `rotation` degrees.
+    pub fn to_world(&self, position: (f32, f32), rotation: f32) -> Convex {
+        self.shape.to_world(self.offset, position, rotation)
+    }
+
+    /// A box around the collider at any rotation, for an entity at `position`,
+    /// which is cheaper to find than its shape
+    pub fn rough_bounds(&self, position: (f32, f32)) -> Rect {
+        let reach = self.offset.0.hypot(self.offset.1) + self.shape.reach();
+        Rect::from_center(position, (reach, reach))
+    }
+
+    /// Whether the layers and masks of two colliders allow them to interact
+    pub fn can_collide(&self, other: &Collider) -> bool {
+        self.layer & other.mask != 0 && other.layer & self.mask != 0
     }
 }
 
+/// Opts a collider into continuous collision detection.
+///
+/// Rather than only testing where it ends up, the collider is swept along its
+/// velocity for the update, so small and fast colliders (like bullets) can't
+/// pass through thin ones between updates. Contacts found this way carry the
+/// time of impact.
+///
+/// # Example
+/// ```
+/// # use specs::prelude::*;
+/// # use stoneng::ecs::{component::*, resource::DeltaTime, system::collision::CollisionSys};
+/// let mut world = World::new();
+/// let mut collision = CollisionSys::default();
+/// System::setup(&mut collision, &mut world);
+/// world.insert(DeltaTime(1.0));
+/// world.create_entity()
+///     .with(Position { x: 0.0, y: 0.0, z: 0.0 })
+///     .with(Collider::new(1000.0, 10.0).with_body(BodyType::Static))
+///     .build();
+///
+/// // Balls, each created where it ended up after moving for the update
+/// let ball = |world: &mut World, (x, y): (f32, f32), (vx, vy): (f32, f32)| world.create_entity()
+///     .with(Position { x, y, z: 0.0 })
+///     .with(Velocity::new(vx, vy))
+///     .with(Collider::circle(1.0).with_body(BodyType::Dynamic))
+///     .with(Ccd)
+///     .build();
+/// // A bullet which skimmed across the floor and out the other side
+/// let bullet = ball(&mut world, (600.0, -20.0), (1000.0, -50.0));
+/// // Balls which started touching the floor's underside, sliding along it,
+/// // dropping away from it and pressing up into it
+/// let sliding = ball(&mut world, (105.0, -6.0), (5.0, 0.0));
+/// let dropping = ball(&mut world, (-100.0, -11.0), (0.0, -5.0));
+/// let pressing = ball(&mut world, (205.0, -1.0), (5.0, 5.0));
+/// collision.run_now(&world);
+///
+/// let positions = world.read_storage::<Position>();
+/// let at = |entity, x: f32, y: f32| {
+///     let pos = positions.get(entity).unwrap();
+///     (pos.x - x).abs() < 0.1 && (pos.y - y).abs() < 0.1
+/// };
+/// // Stopped on the floor's surface, where it first touched
+/// assert!(at(bullet, 80.0, 6.0));
+/// // Only the motion into the floor is stopped
+/// assert!(at(sliding, 105.0, -6.0));
+/// assert!(at(dropping, -100.0, -11.0));
+/// assert!(at(pressing, 205.0, -6.0));
+/// ```
+#[derive(Debug, Component, Clone, Copy, Default)]
+#[storage(NullStorage)]
+pub struct Ccd;
@@ -0,0 +1,191 @@
+use specs::{Component, VecStorage};
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+
+use crate::EngineError;
+
+/// An RGBA colour with straight (non-premultiplied) alpha.
+///
+/// Colours (de)serialize as hex strings, e.g. `"#ff8800"`, but will also
+/// accept a `(r: 1.0, g: 0.5, b: 0.0, a: 1.0)` struct.
+#[repr(C)]
+#[derive(Debug, Component, Clone, Copy, PartialEq)]
+#[storage(VecStorage)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+impl Color {
+    pub fn new(r: f32, g: f32, b: f32, a: f32) -> Self { Self { r, g, b, a } }
+    pub fn rgb(r: f32, g: f32, b: f32) -> Self { Self { r, g, b, a: 1.0 } }
+    pub fn with_alpha(self, a: f32) -> Self { Self { a, ..self } }
+
+    /// Parses a colour from a `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` hex string.
+    ///
+    /// The leading `#` is optional.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::ecs::component::Color;
+    /// let orange = Color::from_hex("#ff8800").unwrap();
+    /// assert_eq!(orange, Color::new(1.0, 0x88 as f32 / 255.0, 0.0, 1.0));
+    /// assert_eq!(Color::from_hex("f80").unwrap(), orange);
+    /// assert_eq!(orange.to_hex(), "#ff8800");
+    ///
+    /// assert!(Color::from_hex("#ff88").unwrap().a < 1.0);
+    /// assert!(Color::from_hex("#ff88000").is_err());
+    /// ```
+    pub fn from_hex(hex: &str) -> Result<Self, EngineError> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let error = || EngineError::ColorParseError(format!("Invalid hex colour \"{}\"", hex));
+
+        // Short forms repeat each digit, e.g. "f80" == "ff8800"
+        let expanded: String = match digits.len() {
+            3 | 4 => digits.chars().flat_map(|c| [c, c]).collect(),
+            6 | 8 => digits.into(),
+            _ => return Err(error()),
+        };
+
+        let mut channels = [1.0; 4];
+        for (i, channel) in channels.iter_mut().enumerate().take(expanded.len() / 2) {
+            let byte = expanded.get(i*2..i*2 + 2).ok_or_else(error)?;
+            let byte = u8::from_str_radix(byte, 16).map_err(|_| error())?;
+            *channel = byte as f32 / 255.0;
+        }
+
+        let [r, g, b, a] = channels;
+        Ok(Self { r, g, b, a })
+    }
+
+    /// Formats the colour as a hex string, omitting the alpha when opaque.
+    pub fn to_hex(&self) -> String {
+        let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let rgb = format!("#{:02x}{:02x}{:02x}", byte(self.r), byte(self.g), byte(self.b));
+        if byte(self.a) == 255 { rgb } else { format!("{}{:02x}", rgb, byte(self.a)) }
+    }
+
+    /// Builds an opaque colour from a hue (degrees), saturation and value.
+    pub fn from_hsv(hue: f32, saturation: f32, value: f32) -> Self {
+        let chroma = value * saturation;
+        Self::from_hue(hue, chroma, value - chroma)
+    }
+
+    /// Builds an opaque colour from a hue (degrees), saturation and lightness.
+    pub fn from_hsl(hue: f32, saturation: f32, lightness: f32) -> Self {
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+        Self::from_hue(hue, chroma, lightness - chroma / 2.0)
+    }
+
+    /// Shared hue sector conversion of HSV and HSL
+    fn from_hue(hue: f32, chroma: f32, min: f32) -> Self {
+        let sector = hue.rem_euclid(360.0) / 60.0;
+        let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+        let (r, g, b) = match sector as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        Self::rgb(r + min, g + min, b + min)
+    }
+
+    /// Linearly interpolates each channel towards `other`.
+    pub fn lerp(&self, other: &Color, t: f32) -> Self {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        Self {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+
+    /// Interpolates towards `other` in the Oklab colour space.
+    ///
+    /// This keeps the perceived brightness even across the gradient, avoiding
+    /// the muddy midpoints of a plain `lerp`.
+    pub fn lerp_perceptual(&self, other: &Color, t: f32) -> Self {
+        let (a, b) = (self.to_oklab(), other.to_oklab());
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        let mixed = [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])];
+        Self::from_oklab(mixed, mix(self.a, other.a))
+    }
+
+    /// Returns the colour with its rgb channels multiplied by its alpha.
+    pub fn premultiplied(&self) -> Self {
+        Self { r: self.r * self.a, g: self.g * self.a, b: self.b * self.a, a: self.a }
+    }
+
+    /// Reverses `premultiplied`, fully transparent colours become transparent black.
+    pub fn unpremultiplied(&self) -> Self {
+        if self.a <= 0.0 { return Self::new(0.0, 0.0, 0.0, 0.0); }
+        Self { r: self.r / self.a, g: self.g / self.a, b: self.b / self.a, a: self.a }
+    }
+
+    fn to_oklab(self) -> [f32; 3] {
+        let (r, g, b) = (to_linear(self.r), to_linear(self.g), to_linear(self.b));
+        let l = (0.41222147 * r + 0.53633254 * g + 0.051445993 * b).cbrt();
+        let m = (0.2119035 * r + 0.6806995 * g + 0.10739696 * b).cbrt();
+        let s = (0.08830246 * r + 0.28171884 * g + 0.6299787 * b).cbrt();
+        [
+            0.21045426 * l + 0.7936178 * m - 0.004072047 * s,
+            1.9779985 * l - 2.4285922 * m + 0.4505937 * s,
+            0.025904037 * l + 0.78277177 * m - 0.80867577 * s,
+        ]
+    }
+
+    fn from_oklab(lab: [f32; 3], alpha: f32) -> Self {
+        let [lightness, a, b] = lab;
+        let l = (lightness + 0.39633778 * a + 0.21580376 * b).powi(3);
+        let m = (lightness - 0.105561346 * a - 0.06385417 * b).powi(3);
+        let s = (lightness - 0.08948418 * a - 1.2914855 * b).powi(3);
+        Self {
+            r: from_linear( 4.0767417 * l - 3.3077116 * m + 0.23096993 * s),
+            g: from_linear(-1.268438 * l + 2.6097574 * m - 0.3413194 * s),
+            b: from_linear(-0.0041960863 * l - 0.7034186 * m + 1.7076147 * s),
+            a: alpha,
+        }
+    }
+}
+impl From<Color> for (f32, f32, f32, f32) {
+    fn from(c: Color) -> Self { (c.r, c.g, c.b, c.a) }
+}
+impl Default for Color { fn default() -> Self { Self { r: 1.0, g: 1.0, b: 1.0, a: 1.0 } } }
+
+impl Serialize for Color {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+impl<'de> Deserialize<'de> for Color {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        /// The accepted serialized forms of a Color
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum ColorRepr {
+            Hex(String),
+            Rgba { r: f32, g: f32, b: f32, #[serde(default = "opaque")] a: f32 },
+        }
+        fn opaque() -> f32 { 1.0 }
+
+        match ColorRepr::deserialize(deserializer)? {
+            ColorRepr::Hex(hex) => Color::from_hex(&hex)
+                .map_err(|_| serde::de::Error::custom(format!("invalid hex colour \"{}\"", hex))),
+            ColorRepr::Rgba { r, g, b, a } => Ok(Color { r, g, b, a }),
+        }
+    }
+}
+
+/// Converts an sRGB encoded channel into linear light
+fn to_linear(c: f32) -> f32 {
+    if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+}
+
+/// Converts a linear light channel into sRGB encoding
+fn from_linear(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 { c * 12.92 } else { 1.055 * c.powf(1.0 / 2.4) - 0.055 }
+}
@@ -0,0 +1,24 @@
+use specs::{Component, DenseVecStorage};
+
+/// Sees the tiles around it, which the FovSys marks visible in the VisibilityGrid.
+#[derive(Debug, Component, Clone, Copy)]
+#[storage(DenseVecStorage)]
+pub struct Viewer {
+    /// How many tiles away the viewer can see
+    pub radius: i32,
+}
+impl Viewer {
+    pub fn new(radius: i32) -> Self { Self { radius } }
+}
+
+/// How an entity's sprite is drawn while its tile isn't in sight of a Viewer.
+///
+/// Entities without one are always drawn.
+#[derive(Debug, Component, Clone, Copy, PartialEq, Eq)]
+#[storage(DenseVecStorage)]
+pub enum FogOfWar {
+    /// Only drawn while in sight, for anything which moves about
+    Hide,
+    /// Drawn dimmed on remembered tiles, like the tiles themselves, for scenery
+    Dim,
+}
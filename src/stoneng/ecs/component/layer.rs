@@ -0,0 +1,22 @@
+use specs::{Component, DenseVecStorage, NullStorage};
+
+/// Places an entity's sprite on a named layer of the `RenderLayers` table.
+///
+/// The order sorts sprites within the layer, higher orders are drawn on top.
+/// Entities without a RenderLayer are drawn using their Position's z.
+#[derive(Debug, Component, Clone)]
+#[storage(DenseVecStorage)]
+pub struct RenderLayer {
+    pub name:   String,
+    pub order:  u16,
+}
+impl RenderLayer {
+    pub fn new(name: &str) -> Self { Self { name: name.into(), order: 0 } }
+    pub fn with_order(name: &str, order: u16) -> Self { Self { name: name.into(), order } }
+}
+
+/// Marks an entity, usually the player, which overhead tilemap layers fade
+/// away from while it stands beneath them.
+#[derive(Debug, Component, Clone, Copy, Default)]
+#[storage(NullStorage)]
+pub struct RevealsOverhead;
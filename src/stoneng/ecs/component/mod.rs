@@ -1,9 +1,13 @@
 pub mod transform;
 pub mod sprite;
+pub mod color;
 pub mod physics;
-pub mod tile;
 pub mod collision;
 pub mod particle; 
+pub mod layer;
+pub mod path;
+pub mod fov;
+pub mod streaming;
 
 use specs::{Component, DenseVecStorage};
 use crate::renderer::{
@@ -15,21 +19,31 @@ pub use transform::Position as Position;
 pub use transform::Scale as Scale;
 pub use transform::Rotation as Rotation;
 
-pub use sprite::Color as Color;
+pub use color::Color as Color;
 pub use sprite::Sprite as Sprite;
 pub use sprite::Animation as Animation;
 
 pub use physics::Velocity as Velocity;
+pub use physics::RigidBody as RigidBody;
  
-pub use tile::Tile as Tile;
-pub use tile::Floor as Floor;
-pub use tile::Wall as Wall;
-
 pub use particle::Lifetime as Lifetime;
 pub use particle::Wandering as Wandering;
 pub use particle::Scaling as Scaling;
 
 pub use collision::Collider as Collider;
+pub use collision::BodyType as BodyType;
+pub use collision::Ccd as Ccd;
+
+pub use layer::RenderLayer as RenderLayer;
+pub use layer::RevealsOverhead as RevealsOverhead;
+
+pub use path::PathFollower as PathFollower;
+pub use path::FlowAgent as FlowAgent;
+
+pub use fov::Viewer as Viewer;
+pub use fov::FogOfWar as FogOfWar;
+
+pub use streaming::ChunkEntity as ChunkEntity;
 
 #[derive(Debug, Component, Clone, Copy)]
 #[storage(DenseVecStorage)]
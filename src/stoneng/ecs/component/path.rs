@@ -0,0 +1,73 @@
+use specs::{Component, DenseVecStorage};
+
+/// Walks an entity to a target, along a path across the NavGrid.
+///
+/// The PathFollowSys finds the path, finding it again whenever the target
+/// moves to another tile or the grid changes, and steers the entity's
+/// Velocity towards each waypoint in turn. The entity stops once it arrives,
+/// or if there's no way to the target.
+///
+/// # Example
+/// ```
+/// # use stoneng::ecs::component::PathFollower;
+/// let mut zombie = PathFollower::new(40.0).with_arrive_distance(2.0);
+/// zombie.go_to((120.0, 80.0));
+/// assert_eq!(zombie.target, Some((120.0, 80.0)));
+/// ```
+#[derive(Debug, Component, Clone)]
+#[storage(DenseVecStorage)]
+pub struct PathFollower {
+    /// Where to walk to, in world coordinates, or None to stand still
+    pub target:             Option<(f32, f32)>,
+    /// How fast to walk, in units per second
+    pub speed:              f32,
+    /// How near a waypoint must be for it to count as reached
+    pub arrive_distance:    f32,
+
+    /// The waypoints left to walk through, in world coordinates, the next first
+    pub(crate) waypoints:   Vec<(f32, f32)>,
+    /// The goal tile and grid revision the waypoints were found for
+    pub(crate) found_for:   Option<((i32, i32), u64)>,
+}
+impl PathFollower {
+    pub fn new(speed: f32) -> Self {
+        Self { target: None, speed, arrive_distance: 1.0, waypoints: vec![], found_for: None }
+    }
+    pub fn with_arrive_distance(self, arrive_distance: f32) -> Self { Self { arrive_distance, ..self } }
+
+    pub fn go_to(&mut self, target: (f32, f32)) { self.target = Some(target); }
+    pub fn stop(&mut self) { self.target = None; }
+
+    /// The waypoints still to be walked through, the last being the target
+    pub fn waypoints(&self) -> &[(f32, f32)] { &self.waypoints }
+
+    /// Whether there are waypoints left to walk to, false once arrived or
+    /// when there's no way to the target
+    pub fn is_moving(&self) -> bool { !self.waypoints.is_empty() }
+}
+
+/// Walks an entity along the FlowField towards its nearest goal, one of a
+/// crowd steered by the FlowAgentSys.
+///
+/// Agents steer away from others nearer than their `separation_radius`, so
+/// that they don't stack up on one point, and match the velocity of those
+/// around them so that crowds move together.
+#[derive(Debug, Component, Clone)]
+#[storage(DenseVecStorage)]
+pub struct FlowAgent {
+    /// How fast to walk, in units per second
+    pub speed:              f32,
+    /// How near others may come before being steered away from
+    pub separation_radius:  f32,
+    /// How strongly to steer away from others, against following the field
+    pub separation:         f32,
+    /// How strongly to match the velocity of others, against following the field
+    pub alignment:          f32,
+}
+impl FlowAgent {
+    pub fn new(speed: f32, separation_radius: f32) -> Self {
+        Self { speed, separation_radius, separation: 1.5, alignment: 0.3 }
+    }
+    pub fn with_separation(self, separation: f32) -> Self { Self { separation, ..self } }
+    pub fn with_alignment(self, alignment: f32) -> Self { Self { alignment, ..self } }
+}
@@ -11,6 +11,89 @@ impl Velocity {
     pub fn new(x: f32, y: f32) -> Self { Self { x, y } }
 }
 
+/// A body moved by forces and impulses, integrated into its Velocity by the RigidBodySys.
+///
+/// Forces and impulses accumulate until the next update, then are cleared.
+///
+/// # Example
+/// ```
+/// # use stoneng::ecs::component::RigidBody;
+/// let mut crate_body = RigidBody::new(4.0).with_friction(0.8);
+/// // A kick, changing the crate's velocity by 50 units per second
+/// crate_body.add_impulse(200.0, 0.0);
+/// assert_eq!(crate_body.inverse_mass(), 0.25);
+/// ```
+#[derive(Debug, Component, Clone)]
+#[storage(DenseVecStorage)]
+pub struct RigidBody {
+    /// Bodies with zero mass are immovable, ignoring forces and impulses
+    pub mass:           f32,
+    /// How quickly the body slows by itself, as a fraction of velocity per second
+    pub linear_damping: f32,
+    /// The coefficient of friction against the floor, and the surfaces the body slides on
+    pub friction:       f32,
+    /// Multiplies the world's gravity for this body
+    pub gravity_scale:  f32,
+
+    /// The total force to apply over the next update
+    force:              (f32, f32),
+    /// The total impulse to apply at the next update
+    impulse:            (f32, f32),
+}
+impl RigidBody {
+    pub fn new(mass: f32) -> Self {
+        Self { 
+            mass, 
+            linear_damping: 0.0, 
+            friction: 0.0, 
+            gravity_scale: 1.0, 
+            force: (0.0, 0.0), 
+            impulse: (0.0, 0.0),
+        }
+    }
+    pub fn with_damping(self, linear_damping: f32) -> Self { Self { linear_damping, ..self } }
+    pub fn with_friction(self, friction: f32) -> Self { Self { friction, ..self } }
+    pub fn with_gravity_scale(self, gravity_scale: f32) -> Self { Self { gravity_scale, ..self } }
+
+    pub fn inverse_mass(&self) -> f32 {
+        if self.mass > 0.0 { 1.0 / self.mass } else { 0.0 }
+    }
+
+    /// Adds a force (mass * units/s²) to be applied throughout the next update.
+    pub fn add_force(&mut self, x: f32, y: f32) {
+        self.force = (self.force.0 + x, self.force.1 + y);
+    }
+
+    /// Adds an instant change in momentum (mass * units/s), such as a hit or knockback.
+    ///
+    /// Unlike movement, impulses aren't limited to any maximum speed.
+    pub fn add_impulse(&mut self, x: f32, y: f32) {
+        self.impulse = (self.impulse.0 + x, self.impulse.1 + y);
+    }
+
+    /// Adds an impulse pushing the body, at `position`, away from an explosion.
+    ///
+    /// The impulse falls off linearly from `strength` at the center to nothing at `radius`.
+    pub fn add_explosion(&mut self, position: (f32, f32), center: (f32, f32), strength: f32, radius: f32) {
+        let offset = (position.0 - center.0, position.1 - center.1);
+        let dist = (offset.0 * offset.0 + offset.1 * offset.1).sqrt();
+        if dist >= radius { return; }
+
+        // Bodies at the very center are thrown upwards
+        let dir = if dist > 0.0 { (offset.0 / dist, offset.1 / dist) } else { (0.0, 1.0) };
+        let falloff = strength * (1.0 - dist / radius);
+        self.add_impulse(dir.0 * falloff, dir.1 * falloff);
+    }
+
+    /// Removes the accumulated (force, impulse), to be integrated.
+    pub(crate) fn take_forces(&mut self) -> ((f32, f32), (f32, f32)) {
+        let forces = (self.force, self.impulse);
+        self.force = (0.0, 0.0);
+        self.impulse = (0.0, 0.0);
+        forces
+    }
+}
+
 #[allow(dead_code)]
 #[derive(Debug, Component, Copy, Clone)]
 #[storage(DenseVecStorage)]
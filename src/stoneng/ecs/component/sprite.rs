@@ -4,26 +4,11 @@ use specs::{Component, VecStorage, DenseVecStorage};
 
 use crate::{
     ecs::component::transform::{Scale, Position, Rotation},
+    ecs::component::color::Color,
     model::spritesheet::{SpriteSheet, SpriteSchema, AnimationSchema},
     renderer::sprite::RenderSprite,
 };
 
-#[repr(C)]
-#[derive(Debug, Component, Clone, Copy)]
-#[storage(VecStorage)]
-pub struct Color {
-    pub r: f32,
-    pub g: f32,
-    pub b: f32,
-    pub a: f32,
-}
-impl Into<(f32, f32, f32, f32)> for Color {
-    fn into(self) -> (f32, f32, f32, f32) {
-        (self.r, self.g, self.b, self.a)
-    }
-}
-impl Default for Color { fn default() -> Self { Self { r: 1.0, g: 1.0, b: 1.0, a: 1.0 } } }
-
 /// A Sprite component is a renderable sub-texture from a SpriteSys' atlas
 ///
 /// These are easily convertable into a RenderSprite which is used by OpenGL
@@ -59,7 +44,7 @@ impl From<(&Sprite, &Position, &Scale, &Color)> for RenderSprite {
             sprite_id:      (spr.schema.root as i32 + spr.id_offset) as u32,
             sprite_dims:    dim_x | (dim_y << 4),
             sprite_flags:   0,
-            reserved:       0,
+            ..Default::default()
         }
     }
 }
@@ -0,0 +1,18 @@
+use specs::{Component, DenseVecStorage};
+
+use crate::model::tiled::Spawn;
+
+/// Marks an entity as belonging to a chunk of a streamed world, which the
+/// ChunkStreamSys spawned it for.
+///
+/// The entity belongs to whichever loaded chunk it's in, and is deleted
+/// when that chunk is unloaded, its spawn being kept with the chunk at the
+/// entity's last position. Entities deleted by the game aren't spawned again.
+#[derive(Debug, Component, Clone)]
+#[storage(DenseVecStorage)]
+pub struct ChunkEntity {
+    /// The chunk the entity belongs to
+    pub chunk:  (i32, i32),
+    /// What the entity was spawned as
+    pub spawn:  Spawn,
+}
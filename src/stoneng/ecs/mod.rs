@@ -1,3 +1,4 @@
 pub mod system;
 pub mod component;
 pub mod resource;
+pub mod query;
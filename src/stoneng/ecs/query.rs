@@ -0,0 +1,192 @@
+use specs::prelude::*;
+use specs::{SystemData, World, shred::ResourceId};
+use glm::{Vec2, vec2};
+
+use crate::model::{Rect, shape::{ColliderShape, Convex}, tilemap::Tilemap};
+use crate::ecs::component::{Position, Rotation, Collider};
+
+/// What a query struck.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HitTarget {
+    Entity(Entity),
+    /// A solid tile, by its tile position
+    Tile((i32, i32)),
+}
+
+/// Where a query struck something.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryHit {
+    pub target:     HitTarget,
+    /// The world position of the hit, on the target's surface
+    pub point:      (f32, f32),
+    /// The unit surface normal at the point, facing back towards the query
+    pub normal:     (f32, f32),
+    /// How far along the query's direction the hit was
+    pub distance:   f32,
+}
+
+/// Read-only spatial queries against the colliders and wall tiles of the world,
+/// for hitscan weapons, line of sight checks and the like.
+///
+/// (Position, Rotation, Collider, Tilemap)
+///
+/// Each query takes a mask, and only finds colliders whose layer is within it.
+/// Solid tiles are shapes on the `WALL_LAYER`. Only the tiles near the
+/// query, and colliders which may reach it, are tested.
+/// Queries see colliders where they were after the last CollisionSys update.
+///
+/// # Example
+/// ```
+/// # use specs::prelude::*;
+/// # use stoneng::ecs::{query::*, component::{Position, Collider}};
+/// let mut world = World::new();
+/// PhysicsQuery::setup(&mut world);
+/// let target = world.create_entity()
+///     .with(Position { x: 100.0, y: 0.0, z: 0.0 })
+///     .with(Collider::circle(10.0))
+///     .build();
+///
+/// let query = world.system_data::<PhysicsQuery>();
+/// let hit = query.raycast((0.0, 0.0), (1.0, 0.0), 500.0, Collider::ALL_LAYERS).unwrap();
+/// assert_eq!(hit.target, HitTarget::Entity(target));
+/// assert_eq!(hit.distance, 90.0);
+/// assert_eq!((hit.point, hit.normal), ((90.0, 0.0), (-1.0, 0.0)));
+///
+/// // Masked out
+/// assert!(query.raycast((0.0, 0.0), (1.0, 0.0), 500.0, 1 << 4).is_none());
+/// let found = query.overlap_circle((80.0, 0.0), 15.0, Collider::ALL_LAYERS);
+/// assert_eq!(found, vec![HitTarget::Entity(target)]);
+/// ```
+#[derive(SystemData)]
+pub struct PhysicsQuery<'a> {
+    entities:   Entities<'a>,
+    positions:  ReadStorage<'a, Position>,
+    rotations:  ReadStorage<'a, Rotation>,
+    colliders:  ReadStorage<'a, Collider>,
+    tilemap:    Read<'a, Tilemap>,
+}
+
+impl<'a> PhysicsQuery<'a> {
+    /// The collision layer solid tiles occupy, a bit of their own which
+    /// colliders shouldn't be placed on
+    pub const WALL_LAYER: u32 = 1 << 31;
+
+    /// Finds the nearest target along a ray, within `max_dist` of its origin.
+    pub fn raycast(&self, origin: (f32, f32), dir: (f32, f32), max_dist: f32, mask: u32)
+            -> Option<QueryHit> {
+        self.raycast_all(origin, dir, max_dist, mask).into_iter().next()
+    }
+
+    /// Finds every target along a ray, within `max_dist` of its origin, nearest first.
+    pub fn raycast_all(&self, origin: (f32, f32), dir: (f32, f32), max_dist: f32, mask: u32)
+            -> Vec<QueryHit> {
+        let (start, dir) = match unit(dir) {
+            Some(dir) => (vec2(origin.0, origin.1), dir),
+            None => return vec![],
+        };
+        let end = start + dir * max_dist;
+        let area = Rect::new((start.x.min(end.x), start.y.max(end.y)), (start.x.max(end.x), start.y.min(end.y)));
+
+        let mut hits: Vec<QueryHit> = self.shapes(mask, &area)
+            .filter_map(|(target, shape)| {
+                let (dist, normal) = shape.raycast(start, dir, max_dist)?;
+                Some(Self::hit(target, start + dir * dist, normal, dist))
+            })
+            .collect();
+        hits.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        hits
+    }
+
+    /// Finds every target overlapping a rect.
+    pub fn overlap_rect(&self, rect: &Rect, mask: u32) -> Vec<HitTarget> {
+        let half_extents = (rect.width() / 2.0, rect.height() / 2.0);
+        let area = ColliderShape::Aabb { half_extents }.to_world((0.0, 0.0), rect.center(), 0.0);
+        self.overlapping(&area, mask)
+    }
+
+    /// Finds every target overlapping a circle.
+    pub fn overlap_circle(&self, center: (f32, f32), radius: f32, mask: u32) -> Vec<HitTarget> {
+        let area = ColliderShape::Circle { radius }.to_world((0.0, 0.0), center, 0.0);
+        self.overlapping(&area, mask)
+    }
+
+    /// Sweeps a shape from `origin`, rotated by `rotation` degrees, along `dir`
+    /// and finds the first target it would touch within `max_dist`.
+    ///
+    /// The hit's point is where the shape touches the target, with the shape
+    /// moved by the hit's distance.
+    pub fn shape_cast(&self, shape: &ColliderShape, origin: (f32, f32), rotation: f32,
+                      dir: (f32, f32), max_dist: f32, mask: u32) -> Option<QueryHit> {
+        let dir = unit(dir)?;
+        let cast = shape.to_world((0.0, 0.0), origin, rotation);
+        let mut end = cast.clone();
+        end.translate(dir * max_dist);
+        let area = cast.bounds().union(&end.bounds());
+
+        let (target, dist, normal) = self.shapes(mask, &area)
+            .filter_map(|(target, shape)| {
+                let (dist, normal) = cast.sweep(dir, max_dist, &shape)?;
+                Some((target, dist, normal))
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        // The point of the moved shape reaching furthest into the surface
+        let mut moved = cast;
+        moved.translate(dir * dist);
+        let deepest = moved.points.iter()
+            .min_by(|a, b| a.dot(&normal).total_cmp(&b.dot(&normal)))
+            .copied()?;
+        Some(Self::hit(target, deepest - normal * moved.radius, normal, dist))
+    }
+
+    /// Every target whose shape overlaps `area`
+    fn overlapping(&self, area: &Convex, mask: u32) -> Vec<HitTarget> {
+        let bounds = area.bounds();
+        self.shapes(mask, &bounds)
+            .filter(|(_, shape)| bounds.intersects(&shape.bounds()) && area.contact(shape).is_some())
+            .map(|(target, _)| target)
+            .collect()
+    }
+
+    /// The world shapes of the colliders and solid tiles which may be within `area`, within the mask
+    fn shapes<'s>(&'s self, mask: u32, area: &Rect) -> impl Iterator<Item = (HitTarget, Convex)> + 's {
+        let cull = *area;
+        let colliders = (&self.entities, &self.positions, self.rotations.maybe(), &self.colliders)
+            .join()
+            // Only colliders which may reach the area are placed in the world
+            .filter(move |(_, pos, _, coll)| {
+                coll.layer & mask != 0 && cull.intersects(&coll.rough_bounds((pos.x, pos.y)))
+            })
+            .map(|(entity, pos, rot, coll)| {
+                let shape = coll.to_world((pos.x, pos.y), rot.map_or(0.0, |r| r.deg));
+                (HitTarget::Entity(entity), shape)
+            });
+
+        let ((min_x, min_y), (max_x, max_y)) = if Self::WALL_LAYER & mask != 0 {
+            self.tilemap.tiles_in(area)
+        } else {
+            // An empty range
+            ((0, 0), (-1, -1))
+        };
+        let walls = (min_x..=max_x)
+            .flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter(|pos| self.tilemap.is_solid(*pos))
+            .map(move |pos| {
+                let points = self.tilemap.tile_shape(pos).into_iter().map(|(x, y)| vec2(x, y)).collect();
+                let shape = Convex { points, radius: 0.0 };
+                (HitTarget::Tile(pos), shape)
+            });
+
+        colliders.chain(walls)
+    }
+
+    fn hit(target: HitTarget, point: Vec2, normal: Vec2, distance: f32) -> QueryHit {
+        QueryHit { target, point: (point.x, point.y), normal: (normal.x, normal.y), distance }
+    }
+}
+
+/// Normalizes a direction, which can't be zero length
+fn unit(dir: (f32, f32)) -> Option<Vec2> {
+    let dir = vec2(dir.0, dir.1);
+    if dir.norm_squared() <= f32::EPSILON { None } else { Some(dir.normalize()) }
+}
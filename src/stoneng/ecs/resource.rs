@@ -1,5 +1,12 @@
+use std::collections::HashMap;
+
 use shrev::EventChannel;
 use specs::Entity;
+use serde::{Serialize, Deserialize};
+
+use crate::EngineError;
+use crate::ecs::component::Color;
+use crate::model::tilemap::TileEvent;
 
 #[derive(Default, Clone, Debug)]
 pub struct SpritesheetImgRef(pub &'static [u8]);
@@ -13,14 +20,133 @@ pub struct WindowSize(pub f32, pub f32);
 #[derive(Default, Clone, Copy, Debug)]
 pub struct View(pub f32, pub f32, pub f32);
 
+/// How gravity acts upon RigidBodies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PhysicsMode {
+    /// Looking down onto the ground. There is no gravity within the plane, but
+    /// bodies drag along the floor as if pressed into it by `floor_gravity`.
+    TopDown { floor_gravity: f32 },
+    /// Looking side on, bodies fall by `gravity` (units/s²) and only feel the
+    /// friction of the surfaces they collide with.
+    Gravity { gravity: (f32, f32) },
+}
+impl Default for PhysicsMode {
+    fn default() -> Self { Self::TopDown { floor_gravity: 980.0 } }
+}
+
+/// The overlap between a pair of colliders.
 #[derive(Debug, Clone, Copy)]
-pub struct CollisionEvent {
+pub struct Contact {
     pub collider_a: Entity,
     pub collider_b: Entity,
+    /// The unit vector pointing from collider_a into collider_b
+    pub normal:     (f32, f32),
+    /// How far the colliders overlap along the normal
+    pub depth:      f32,
+    /// Whichever collider of the pair is a trigger, if either is
+    pub trigger:    Option<Entity>,
+    /// For contacts found by sweeping a Ccd collider, the fraction (0 to 1)
+    /// of the update's motion at which the pair first touched
+    pub toi:        Option<f32>,
+}
+impl Contact {
+    pub fn new(a: Entity, b: Entity, normal: (f32, f32), depth: f32) -> Self {
+        Self { collider_a: a, collider_b: b, normal, depth, trigger: None, toi: None }
+    }
+
+    /// The same contact, as seen from collider_b
+    pub fn flipped(&self) -> Self {
+        Self {
+            collider_a: self.collider_b, 
+            collider_b: self.collider_a, 
+            normal:     (-self.normal.0, -self.normal.1), 
+            ..*self
+        }
+    }
+
+    pub fn involves(&self, entity: Entity) -> bool {
+        self.collider_a == entity || self.collider_b == entity
+    }
+
+    /// Returns the other collider of the pair, if `entity` is part of it.
+    pub fn other(&self, entity: Entity) -> Option<Entity> {
+        if self.collider_a == entity { Some(self.collider_b) }
+        else if self.collider_b == entity { Some(self.collider_a) }
+        else { None }
+    }
+}
+
+/// Collision events are sent once per colliding pair, every update.
+#[derive(Debug, Clone, Copy)]
+pub enum CollisionEvent {
+    /// The pair began overlapping this update.
+    Started(Contact),
+    /// The pair was already overlapping last update.
+    Ongoing(Contact),
+    /// The pair stopped overlapping, or one was removed. Holds their last contact.
+    Ended(Contact),
 }
 impl CollisionEvent {
-    pub fn new(a: Entity, b: Entity) -> Self {
-        Self { collider_a: a, collider_b: b }
+    pub fn contact(&self) -> &Contact {
+        match self {
+            Self::Started(contact) | Self::Ongoing(contact) | Self::Ended(contact) => contact,
+        }
     }
+
+    /// Whether the pair is overlapping as of this update (i.e. not Ended).
+    pub fn is_touching(&self) -> bool { !matches!(self, Self::Ended(_)) }
 }
 pub type CollisionEvents = EventChannel<CollisionEvent>;
+
+/// A Ccd collider striking a solid tile of the Tilemap, sent once per hit.
+#[derive(Debug, Clone, Copy)]
+pub struct TileHit {
+    pub collider:   Entity,
+    /// The position of the tile struck
+    pub tile:       (i32, i32),
+    /// The unit surface normal of the tile where it was struck, facing back
+    /// towards the collider
+    pub normal:     (f32, f32),
+    /// The fraction (0 to 1) of the update's motion at which the collider touched the tile
+    pub toi:        f32,
+}
+pub type TileHits = EventChannel<TileHit>;
+
+/// The changes made to the Tilemap, sent on by the TileEventSys each update.
+pub type TileEvents = EventChannel<TileEvent>;
+
+/// A set of named colours, allowing themes to be swapped without code changes.
+///
+/// # Example
+/// ```
+/// # use stoneng::ecs::{resource::Palette, component::Color};
+/// let palette = Palette::new(r##"
+/// Palette (
+///     colors: {
+///         "blood":    "#8a0303",
+///         "ui-text":  (r: 1.0, g: 1.0, b: 0.9),
+///     }
+/// )
+/// "##).unwrap();
+///
+/// assert_eq!(palette.get("blood"), Some(Color::from_hex("#8a0303").unwrap()));
+/// assert_eq!(palette.get("ui-text").unwrap().a, 1.0);
+/// assert_eq!(palette.get_or_default("missing"), Color::default());
+/// ```
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Palette {
+    pub colors: HashMap<String, Color>,
+}
+impl Palette {
+    /// Parses a palette from its Rusty Object Notation layout.
+    pub fn new(layout: &str) -> Result<Self, EngineError> {
+        Ok(ron::from_str::<Palette>(layout)?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Color> { self.colors.get(name).copied() }
+
+    /// Looks up a colour, falling back to the default (white) when it is missing.
+    pub fn get_or_default(&self, name: &str) -> Color { self.get(name).unwrap_or_default() }
+
+    pub fn insert(&mut self, name: &str, color: Color) { self.colors.insert(name.into(), color); }
+}
@@ -1,44 +1,442 @@
+use std::collections::{HashMap, HashSet};
+
 use specs::{ReadStorage, WriteStorage, System, Join, Write, Read, SystemData};
 use specs::prelude::*;
-use crate::model::Rect;
+use glm::{Vec2, vec2};
+use crate::model::{spatial::SpatialHash, shape::Convex, tilemap::{Tilemap, TileEvent}};
 use crate::ecs::{
-    component::{Position, Velocity, Collider},
-    resource::{DeltaTime, CollisionEvents, CollisionEvent},
+    component::{Position, Rotation, Velocity, RigidBody, Collider, BodyType, Ccd},
+    resource::{DeltaTime, CollisionEvents, CollisionEvent, Contact, TileEvents, TileHit, TileHits},
+    query::PhysicsQuery,
 };
 
+/// A collider's state during a single CollisionSys update
+struct Body {
+    entity:     Entity,
+    shape:      Convex,
+    collider:   Collider,
+    /// The total distance the body has been pushed this update
+    push:       (f32, f32),
+    /// How far a Ccd body moved this update, for it to be swept along
+    motion:     Option<Vec2>,
+    /// The time of impact, and surface normal, of the first solid collider a
+    /// swept body hit
+    impact:     Option<(f32, Vec2)>,
+}
 
+/// What a swept body touched
+enum Struck {
+    Body(usize),
+    Tile((i32, i32)),
+}
+
+/// A body which touched another body, or a solid tile, part way through its sweep
+struct Impact {
+    /// The fraction of the update's motion when they first touched
+    toi:        f32,
+    a:          usize,
+    b:          Struck,
+    /// The contact normal, pointing from a into b
+    normal:     Vec2,
+}
+
+/// A system to detect and resolve overlapping colliders.
+///
+/// (Position, Rotation, Velocity, RigidBody, Collider, Ccd, Tilemap,
+///  resource::CollisionEvents, resource::TileHits, resource::TileEvents)
+///
+/// Colliders are sorted into a uniform grid by their bounds so that only nearby
+/// pairs have their shapes tested.
+/// Overlapping pairs, whose layers and masks match, are tracked between updates
+/// and reported once each as a Started, Ongoing or Ended CollisionEvent.
+///
+/// Unless either is a trigger, dynamic bodies are then pushed out along the
+/// contact normal, losing any velocity into the surface so that they slide
+/// along it. Rigid bodies also lose sliding speed to their friction, in
+/// proportion to how hard they hit the surface. This should run after the VelocitySys.
+///
+/// Ccd colliders are instead swept back along this update's velocity, and
+/// stopped at the first solid collider, or solid tile, they would have touched.
+/// Tiles struck this way are reported as TileHits.
+///
+/// Dynamic bodies whose mask includes the `PhysicsQuery::WALL_LAYER` are then
+/// pushed out of any solid tiles, which are kept track of through TileEvents.
+/// Otherwise touching tiles isn't reported.
+///
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use specs::prelude::*;
+/// # use stoneng::model::{spritesheet::SpriteSheet, tilemap::Tilemap};
+/// # use stoneng::ecs::{component::*, resource::{DeltaTime, TileHits}, system::{collision::CollisionSys, tilemap::TileEventSys}};
+/// # let img_data = include_bytes!("./collision.rs");
+/// let sheet = Arc::new(SpriteSheet::new(r#"SpriteSheet(sheet_width: 100, tile_width: 10, sprites: {
+///     "brick": (root: 0),
+/// })"#, img_data).unwrap());
+/// // A column of wall tiles, 50 units wide, centered on x = 500
+/// let mut map = Tilemap::new(sheet.clone());
+/// map.fill_rect((10, -2), (10, 2), "walls", Some(sheet.sprites["brick"].clone()));
+///
+/// let mut world = World::new();
+/// let (mut tile_events, mut collision) = (TileEventSys, CollisionSys::default());
+/// System::setup(&mut tile_events, &mut world);
+/// System::setup(&mut collision, &mut world);
+/// world.insert(map);
+/// world.insert(DeltaTime(1.0));
+/// let mut hits = world.fetch_mut::<TileHits>().register_reader();
+///
+/// // A bullet which passed straight through the wall this update
+/// let bullet = world.create_entity()
+///     .with(Position { x: 1500.0, y: 0.0, z: 0.0 })
+///     .with(Velocity::new(1500.0, 0.0))
+///     .with(Collider::circle(2.0).with_body(BodyType::Dynamic))
+///     .with(Ccd)
+///     .build();
+/// tile_events.run_now(&world);
+/// collision.run_now(&world);
+///
+/// let pos = *world.read_storage::<Position>().get(bullet).unwrap();
+/// assert!((pos.x - 473.0).abs() < 0.1);
+/// let hit = *world.fetch::<TileHits>().read(&mut hits).next().unwrap();
+/// assert_eq!((hit.collider, hit.tile, hit.normal), (bullet, (10, 0), (-1.0, 0.0)));
+/// ```
 // TODO implement ncollide
 #[derive(Default)]
-pub struct CollisionSys;
+pub struct CollisionSys {
+    tile_reader: Option<ReaderId<TileEvent>>,
+    /// The positions of every solid tile of the Tilemap
+    solid_tiles: HashSet<(i32, i32)>,
+    broadphase: SpatialHash,
+    /// The collider bodies, indexed by their broadphase id
+    bodies:     Vec<Body>,
+    /// The contacts found last update, keyed by their (lower, higher) entity pair
+    contacts:   HashMap<(Entity, Entity), Contact>,
+    /// The contacts being found this update
+    next_contacts: HashMap<(Entity, Entity), Contact>,
+}
+impl CollisionSys {
+    /// Creates a CollisionSys with a broadphase cell of the given size.
+    ///
+    /// Cells should be a bit larger than the typical collider.
+    pub fn with_cell_size(cell_size: f32) -> Self {
+        Self { broadphase: SpatialHash::new(cell_size), ..Default::default() }
+    }
+
+    /// Splits the separating distance between two bodies, based on their types.
+    ///
+    /// Returns the share of the push applied to each body.
+    fn push_shares(a: &Collider, b: &Collider) -> (f32, f32) {
+        if a.trigger || b.trigger { return (0.0, 0.0); }
+
+        match (a.body, b.body) {
+            (BodyType::Dynamic, BodyType::Dynamic) => (0.5, 0.5),
+            (BodyType::Dynamic, _) => (1.0, 0.0),
+            (_, BodyType::Dynamic) => (0.0, 1.0),
+            _ => (0.0, 0.0),
+        }
+    }
+
+    /// Builds the contact between two bodies, with the lower entity first so
+    /// that pairs match between updates.
+    fn contact(a: &Body, b: &Body, normal: Vec2, depth: f32) -> Contact {
+        let mut contact = Contact::new(a.entity, b.entity, (normal.x, normal.y), depth);
+        contact.trigger = if a.collider.trigger { Some(a.entity) }
+            else if b.collider.trigger { Some(b.entity) }
+            else { None };
+        if b.entity < a.entity { contact.flipped() } else { contact }
+    }
+
+    /// Keeps the solid tiles up to date with the changes made to the map
+    fn track_tiles(&mut self, tilemap: &Tilemap, events: &TileEvents) {
+        let reader = match self.tile_reader.as_mut() { Some(reader) => reader, None => return };
+        let mut rebuild = false;
+        for event in events.read(reader) {
+            match event {
+                TileEvent::Changed { pos, .. } | TileEvent::PropertiesChanged(pos) => if tilemap.is_solid(*pos) {
+                    self.solid_tiles.insert(*pos);
+                } else {
+                    self.solid_tiles.remove(pos);
+                },
+                TileEvent::LayerChanged(_) | TileEvent::Reset => rebuild = true,
+            }
+        }
+        if rebuild {
+            self.solid_tiles = tilemap.positions().into_iter()
+                .filter(|pos| tilemap.is_solid(*pos))
+                .collect();
+        }
+    }
+
+    /// Whether a body is kept out of solid tiles
+    fn hits_tiles(collider: &Collider) -> bool {
+        collider.body == BodyType::Dynamic && !collider.trigger && collider.mask & PhysicsQuery::WALL_LAYER != 0
+    }
+
+    /// Sweeps a Ccd body from where it began the update against the solid
+    /// tiles along its way, finding the first it touched
+    fn sweep_tiles(&self, body: &Body, tilemap: &Tilemap) -> Option<(f32, Vec2, (i32, i32))> {
+        let motion = body.motion?;
+        let length = motion.norm();
+        let mut start = body.shape.clone();
+        start.translate(-motion);
+
+        let ((min_x, min_y), (max_x, max_y)) = tilemap.tiles_in(&start.bounds().union(&body.shape.bounds()));
+        let within = |(x, y): &(i32, i32)| (min_x..=max_x).contains(x) && (min_y..=max_y).contains(y);
+        // Long sweeps can cover more tiles than there are solid ones
+        let area = (max_x as i64 - min_x as i64 + 1) * (max_y as i64 - min_y as i64 + 1);
+        let tiles: Vec<(i32, i32)> = if area > self.solid_tiles.len() as i64 {
+            self.solid_tiles.iter().copied().filter(within).collect()
+        } else {
+            (min_x..=max_x).flat_map(|x| (min_y..=max_y).map(move |y| (x, y)))
+                .filter(|pos| self.solid_tiles.contains(pos))
+                .collect()
+        };
+
+        tiles.into_iter()
+            .filter_map(|pos| {
+                let points = tilemap.tile_shape(pos).into_iter().map(|(x, y)| vec2(x, y)).collect();
+                let (dist, normal) = start.sweep(motion / length, length, &Convex { points, radius: 0.0 })?;
+                Some((dist / length, -normal, pos))
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+    }
+
+    /// Pushes a dynamic body out of the solid tiles it overlaps, most overlapped first
+    fn push_out_of_tiles(&self, body: &mut Body, tilemap: &Tilemap) {
+        let bounds = body.shape.bounds();
+        let ((min_x, min_y), (max_x, max_y)) = tilemap.tiles_in(&bounds);
+        let mut tiles: Vec<(f32, Convex)> = (min_x..=max_x)
+            .flat_map(|x| (min_y..=max_y).map(move |y| (x, y)))
+            .filter(|pos| self.solid_tiles.contains(pos))
+            .map(|pos| {
+                let tile = tilemap.tile_bounds(pos);
+                let overlap = (bounds.right().min(tile.right()) - bounds.left().max(tile.left())) *
+                    (bounds.top().min(tile.top()) - bounds.bottom().max(tile.bottom()));
+                let points = tilemap.tile_shape(pos).into_iter().map(|(x, y)| vec2(x, y)).collect();
+                (overlap, Convex { points, radius: 0.0 })
+            })
+            .collect();
+        // Resolving the deepest tiles first stops bodies catching on the seams between tiles
+        tiles.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        for (_, tile) in tiles {
+            if let Some((normal, depth)) = body.shape.contact(&tile) {
+                let (dx, dy) = (-normal.0 * depth, -normal.1 * depth);
+                body.shape.translate(vec2(dx, dy));
+                body.push = (body.push.0 + dx, body.push.1 + dy);
+            }
+        }
+    }
+
+    /// Sweeps a pair of bodies, at least one with Ccd, from where they began the update.
+    fn sweep(a: &Body, b: &Body) -> Option<(f32, Vec2)> {
+        let zero = vec2(0.0, 0.0);
+        let (motion_a, motion_b) = (a.motion.unwrap_or(zero), b.motion.unwrap_or(zero));
+        let relative = motion_a - motion_b;
+        let length = relative.norm();
+
+        let mut start_a = a.shape.clone();
+        start_a.translate(-motion_a);
+        let mut start_b = b.shape.clone();
+        start_b.translate(-motion_b);
+
+        // Moving together, only their final positions matter
+        if length <= 0.0 {
+            return start_a.contact(&start_b).map(|(n, _)| (1.0, vec2(n.0, n.1)));
+        }
+        let (dist, normal) = start_a.sweep(relative / length, length, &start_b)?;
+        Some((dist / length, -normal))
+    }
+}
 impl<'a> System<'a> for CollisionSys {
     type SystemData = (Entities<'a>,
-                       ReadStorage<'a, Position>,
-                       WriteStorage<'a, Collider>,
-                       Write<'a, CollisionEvents>);
+                       WriteStorage<'a, Position>,
+                       ReadStorage<'a, Rotation>,
+                       WriteStorage<'a, Velocity>,
+                       ReadStorage<'a, RigidBody>,
+                       ReadStorage<'a, Collider>,
+                       ReadStorage<'a, Ccd>,
+                       Read<'a, Tilemap>,
+                       Read<'a, DeltaTime>,
+                       Write<'a, CollisionEvents>,
+                       Write<'a, TileHits>,
+                       Read<'a, TileEvents>);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (entities, positions, colliders, mut collision_events) = data;
-
-        let collision_group = (&entities, &positions, &colliders).join();
-        for (ent, pos, coll) in collision_group.clone() {
-            for (ent_b, pos_b, coll_b) in collision_group.clone() {
-                if ent == ent_b { continue; }
-                
-                // Build collision boxes using the bounds and position
-                let (x,y,w,h) = (pos.x, pos.y, coll.width/2.0, coll.height/2.0);
-                let coll_rect = Rect::new((x-w, y+h), (x+w, y-h));
-
-                let (x,y,w,h) = (pos_b.x, pos_b.y, coll_b.width/2.0, coll_b.height/2.0);
-                let coll_rect_b = Rect::new((x-w, y+h), (x+w, y-h));
-                
-                // Test for collision
-                if coll_rect.intersects(&coll_rect_b) {
-                    // Emit a new collision event
-                    let coll_event = CollisionEvent::new(ent, ent_b);
-                    collision_events.single_write(coll_event);
+        let (entities, mut positions, rotations, mut vels, rigid_bodies, colliders, ccds, tilemap, dt,
+             mut collision_events, mut tile_hits, tile_events) = data;
+        let dt = dt.0 as f32;
+        self.track_tiles(&tilemap, &tile_events);
+
+        // Place the collider shapes in the world, once per collider
+        self.broadphase.clear();
+        self.bodies.clear();
+        for (entity, pos, rot, vel, ccd, coll) in
+                (&entities, &positions, rotations.maybe(), (&vels).maybe(), ccds.maybe(), &colliders).join() {
+            let rotation = rot.map_or(0.0, |r| r.deg);
+            let shape = coll.to_world((pos.x, pos.y), rotation);
+
+            // Swept bodies cover everywhere they passed through
+            let motion = ccd.and(vel)
+                .map(|vel| vec2(vel.x, vel.y) * dt)
+                .filter(|motion| motion.norm_squared() > 0.0);
+            let mut bounds = shape.bounds();
+            if let Some(motion) = motion {
+                let mut start = shape.clone();
+                start.translate(-motion);
+                bounds = bounds.union(&start.bounds());
+            }
+
+            self.broadphase.insert(&bounds);
+            self.bodies.push(Body {
+                entity, shape, collider: coll.clone(), push: (0.0, 0.0), motion, impact: None,
+            });
+        }
+
+        let bodies = &mut self.bodies;
+        let next_contacts = &mut self.next_contacts;
+        let mut impacts = Vec::new();
+        self.broadphase.for_each_pair(|a, b| {
+            if !bodies[a].collider.can_collide(&bodies[b].collider) { return; }
+
+            // Swept pairs are resolved afterwards, in the order they touched
+            if bodies[a].motion.is_some() || bodies[b].motion.is_some() {
+                if let Some((toi, normal)) = Self::sweep(&bodies[a], &bodies[b]) {
+                    impacts.push(Impact { toi, a, b: Struck::Body(b), normal });
                 }
+                return;
+            }
+
+            // Test for collision
+            let (normal, depth) = match bodies[a].shape.contact(&bodies[b].shape) {
+                Some(contact) => contact,
+                None => return,
+            };
+            let contact = Self::contact(&bodies[a], &bodies[b], vec2(normal.0, normal.1), depth);
+            next_contacts.insert((contact.collider_a, contact.collider_b), contact);
+
+            // Push the bodies apart, a against the normal and b along it
+            let (share_a, share_b) = Self::push_shares(&bodies[a].collider, &bodies[b].collider);
+            for (id, dist) in [(a, -share_a * depth), (b, share_b * depth)] {
+                if dist == 0.0 { continue; }
+                let body = &mut bodies[id];
+                let (dx, dy) = (normal.0 * dist, normal.1 * dist);
+                body.shape.translate(vec2(dx, dy));
+                body.push = (body.push.0 + dx, body.push.1 + dy);
+            }
+        });
+
+        // And against the solid tiles along the way
+        for (a, body) in self.bodies.iter().enumerate() {
+            if !Self::hits_tiles(&body.collider) { continue; }
+            if let Some((toi, normal, pos)) = self.sweep_tiles(body, &tilemap) {
+                impacts.push(Impact { toi, a, b: Struck::Tile(pos), normal });
             }
         }
 
+        // Stop each swept body at the first solid collider or tile it reached
+        let bodies = &mut self.bodies;
+        let next_contacts = &mut self.next_contacts;
+        impacts.sort_by(|x, y| x.toi.total_cmp(&y.toi));
+        for Impact { toi, a, b, normal } in impacts {
+            // Neither body got further than where it was stopped
+            let stopped_before = |body: &Body| body.impact.is_some_and(|(stop, _)| stop < toi);
+            if stopped_before(&bodies[a]) { continue; }
+
+            let (b, (share_a, share_b)) = match b {
+                Struck::Body(b) => {
+                    if stopped_before(&bodies[b]) { continue; }
+                    let mut contact = Self::contact(&bodies[a], &bodies[b], normal, 0.0);
+                    contact.toi = Some(toi);
+                    next_contacts.insert((contact.collider_a, contact.collider_b), contact);
+                    (Some(b), Self::push_shares(&bodies[a].collider, &bodies[b].collider))
+                },
+                Struck::Tile(tile) => {
+                    // Already stopped, by something else it touched at the same time
+                    if bodies[a].impact.is_some() { continue; }
+                    tile_hits.single_write(TileHit {
+                        collider: bodies[a].entity, tile, normal: (-normal.x, -normal.y), toi,
+                    });
+                    (None, (1.0, 0.0))
+                },
+            };
+
+            // Rewind the bodies to where they touched, a facing against the normal and b along it
+            for (id, share, facing) in std::iter::once((a, share_a, -normal)).chain(b.map(|b| (b, share_b, normal))) {
+                let body = &mut bodies[id];
+                let motion = match body.motion {
+                    Some(motion) if share > 0.0 && body.impact.is_none() => motion,
+                    _ => continue,
+                };
+                // Bodies touching from the start only lose their motion into the
+                // surface, so they can still slide along it or lift off it
+                let moved = motion + vec2(body.push.0, body.push.1);
+                let rewind = if toi > 0.0 { -motion * (1.0 - toi) } else { -facing * moved.dot(&facing).min(0.0) };
+                body.shape.translate(rewind);
+                body.push = (body.push.0 + rewind.x, body.push.1 + rewind.y);
+                if toi > 0.0 { body.impact = Some((toi, facing)); }
+            }
+        }
+
+        // Keep bodies out of the walls
+        let mut bodies = std::mem::take(&mut self.bodies);
+        for body in bodies.iter_mut() {
+            if Self::hits_tiles(&body.collider) {
+                self.push_out_of_tiles(body, &tilemap);
+            }
+        }
+        self.bodies = bodies;
+
+        // Emit the events, comparing against the previous update's contacts
+        for (pair, contact) in self.next_contacts.iter() {
+            let event = if self.contacts.contains_key(pair) {
+                CollisionEvent::Ongoing(*contact)
+            } else {
+                CollisionEvent::Started(*contact)
+            };
+            collision_events.single_write(event);
+        }
+        for (pair, contact) in self.contacts.iter() {
+            if !self.next_contacts.contains_key(pair) {
+                collision_events.single_write(CollisionEvent::Ended(*contact));
+            }
+        }
+        std::mem::swap(&mut self.contacts, &mut self.next_contacts);
+        self.next_contacts.clear();
+
+        // Apply the resolved positions
+        for body in self.bodies.iter() {
+            if body.push == (0.0, 0.0) && body.impact.is_none() { continue; }
+            if let Some(pos) = positions.get_mut(body.entity) {
+                pos.x += body.push.0;
+                pos.y += body.push.1;
+            }
+            // Remove any velocity into the surface, leaving only the sliding motion
+            if let Some(vel) = vels.get_mut(body.entity) {
+                let normal = match body.impact {
+                    Some((_, normal)) => normal,
+                    None => vec2(body.push.0, body.push.1).normalize(),
+                };
+                let into_surface = vec2(vel.x, vel.y).dot(&normal);
+                if into_surface < 0.0 {
+                    vel.x -= normal.x * into_surface;
+                    vel.y -= normal.y * into_surface;
+
+                    // Coulomb friction, the harder the impact the more sliding is lost
+                    let friction = rigid_bodies.get(body.entity).map_or(0.0, |rb| rb.friction);
+                    let sliding = vec2(vel.x, vel.y);
+                    let speed = sliding.norm();
+                    if friction > 0.0 && speed > 0.0 {
+                        let slowed = (speed + friction * into_surface).max(0.0);
+                        vel.x *= slowed / speed;
+                        vel.y *= slowed / speed;
+                    }
+                }
+            }
+        }
+    }
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.tile_reader = Some(world.fetch_mut::<TileEvents>().register_reader());
     }
 }
@@ -0,0 +1,37 @@
+use std::collections::HashSet;
+
+use specs::{ReadStorage, System, Join, Read, Write};
+use crate::model::{tilemap::Tilemap, fov::{VisibilityGrid, field_of_view}};
+use crate::ecs::component::{Position, Viewer};
+
+/// A system to find the tiles in sight of every Viewer.
+///
+/// (Tilemap, Position, Viewer, VisibilityGrid)
+///
+/// Opaque tiles block sight, see `Tilemap::is_opaque`. Tiles leaving sight are remembered by the
+/// VisibilityGrid, and with no viewers left, nothing is hidden. Sight is
+/// cast across the tiles' (x, y) grid, so it's exact for orthogonal and
+/// isometric layouts but only approximate for staggered and hexagonal ones.
+/// This should run after anything which moves viewers, and before rendering.
+#[derive(Default)]
+pub struct FovSys;
+impl<'a> System<'a> for FovSys {
+    type SystemData = (Read<'a, Tilemap>,
+                       ReadStorage<'a, Position>,
+                       ReadStorage<'a, Viewer>,
+                       Write<'a, VisibilityGrid>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (tilemap, positions, viewers, mut grid) = data;
+
+        let mut visible = HashSet::new();
+        let mut any_viewer = false;
+        for (pos, viewer) in (&positions, &viewers).join() {
+            any_viewer = true;
+            let origin = tilemap.world_to_tile((pos.x, pos.y));
+            field_of_view(origin, viewer.radius, |tile| tilemap.is_opaque(tile), |tile| { visible.insert(tile); });
+        }
+
+        if any_viewer { grid.update(visible); } else { grid.deactivate(); }
+    }
+}
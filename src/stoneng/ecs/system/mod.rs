@@ -4,6 +4,10 @@ pub mod text;
 pub mod movement;
 pub mod collision;
 pub mod particle;
+pub mod tilemap;
+pub mod pathfinding;
+pub mod fov;
+pub mod streaming;
 
 use specs::prelude::*;
 
@@ -1,10 +1,60 @@
 use specs::{ReadStorage, WriteStorage, System, Join, Read, SystemData};
 use specs::prelude::*;
 use crate::ecs::{
-    component::{Position, Velocity},
-    resource::DeltaTime,
+    component::{Position, Velocity, RigidBody},
+    resource::{DeltaTime, PhysicsMode},
 };
 
+/// A system to integrate the forces acting on rigid bodies into their velocity.
+///
+/// (RigidBody, Velocity, resource::PhysicsMode, resource::DeltaTime)
+///
+/// Each update, a body's accumulated impulses, forces and gravity change its
+/// velocity before damping, and floor friction in top-down mode, slow it down.
+/// This should run before the VelocitySys.
+#[derive(Default)]
+pub struct RigidBodySys;
+impl<'a> System<'a> for RigidBodySys {
+    type SystemData = (WriteStorage<'a, RigidBody>,
+                       WriteStorage<'a, Velocity>,
+                       Read<'a, PhysicsMode>,
+                       Read<'a, DeltaTime>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut bodies, mut vels, mode, dt) = data;
+        let dt = dt.0 as f32;
+
+        for (body, vel) in (&mut bodies, &mut vels).join() {
+            let (force, impulse) = body.take_forces();
+            let inv_mass = body.inverse_mass();
+            if inv_mass == 0.0 { continue; }
+
+            // Semi-implicit Euler, the position is then moved by the new velocity
+            let gravity = match *mode {
+                PhysicsMode::Gravity { gravity } => gravity,
+                PhysicsMode::TopDown { .. } => (0.0, 0.0),
+            };
+            vel.x += impulse.0 * inv_mass + (force.0 * inv_mass + gravity.0 * body.gravity_scale) * dt;
+            vel.y += impulse.1 * inv_mass + (force.1 * inv_mass + gravity.1 * body.gravity_scale) * dt;
+
+            // Damping never reverses the velocity, however large the timestep
+            let damping = 1.0 / (1.0 + body.linear_damping * dt);
+            vel.x *= damping;
+            vel.y *= damping;
+
+            // Kinetic friction against the floor slows the body to a stop
+            if let PhysicsMode::TopDown { floor_gravity } = *mode {
+                let speed = (vel.x * vel.x + vel.y * vel.y).sqrt();
+                if speed > 0.0 {
+                    let slowed = (speed - body.friction * floor_gravity * dt).max(0.0);
+                    vel.x *= slowed / speed;
+                    vel.y *= slowed / speed;
+                }
+            }
+        }
+    }
+}
+
 /// A system to simulate entity velocity.
 /// 
 /// (Velocity, Position, resource::DeltaTime)
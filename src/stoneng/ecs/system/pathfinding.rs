@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+
+use specs::{ReadStorage, WriteStorage, System, Join, Read, Write, SystemData};
+use specs::prelude::*;
+use crate::model::{Rect, spatial::SpatialHash, pathfinding::{NavGrid, FlowField}, tilemap::{Tilemap, TileEvent}};
+use crate::ecs::{
+    component::{Position, Rotation, Velocity, Collider, BodyType, PathFollower, FlowAgent},
+    resource::{DeltaTime, TileEvents},
+};
+
+/// A system to keep the NavGrid up to date with the map.
+///
+/// (Tilemap, resource::TileEvents, Position, Rotation, Collider, NavGrid)
+///
+/// Solid tiles are walls, through TileEvents, and the tiles under static,
+/// non-trigger colliders are obstacles. A NavGrid which hasn't been built
+/// yet, like one inserted in place of the last, is built from the whole map,
+/// as it is when the map's layout changes.
+#[derive(Default)]
+pub struct NavGridSys {
+    tile_reader: Option<ReaderId<TileEvent>>,
+}
+impl<'a> System<'a> for NavGridSys {
+    type SystemData = (Read<'a, Tilemap>,
+                       Read<'a, TileEvents>,
+                       ReadStorage<'a, Position>,
+                       ReadStorage<'a, Rotation>,
+                       ReadStorage<'a, Collider>,
+                       Write<'a, NavGrid>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (tilemap, tile_events, positions, rotations, colliders, mut grid) = data;
+
+        let mut rebuild = !grid.is_built() || grid.layout() != tilemap.layout;
+        if let Some(reader) = self.tile_reader.as_mut() {
+            for event in tile_events.read(reader) {
+                match event {
+                    TileEvent::Changed { pos, .. } | TileEvent::PropertiesChanged(pos) =>
+                        if !rebuild { grid.update_tile(&tilemap, *pos) },
+                    TileEvent::LayerChanged(_) | TileEvent::Reset => rebuild = true,
+                }
+            }
+        }
+        if rebuild { grid.rebuild(&tilemap); }
+
+        let mut obstacles = HashSet::new();
+        for (pos, rot, coll) in (&positions, rotations.maybe(), &colliders).join() {
+            if coll.body != BodyType::Static || coll.trigger { continue; }
+
+            let bounds = coll.to_world((pos.x, pos.y), rot.map_or(0.0, |r| r.deg)).bounds();
+            let ((min_x, min_y), (max_x, max_y)) = tilemap.tiles_in(&bounds);
+            for x in min_x..=max_x {
+                for y in min_y..=max_y {
+                    // Only tiles the collider covers, not those it merely touches
+                    if tilemap.tile_bounds((x, y)).intersects(&bounds) { obstacles.insert((x, y)); }
+                }
+            }
+        }
+        grid.set_obstacles(obstacles);
+    }
+    fn setup(&mut self, world: &mut World) {
+        Self::SystemData::setup(world);
+        self.tile_reader = Some(world.fetch_mut::<TileEvents>().register_reader());
+    }
+}
+
+/// A system to walk PathFollowers along their paths.
+///
+/// (NavGrid, Tilemap, resource::DeltaTime, Position, PathFollower, Velocity)
+///
+/// A follower's path is found again when its target moves to another tile,
+/// or the NavGrid changes, then smoothed so that it walks straight wherever
+/// it can. Its velocity is set towards the next waypoint, slowing so as not
+/// to overshoot it, and zeroed once it arrives or is stopped.
+/// This should run after the NavGridSys, and before the VelocitySys.
+#[derive(Default)]
+pub struct PathFollowSys;
+impl<'a> System<'a> for PathFollowSys {
+    type SystemData = (Read<'a, NavGrid>,
+                       Read<'a, Tilemap>,
+                       Read<'a, DeltaTime>,
+                       ReadStorage<'a, Position>,
+                       WriteStorage<'a, PathFollower>,
+                       WriteStorage<'a, Velocity>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (grid, tilemap, dt, positions, mut followers, mut vels) = data;
+        let dt = dt.0 as f32;
+
+        for (pos, follower, vel) in (&positions, &mut followers, &mut vels).join() {
+            let target = match follower.target {
+                Some(target) => target,
+                None => {
+                    // Stop once, leaving the velocity to anything else afterwards
+                    if follower.found_for.take().is_some() {
+                        follower.waypoints.clear();
+                        *vel = Velocity::new(0.0, 0.0);
+                    }
+                    continue;
+                },
+            };
+
+            let goal = tilemap.world_to_tile(target);
+            if follower.found_for != Some((goal, grid.revision())) {
+                let start = tilemap.world_to_tile((pos.x, pos.y));
+                follower.waypoints = match grid.find_path(start, goal) {
+                    Some(path) => {
+                        // The follower is already on the first tile
+                        let mut waypoints: Vec<(f32, f32)> = grid.smooth(&path).into_iter()
+                            .skip(1)
+                            .map(|tile| tilemap.tile_to_world(tile))
+                            .collect();
+                        // and walks to the target itself, rather than the center of its tile
+                        match waypoints.last_mut() {
+                            Some(last) => *last = target,
+                            None => waypoints.push(target),
+                        }
+                        waypoints
+                    },
+                    None => vec![],
+                };
+                follower.found_for = Some((goal, grid.revision()));
+            }
+
+            // Skip past the waypoints already reached
+            let distance = |waypoint: &(f32, f32)| ((waypoint.0 - pos.x).powi(2) + (waypoint.1 - pos.y).powi(2)).sqrt();
+            while follower.waypoints.first().is_some_and(|waypoint| distance(waypoint) <= follower.arrive_distance) {
+                follower.waypoints.remove(0);
+            }
+
+            *vel = match follower.waypoints.first() {
+                Some(waypoint) => {
+                    let dist = distance(waypoint);
+                    // Slowing to land on the waypoint, rather than overshooting it
+                    let speed = if dt > 0.0 { follower.speed.min(dist / dt) } else { follower.speed };
+                    Velocity::new((waypoint.0 - pos.x) / dist * speed, (waypoint.1 - pos.y) / dist * speed)
+                },
+                None => Velocity::new(0.0, 0.0),
+            };
+        }
+    }
+}
+
+/// A system to build the FlowField again whenever its goals or the NavGrid change.
+///
+/// (NavGrid, FlowField)
+///
+/// The goals are set by the game, and the field is built at most once per
+/// update however many agents follow it. This should run after the NavGridSys.
+#[derive(Default)]
+pub struct FlowFieldSys;
+impl<'a> System<'a> for FlowFieldSys {
+    type SystemData = (Read<'a, NavGrid>,
+                       Write<'a, FlowField>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (grid, mut field) = data;
+        if field.is_outdated(&grid) { field.build(&grid); }
+    }
+}
+
+/// The turn between the ways stacked agents are split, spreading them evenly
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+/// A FlowAgent, as found this update
+struct Agent {
+    entity:     Entity,
+    pos:        (f32, f32),
+    vel:        (f32, f32),
+    agent:      FlowAgent,
+    /// The summed push away from the agents too near
+    separation: (f32, f32),
+    /// The summed velocity of the agents near, and how many there are
+    neighbours: ((f32, f32), u32),
+}
+
+/// A system to steer FlowAgents along the FlowField, keeping them apart.
+///
+/// (Tilemap, FlowField, resource::DeltaTime, Position, FlowAgent, Velocity)
+///
+/// Each agent heads for the center of the next tile the field gives for its
+/// own, and on a goal tile, for the center of the goal. Agents near each
+/// other are found through a SpatialHash, so crowds of thousands stay cheap,
+/// and are steered apart and into step before their speed is capped.
+/// Walls are left to the CollisionSys. This should run after the
+/// FlowFieldSys, and before the VelocitySys.
+#[derive(Default)]
+pub struct FlowAgentSys {
+    broadphase: SpatialHash,
+    agents:     Vec<Agent>,
+}
+impl FlowAgentSys {
+    /// Creates a FlowAgentSys with a broadphase cell of the given size.
+    ///
+    /// Cells should be a bit larger than the typical separation radius.
+    pub fn with_cell_size(cell_size: f32) -> Self {
+        Self { broadphase: SpatialHash::new(cell_size), ..Default::default() }
+    }
+}
+impl<'a> System<'a> for FlowAgentSys {
+    type SystemData = (Entities<'a>,
+                       Read<'a, Tilemap>,
+                       Read<'a, FlowField>,
+                       Read<'a, DeltaTime>,
+                       ReadStorage<'a, Position>,
+                       ReadStorage<'a, FlowAgent>,
+                       WriteStorage<'a, Velocity>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, tilemap, field, dt, positions, flow_agents, mut vels) = data;
+        let dt = dt.0 as f32;
+
+        self.broadphase.clear();
+        self.agents.clear();
+        for (entity, pos, agent, vel) in (&entities, &positions, &flow_agents, &vels).join() {
+            // Agents overlap once they're within either one's radius
+            let reach = agent.separation_radius / 2.0;
+            self.broadphase.insert(&Rect::from_center((pos.x, pos.y), (reach, reach)));
+            self.agents.push(Agent {
+                entity, pos: (pos.x, pos.y), vel: (vel.x, vel.y), agent: agent.clone(),
+                separation: (0.0, 0.0), neighbours: ((0.0, 0.0), 0),
+            });
+        }
+
+        let agents = &mut self.agents;
+        self.broadphase.for_each_pair(|a, b| {
+            let range = (agents[a].agent.separation_radius + agents[b].agent.separation_radius) / 2.0;
+            let (dx, dy) = (agents[a].pos.0 - agents[b].pos.0, agents[a].pos.1 - agents[b].pos.1);
+            let dist = (dx * dx + dy * dy).sqrt();
+            if dist >= range { return; }
+
+            // Agents on the very same point are split a different way for each pair,
+            // so that a stack of them spreads out rather than cancelling out
+            let away = if dist > f32::EPSILON {
+                (dx / dist, dy / dist)
+            } else {
+                let angle = (a * 31 + b) as f32 * GOLDEN_ANGLE;
+                (angle.cos(), angle.sin())
+            };
+            let push = 1.0 - dist / range;
+            let (vel_a, vel_b) = (agents[a].vel, agents[b].vel);
+            for (agent, sign, vel) in [(a, 1.0, vel_b), (b, -1.0, vel_a)] {
+                let agent = &mut agents[agent];
+                agent.separation.0 += away.0 * push * sign;
+                agent.separation.1 += away.1 * push * sign;
+                agent.neighbours.0.0 += vel.0;
+                agent.neighbours.0.1 += vel.1;
+                agent.neighbours.1 += 1;
+            }
+        });
+
+        for agent in self.agents.iter() {
+            let speed = agent.agent.speed;
+            let here = tilemap.world_to_tile(agent.pos);
+
+            // Towards the next tile, slowing to stop on the goal rather than overshooting it
+            let desired = field.next_tile(here)
+                .map(|next| {
+                    let target = tilemap.tile_to_world(next);
+                    let (dx, dy) = (target.0 - agent.pos.0, target.1 - agent.pos.1);
+                    let dist = (dx * dx + dy * dy).sqrt();
+                    if dist <= f32::EPSILON { return (0.0, 0.0); }
+                    let speed = if next == here && dt > 0.0 { speed.min(dist / dt) } else { speed };
+                    (dx / dist * speed, dy / dist * speed)
+                })
+                .unwrap_or((0.0, 0.0));
+
+            let mut steer = (
+                desired.0 + agent.separation.0 * agent.agent.separation * speed,
+                desired.1 + agent.separation.1 * agent.agent.separation * speed,
+            );
+            let ((sum_x, sum_y), count) = agent.neighbours;
+            if count > 0 {
+                let count = count as f32;
+                steer.0 += (sum_x / count - desired.0) * agent.agent.alignment;
+                steer.1 += (sum_y / count - desired.1) * agent.agent.alignment;
+            }
+
+            let steer_speed = (steer.0 * steer.0 + steer.1 * steer.1).sqrt();
+            if steer_speed > speed {
+                steer = (steer.0 / steer_speed * speed, steer.1 / steer_speed * speed);
+            }
+            if let Some(vel) = vels.get_mut(agent.entity) {
+                *vel = Velocity::new(steer.0, steer.1);
+            }
+        }
+    }
+}
@@ -1,13 +1,17 @@
 use specs::{ReadStorage, WriteStorage, System, Join, Read, SystemData};
 use specs::prelude::*;
 use std::sync::Arc;
+use std::collections::HashMap;
 use crate::ecs::component;
 use crate::error::EngineError;
 use crate::{
     model::spritesheet::{SpriteSheet, AnimationSchema, AnimMode},
-    ecs::resource::{DeltaTime, WindowSize, View, SpritesheetImgRef},
-    ecs::component::{Color, Sprite, Position, Scale, Animation, tile::*},
-    renderer::sprite::{RenderSprite, SpriteRenderer},
+    model::layer::RenderLayers,
+    ecs::resource::{DeltaTime, WindowSize, View, TileEvents, SpritesheetImgRef},
+    ecs::component::{Color, Sprite, Position, Scale, Animation, RenderLayer, RevealsOverhead, FogOfWar},
+    model::{Rect, layout::TileLayout, tilemap::{Tilemap, TileLayer, TileEvent, Chunk, CHUNK_SIZE}},
+    model::fov::{VisibilityGrid, TileVisibility},
+    renderer::sprite::{RenderSprite, SpriteRenderer, SpriteBatch, BatchStyle},
     renderer::light::{RenderLight, LightRenderer},
 };
 
@@ -180,6 +184,9 @@ impl<'a> System<'a> for AnimSpriteSys {
 
 /// A system for rendering Sprites to the screen.
 ///
+/// Entities with FogOfWar are hidden, or dimmed, while their tile is out of
+/// sight in the VisibilityGrid.
+///
 /// As this is an OpenGL System it must be called on the main thread via `with_tread_local`
 #[derive(Default)]
 pub struct SpriteRenderSys {
@@ -190,17 +197,38 @@ impl<'a> System<'a> for SpriteRenderSys {
                        ReadStorage<'a, Position>,
                        ReadStorage<'a, Scale>,
                        ReadStorage<'a, Color>,
+                       ReadStorage<'a, RenderLayer>,
+                       ReadStorage<'a, FogOfWar>,
+                       Read<'a, RenderLayers>,
+                       Read<'a, Tilemap>,
+                       Read<'a, VisibilityGrid>,
                        Read<'a, WindowSize>,
                        Read<'a, View>);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (sprites, positions, scales, colors, window, view) = data;
+        let (sprites, positions, scales, colors, layers, fogs, layer_table, tilemap, visibility, window, view) = data;
         let window = (window.0, window.1); 
         let view = (view.0, view.1, view.2);
         // Build the RenderSprite Vec from the components
         let sprites: Vec<RenderSprite> = 
-            (&sprites, &positions, &scales, &colors).join()
-                .map(|data| data.into())
+            (&sprites, &positions, &scales, &colors, layers.maybe(), fogs.maybe()).join()
+                .filter_map(|(spr, pos, scale, color, layer, fog)| {
+                    let mut sprite = RenderSprite::from((spr, pos, scale, color));
+                    // Draw depth is taken from the sprite's layer, when it has one
+                    sprite.translation.2 = layer_table.resolve(layer, pos.z);
+
+                    let seen = fog.map_or(TileVisibility::Visible, |_| visibility.get(tilemap.world_to_tile((pos.x, pos.y))));
+                    match (seen, fog) {
+                        (TileVisibility::Visible, _) => {},
+                        (TileVisibility::Remembered, Some(FogOfWar::Dim)) => {
+                            let brightness = visibility.remembered_brightness;
+                            let (r, g, b, a) = sprite.color;
+                            sprite.color = (r * brightness, g * brightness, b * brightness, a);
+                        },
+                        _ => return None,
+                    }
+                    Some(sprite)
+                })
                 .collect();
         self.renderer.render(&sprites, window, view);
     }
@@ -214,54 +242,252 @@ impl<'a> System<'a> for SpriteRenderSys {
 }
 
 //TODO join renderers into a common resource (potentially using the resource system?)
+/// A chunk's sprites on the GPU
+struct ChunkBatch {
+    /// Whether the chunk has changed since the batch was built
+    dirty:      bool,
+    batch:      SpriteBatch,
+}
+
+/// A system to draw the Tilemap resource.
+///
+/// (Tilemap, VisibilityGrid, Position, RevealsOverhead, resource::TileEvents,
+///  resource::WindowSize, resource::View, resource::DeltaTime)
+///
+/// Each visible layer is drawn back to front at its depth, with its tint,
+/// opacity and parallax. In layouts whose tiles overlap, tiles lower down
+/// the screen are drawn over those above them. Overhead layers fade while any RevealsOverhead
+/// entity is beneath one of their tiles.
+///
+/// Each chunk's sprites are kept on the GPU, only being rebuilt when a
+/// TileEvent changes the chunk, and only the chunks overlapping the view are drawn.
+/// Tiles play their sprite's "idle" animation on the GPU, by a clock shared
+/// by every tile so that they stay in step, unless it has a `random_phase`.
+/// Tiles never seen in the VisibilityGrid are hidden, and remembered tiles dimmed.
+#[derive(Default)]
 pub struct TileRenderSys {
-    renderer: SpriteRenderer,
-    scale:  (f32, f32),
+    renderer:   SpriteRenderer,
+    tile_reader: Option<ReaderId<TileEvent>>,
+    /// The batches of each layer's chunks, by layer name
+    batches:    HashMap<String, HashMap<(i32, i32), ChunkBatch>>,
+    /// The tile size and layout the batches were built with
+    built_with: ((f32, f32), TileLayout),
+    /// How far each overhead layer has faded, from 0 to 1, by layer name
+    fades:      HashMap<String, f32>,
+    /// The seconds tile animations have been playing for
+    clock:      f64,
+    /// The revision of the VisibilityGrid the batches were built with
+    visibility_revision: u64,
 }
-impl Default for TileRenderSys {
-    fn default() -> Self {
-        Self {
-            renderer: SpriteRenderer::default(),
-            scale: (5.0, 5.0),
-        }
+impl TileRenderSys {
+    /// The seconds an overhead layer takes to fully fade
+    const FADE_TIME: f32 = 0.25;
+
+    /// Builds the sprites of every tile in a chunk
+    fn chunk_sprites(tilemap: &Tilemap, visibility: &VisibilityGrid, coord: (i32, i32), chunk: &Chunk)
+            -> Vec<RenderSprite> {
+        let origin = (coord.0 * CHUNK_SIZE, coord.1 * CHUNK_SIZE);
+        let scale = tilemap.scale;
+        let offset = tilemap.layout.sprite_offset(tilemap.tile_size());
+        let dimmed = visibility.remembered_brightness;
+        chunk.tiles()
+            .filter_map(|((x, y), schema)| {
+                let pos = (origin.0 + x, origin.1 + y);
+                let color = match visibility.get(pos) {
+                    TileVisibility::Visible => (1.0, 1.0, 1.0, 1.0),
+                    TileVisibility::Remembered => (dimmed, dimmed, dimmed, 1.0),
+                    TileVisibility::Unseen => return None,
+                };
+                let (wx, wy) = tilemap.tile_to_world(pos);
+                let depth = tilemap.layout.depth_bias(wy);
+                let (dim_x, dim_y) = schema.dimensions;
+                let sprite = RenderSprite {
+                    // Layers are moved to their depth as they're drawn, overlapping
+                    // tiles being sorted within it
+                    translation:    (wx + offset.0, wy + offset.1, depth),
+                    scale,
+                    color,
+                    sprite_id:      schema.root,
+                    sprite_dims:    dim_x | (dim_y << 4),
+                    ..Default::default()
+                };
+                Some(match schema.animations.get("idle") {
+                    Some(anim) => {
+                        let phase = if anim.random_phase { Self::phase(pos) } else { 0 };
+                        sprite.with_animation(anim, phase)
+                    },
+                    None => sprite,
+                })
+            })
+            .collect()
+    }
+
+    /// A tile's animation phase, the same each time its chunk is built
+    fn phase(pos: (i32, i32)) -> u8 {
+        let hash = (pos.0 as u32).wrapping_mul(0x9E37_79B1) ^ (pos.1 as u32).wrapping_mul(0x85EB_CA77);
+        (hash.wrapping_mul(0xC2B2_AE35) >> 27) as u8
+    }
+
+    /// Moves an overhead layer's fade towards whether it's hiding something, returning its opacity
+    fn fade(&mut self, layer: &TileLayer, beneath: &[(i32, i32)], dt: f32) -> f32 {
+        let opacity = layer.settings.opacity;
+        let faded = match layer.settings.overhead {
+            Some(faded) => faded,
+            None => return opacity,
+        };
+        let target = if beneath.iter().any(|pos| layer.get(*pos).is_some()) { 1.0 } else { 0.0 };
+        let fade = self.fades.entry(layer.name().into()).or_insert(0.0);
+        let step = dt / Self::FADE_TIME;
+        *fade = if *fade < target { (*fade + step).min(target) } else { (*fade - step).max(target) };
+        opacity * (1.0 + (faded - 1.0) * *fade)
+    }
+
+    /// Drops every batch of a layer, or of every layer
+    fn delete_batches(&mut self, layer: Option<&str>) {
+        let renderer = self.renderer;
+        self.batches.retain(|name, chunks| {
+            let deleted = layer.is_none_or(|layer| layer == name);
+            if deleted {
+                chunks.drain().for_each(|(_, cached)| renderer.delete_batch(cached.batch));
+            }
+            !deleted
+        });
     }
 }
 impl<'a> System<'a> for TileRenderSys {
-    type SystemData = (ReadStorage<'a, Tile>,
-                       ReadStorage<'a, Floor>,
-                       ReadStorage<'a, Wall>,
-                       ReadStorage<'a, Color>,
+    type SystemData = (Read<'a, Tilemap>,
+                       Read<'a, VisibilityGrid>,
+                       ReadStorage<'a, Position>,
+                       ReadStorage<'a, RevealsOverhead>,
+                       Read<'a, TileEvents>,
                        Read<'a, WindowSize>,
-                       Read<'a, View>);
+                       Read<'a, View>,
+                       Read<'a, DeltaTime>);
 
     fn run(&mut self, data: Self::SystemData) {
         // Unpack system data
-        let (tiles, floors, walls, colors, window, view) = data;
+        let (tilemap, visibility, positions, reveals, tile_events, window, view, dt) = data;
         let window = (window.0, window.1);
         let view = (view.0, view.1, view.2);
-        let scale = self.scale.clone();
-        let sprites: Vec<RenderSprite> = 
-            (&tiles, &floors, &colors).join()
-                .map(|data| {
-                    let (tile, floor, color) = data;
-                    RenderSprite::from((tile, color, floor.schema.clone(), scale, -10.1))
-                })
-                .collect();
-        self.renderer.render(&sprites, window, view);
+        let renderer = self.renderer;
+        self.clock += dt.0;
 
-        let sprites: Vec<RenderSprite> = 
-            (&tiles, &walls, &colors).join()
-                .map(|data| {
-                    let (tile, wall, color) = data;
-                    RenderSprite::from((tile, color, wall.schema.clone(), scale, -10.0))
-                })
-                .collect();
-        self.renderer.render(&sprites, window, view);
+        // Everything must be rebuilt if the tiles are sized or laid out
+        // differently, as must any chunk which changed
+        let tile = tilemap.tile_size();
+        if self.built_with != (tile, tilemap.layout) {
+            self.built_with = (tile, tilemap.layout);
+            self.delete_batches(None);
+        }
+        if let Some(reader) = self.tile_reader.as_mut() {
+            for event in tile_events.read(reader) {
+                match event {
+                    TileEvent::Changed { layer, pos, .. } => {
+                        let coord = Tilemap::chunk_coord(*pos);
+                        let chunks = match self.batches.get_mut(layer) { Some(chunks) => chunks, None => continue };
+                        // The batches of unloaded chunks are dropped, rather than kept empty
+                        if tilemap.layer(layer).is_some_and(|layer| layer.chunk(coord).is_none()) {
+                            if let Some(cached) = chunks.remove(&coord) { renderer.delete_batch(cached.batch); }
+                        } else if let Some(cached) = chunks.get_mut(&coord) {
+                            cached.dirty = true;
+                        }
+                    },
+                    TileEvent::LayerChanged(name) => if tilemap.layer(name).is_none() {
+                        self.delete_batches(Some(name));
+                        self.fades.remove(name);
+                    },
+                    TileEvent::Reset => {
+                        self.delete_batches(None);
+                        self.fades.clear();
+                    },
+                    // Properties don't change how tiles are drawn
+                    TileEvent::PropertiesChanged(_) => {},
+                }
+            }
+        }
+
+        // As are the chunks of tiles which came into or out of sight
+        if visibility.revision() != self.visibility_revision {
+            let changed = visibility.changed().filter(|_| visibility.revision() == self.visibility_revision + 1);
+            for chunks in self.batches.values_mut() {
+                match changed {
+                    Some(changed) => for pos in changed {
+                        if let Some(cached) = chunks.get_mut(&Tilemap::chunk_coord(*pos)) { cached.dirty = true; }
+                    },
+                    None => chunks.values_mut().for_each(|cached| cached.dirty = true),
+                }
+            }
+            self.visibility_revision = visibility.revision();
+        }
+
+        let beneath: Vec<(i32, i32)> = (&positions, &reveals).join()
+            .map(|(pos, _)| tilemap.world_to_tile((pos.x, pos.y)))
+            .collect();
+
+        // Drawn back to front, for translucent layers to blend over those beneath
+        let mut layers: Vec<&TileLayer> = tilemap.layers().filter(|layer| layer.settings.visible).collect();
+        layers.sort_by(|a, b| a.settings.depth.total_cmp(&b.settings.depth));
+
+        let mut visible = Vec::new();
+        for layer in layers {
+            let settings = &layer.settings;
+            let opacity = self.fade(layer, &beneath, dt.0 as f32);
+            // Distant layers are pulled along with the view, so appear to move slower
+            let offset = (view.0 * (1.0 - settings.parallax.0), view.1 * (1.0 - settings.parallax.1));
+            let tint = &settings.tint;
+            let style = BatchStyle {
+                tint:   (tint.r, tint.g, tint.b, tint.a * opacity),
+                offset: (offset.0, offset.1, settings.depth),
+                time:   self.clock as f32,
+            };
+
+            // The layer's chunks under the view, with a tile's margin as
+            // sprites are drawn slightly above their position
+            let (left, bottom) = (view.0 - offset.0, view.1 - offset.1);
+            let view_rect = Rect::new(
+                (left - tile.0, bottom + window.1 + tile.1),
+                (left + window.0 + tile.0, bottom - tile.1)
+            );
+            let (min, max) = tilemap.tiles_in(&view_rect);
+            let (min, max) = (Tilemap::chunk_coord(min), Tilemap::chunk_coord(max));
+
+            let batches = self.batches.entry(layer.name().into()).or_default();
+            for cx in min.0..=max.0 {
+                for cy in min.1..=max.1 {
+                    let chunk = match layer.chunk((cx, cy)) {
+                        Some(chunk) => chunk,
+                        None => continue,
+                    };
+
+                    // Rebuild the chunk's batch only once it has changed
+                    let cached = batches.get_mut(&(cx, cy));
+                    if cached.as_ref().is_none_or(|cached| cached.dirty) {
+                        let sprites = Self::chunk_sprites(&tilemap, &visibility, (cx, cy), chunk);
+                        match cached {
+                            Some(cached) => {
+                                renderer.update_batch(&mut cached.batch, &sprites);
+                                cached.dirty = false;
+                            },
+                            None => {
+                                let batch = renderer.create_batch(&sprites);
+                                batches.insert((cx, cy), ChunkBatch { dirty: false, batch });
+                            },
+                        }
+                    }
+                    visible.push((layer.name(), (cx, cy), style));
+                }
+            }
+        }
+
+        let batches = &self.batches;
+        let draws = visible.iter().map(|(name, coord, style)| (&batches[*name][coord].batch, *style));
+        renderer.render_batches(draws, window, view);
     }
 
     fn setup(&mut self, world: &mut World) {
         Self::SystemData::setup(world);
         self.renderer = SpriteRenderer::new();
         self.renderer.init(include_bytes!("../../../../assets/textures/sprites.png")).unwrap();
+        self.tile_reader = Some(world.fetch_mut::<TileEvents>().register_reader());
     }
 }
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Sender, Receiver};
+
+use specs::{ReadStorage, WriteStorage, System, Join, Read, Write};
+use specs::prelude::*;
+use specs::world::LazyBuilder;
+
+use crate::EngineError;
+use crate::model::{
+    tilemap::Tilemap,
+    tiled::Spawn,
+    streaming::{ChunkData, ChunkProvider, ChunkStreaming},
+};
+use crate::ecs::{
+    component::{Position, ChunkEntity},
+    resource::{View, WindowSize},
+};
+
+/// Adds the components of a spawn's prefab to a new entity, at the spawn's
+/// world position.
+pub type Spawner = Box<dyn for<'a> Fn(LazyBuilder<'a>, &Spawn, (f32, f32)) -> LazyBuilder<'a> + Send + Sync>;
+
+/// A request for the provider's thread
+enum ChunkRequest {
+    Load((i32, i32)),
+    Save((i32, i32), ChunkData),
+}
+
+/// What the provider's thread did for a request
+enum ChunkResult {
+    Loaded((i32, i32), Result<Option<ChunkData>, EngineError>),
+    Saved((i32, i32), Result<(), EngineError>),
+}
+
+/// A system to stream the chunks of an endless world into the Tilemap
+/// around the View, and out of it once they're far enough away.
+///
+/// (Tilemap, ChunkStreaming, resource::View, resource::WindowSize, Position, ChunkEntity)
+///
+/// Chunks are loaded and saved by a `ChunkProvider` on a thread of its own,
+/// nearest the center of the view first, and only a few are placed or
+/// unloaded each update so that moving quickly doesn't stall the game.
+/// The spawns of each chunk are created through the spawner, lazily, as
+/// ChunkEntities, and deleted as their chunk is unloaded.
+/// This should run before the TileEventSys.
+pub struct ChunkStreamSys {
+    requests:   Sender<ChunkRequest>,
+    results:    Receiver<ChunkResult>,
+    spawner:    Option<Spawner>,
+    /// The chunks asked for, which haven't been placed yet
+    pending:    HashSet<(i32, i32)>,
+    /// The chunks loaded by the provider, waiting to be placed
+    ready:      HashMap<(i32, i32), ChunkData>,
+    /// The chunks which couldn't be loaded, left alone until they go out of range
+    failed:     HashSet<(i32, i32)>,
+}
+impl ChunkStreamSys {
+    /// Creates a ChunkStreamSys, moving the provider onto a thread of its own.
+    pub fn new(provider: impl ChunkProvider + 'static) -> Self {
+        let (requests, requested) = mpsc::channel::<ChunkRequest>();
+        let (finished, results) = mpsc::channel::<ChunkResult>();
+        let mut provider = provider;
+        std::thread::spawn(move || {
+            // Exit the thread once the system is dropped, after the saves already asked for
+            while let Ok(request) = requested.recv() {
+                let result = match request {
+                    ChunkRequest::Load(coord) => ChunkResult::Loaded(coord, provider.load(coord)),
+                    ChunkRequest::Save(coord, chunk) => ChunkResult::Saved(coord, provider.save(coord, &chunk)),
+                };
+                let _ = finished.send(result);
+            }
+        });
+        Self {
+            requests, results, spawner: None,
+            pending: HashSet::new(), ready: HashMap::new(), failed: HashSet::new(),
+        }
+    }
+
+    /// Creates the entities of chunks' spawns with a spawner, which adds the
+    /// components of each spawn's prefab.
+    pub fn with_spawner<F>(self, spawner: F) -> Self
+            where F: for<'a> Fn(LazyBuilder<'a>, &Spawn, (f32, f32)) -> LazyBuilder<'a> + Send + Sync + 'static {
+        Self { spawner: Some(Box::new(spawner)), ..self }
+    }
+
+    /// Asks the provider to keep a chunk, as it is in the tilemap
+    fn save(&self, tilemap: &Tilemap, streaming: &mut ChunkStreaming, coord: (i32, i32), spawns: Vec<Spawn>) {
+        match ChunkData::from_tilemap(tilemap, coord, spawns) {
+            Ok(chunk) => if self.requests.send(ChunkRequest::Save(coord, chunk)).is_ok() { streaming.saving += 1 },
+            Err(error) => streaming.errors.push((coord, error)),
+        }
+    }
+}
+impl<'a> System<'a> for ChunkStreamSys {
+    type SystemData = (Entities<'a>,
+                       Read<'a, LazyUpdate>,
+                       Write<'a, Tilemap>,
+                       Write<'a, ChunkStreaming>,
+                       Read<'a, View>,
+                       Read<'a, WindowSize>,
+                       ReadStorage<'a, Position>,
+                       WriteStorage<'a, ChunkEntity>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (entities, lazy, mut tilemap, mut streaming, view, window, positions, mut chunk_entities) = data;
+
+        // Collect whatever the provider has finished
+        while let Ok(result) = self.results.try_recv() {
+            match result {
+                ChunkResult::Loaded(coord, result) => {
+                    if !self.pending.contains(&coord) { continue; }
+                    match result {
+                        Ok(chunk) => { self.ready.insert(coord, chunk.unwrap_or_default()); },
+                        Err(error) => {
+                            self.pending.remove(&coord);
+                            self.failed.insert(coord);
+                            streaming.errors.push((coord, error));
+                        },
+                    }
+                },
+                ChunkResult::Saved(coord, result) => {
+                    streaming.saving = streaming.saving.saturating_sub(1);
+                    if let Err(error) = result { streaming.errors.push((coord, error)); }
+                },
+            }
+        }
+
+        let center = Tilemap::chunk_coord(tilemap.world_to_tile((view.0 + window.0 / 2.0, view.1 + window.1 / 2.0)));
+        let distance = |(x, y): (i32, i32)| (((x - center.0).pow(2) + (y - center.1).pow(2)) as f32).sqrt();
+        let unload_radius = streaming.unload_radius.max(streaming.load_radius);
+
+        // Chunks which went out of range before being placed are forgotten
+        self.pending.retain(|coord| distance(*coord) <= unload_radius);
+        self.ready.retain(|coord, _| distance(*coord) <= unload_radius);
+        self.failed.retain(|coord| distance(*coord) <= unload_radius);
+
+        // Ask for the chunks in range, nearest first
+        let reach = streaming.load_radius.max(0.0) as i32;
+        let mut wanted: Vec<(i32, i32)> = (center.0 - reach..=center.0 + reach)
+            .flat_map(|x| (center.1 - reach..=center.1 + reach).map(move |y| (x, y)))
+            .filter(|coord| distance(*coord) <= streaming.load_radius)
+            .filter(|coord| !streaming.loaded.contains(coord) && !self.pending.contains(coord) && !self.failed.contains(coord))
+            .collect();
+        wanted.sort_by(|a, b| distance(*a).total_cmp(&distance(*b)));
+        for coord in wanted {
+            if self.requests.send(ChunkRequest::Load(coord)).is_ok() { self.pending.insert(coord); }
+        }
+
+        // Place a few of the loaded chunks, nearest first
+        let mut ready: Vec<(i32, i32)> = self.ready.keys().copied().collect();
+        ready.sort_by(|a, b| distance(*a).total_cmp(&distance(*b)));
+        for coord in ready.into_iter().take(streaming.max_per_update) {
+            let chunk = self.ready.remove(&coord).unwrap();
+            self.pending.remove(&coord);
+            let tiles = match chunk.resolve(&tilemap, coord) {
+                Ok(tiles) => tiles,
+                Err(error) => {
+                    self.failed.insert(coord);
+                    streaming.errors.push((coord, error));
+                    continue;
+                },
+            };
+            tilemap.load_chunk(coord, tiles);
+            streaming.loaded.insert(coord);
+
+            for spawn in chunk.spawns {
+                let pos = spawn.world_position(&tilemap);
+                let builder = lazy.create_entity(&entities)
+                    .with(Position { x: pos.0, y: pos.1, z: 0.0 });
+                let builder = match self.spawner.as_ref() {
+                    Some(spawner) => spawner(builder, &spawn, pos),
+                    None => builder,
+                };
+                builder.with(ChunkEntity { chunk: coord, spawn }).build();
+            }
+        }
+
+        // Entities belong to the loaded chunk they're in
+        for (pos, member) in (&positions, &mut chunk_entities).join() {
+            let chunk = Tilemap::chunk_coord(tilemap.world_to_tile((pos.x, pos.y)));
+            if chunk != member.chunk && streaming.loaded.contains(&chunk) { member.chunk = chunk; }
+        }
+
+        // Keep every loaded chunk, if asked to
+        if streaming.save_requested {
+            streaming.save_requested = false;
+            let mut spawns: HashMap<(i32, i32), Vec<Spawn>> = HashMap::new();
+            for (pos, member) in (&positions, &chunk_entities).join() {
+                let mut spawn = member.spawn.clone();
+                spawn.set_world_position((pos.x, pos.y), &tilemap);
+                spawns.entry(member.chunk).or_default().push(spawn);
+            }
+            let loaded: Vec<(i32, i32)> = streaming.loaded.iter().copied().collect();
+            for coord in loaded {
+                self.save(&tilemap, &mut streaming, coord, spawns.remove(&coord).unwrap_or_default());
+            }
+        }
+
+        // Unload a few of the chunks out of range, furthest first
+        let mut distant: Vec<(i32, i32)> = streaming.loaded.iter().copied()
+            .filter(|coord| distance(*coord) > unload_radius)
+            .collect();
+        distant.sort_by(|a, b| distance(*b).total_cmp(&distance(*a)));
+        for coord in distant.into_iter().take(streaming.max_per_update) {
+            let mut spawns = vec![];
+            for (entity, pos, member) in (&entities, &positions, &chunk_entities).join() {
+                if member.chunk != coord { continue; }
+                let mut spawn = member.spawn.clone();
+                spawn.set_world_position((pos.x, pos.y), &tilemap);
+                spawns.push(spawn);
+                let _ = entities.delete(entity);
+            }
+            if streaming.save_unloaded { self.save(&tilemap, &mut streaming, coord, spawns); }
+            tilemap.unload_chunk(coord);
+            streaming.loaded.remove(&coord);
+        }
+    }
+}
@@ -1,7 +1,8 @@
 use specs::{ReadStorage, WriteStorage, System, Join, Read, SystemData};
 use specs::prelude::*;
 use crate::{
-    ecs::component::{Color, Position, Text},
+    model::layer::RenderLayers,
+    ecs::component::{Color, Position, Text, RenderLayer},
     ecs::resource::{WindowSize, View},
     renderer::text::*,
 };
@@ -15,16 +16,22 @@ impl<'a> System<'a> for TextRenderSys {
     type SystemData = (ReadStorage<'a, Text>,
                        ReadStorage<'a, Position>,
                        ReadStorage<'a, Color>,
+                       ReadStorage<'a, RenderLayer>,
+                       Read<'a, RenderLayers>,
                        Read<'a, WindowSize>,
                        Read<'a, View>);
 
     fn run(&mut self, data: Self::SystemData) {
-        let (texts, pos, colors, window, view) = data;
+        let (texts, pos, colors, layers, layer_table, window, view) = data;
         let window = (window.0, window.1);
         let view = (view.0, view.1, view.2);
         let texts: Vec<RenderString> = 
-            (&texts, &pos, &colors).join()
-                .map(|data| data.into())
+            (&texts, &pos, &colors, layers.maybe()).join()
+                .map(|(text, pos, color, layer)| {
+                    let mut string = RenderString::from((text, pos, color));
+                    string.position.2 = layer_table.resolve(layer, pos.z);
+                    string
+                })
                 .collect();
         self.renderer.render(&texts, window, view);
     }
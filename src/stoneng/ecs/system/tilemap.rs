@@ -0,0 +1,22 @@
+use specs::{System, Write};
+
+use crate::model::tilemap::Tilemap;
+use crate::ecs::resource::TileEvents;
+
+/// A system to send the changes made to the Tilemap out as TileEvents.
+///
+/// (Tilemap, resource::TileEvents)
+///
+/// Systems which keep anything about the map between updates, like the
+/// TileRenderSys and the CollisionSys, read these, so this should run first.
+#[derive(Default)]
+pub struct TileEventSys;
+impl<'a> System<'a> for TileEventSys {
+    type SystemData = (Write<'a, Tilemap>,
+                       Write<'a, TileEvents>);
+
+    fn run(&mut self, data: Self::SystemData) {
+        let (mut tilemap, mut events) = data;
+        events.iter_write(tilemap.drain_events());
+    }
+}
@@ -7,6 +7,17 @@ pub enum EngineError {
     SheetParseError(ron::error::Error),
     SheetSizeError(String),
     AnimationError(String),
+    LayerRangeError(String),
+    ColorParseError(String),
+    /// A text level was malformed, at the given (1-based) line and column
+    LevelParseError { line: usize, column: usize, reason: String },
+    /// A Tiled map couldn't be loaded
+    MapLoadError(String),
+    AutotileError(String),
+    /// A level file couldn't be saved or loaded
+    LevelError(String),
+    /// A collider's shape was degenerate, e.g. a polygon which isn't convex
+    ShapeError(String),
 }
 
 impl From<ron::error::Error> for EngineError {
@@ -10,6 +10,7 @@ pub mod ecs;
 pub mod controller;
 
 pub mod audio;
+pub mod procgen;
 
 mod shader;
 mod error;
@@ -0,0 +1,185 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::EngineError;
+use crate::model::spritesheet::{SpriteSheet, SpriteSchema};
+
+/// The neighbours of a tile, clockwise from north, as their bit in an eight-bit mask
+const NEIGHBOURS: [(i32, i32); 8] = [(0, 1), (1, 1), (1, 0), (1, -1), (0, -1), (-1, -1), (-1, 0), (-1, 1)];
+
+/// How an autotiled sprite's neighbours are combined into a mask.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutotileStyle {
+    /// The four edge neighbours, N = 1, E = 2, S = 4 and W = 8 (16 masks)
+    FourBit,
+    /// All eight neighbours, clockwise from N = 1, NE = 2, E = 4 ... to NW = 128 (256 masks)
+    EightBit,
+    /// As EightBit, but corners only count when both edges beside them connect (47 masks)
+    Blob,
+}
+impl AutotileStyle {
+    /// Builds the mask of a tile, from whether each neighbour offset connects to it.
+    pub fn mask(&self, connects: impl Fn((i32, i32)) -> bool) -> u8 {
+        let eight_bit = NEIGHBOURS.iter().enumerate()
+            .filter(|(_, offset)| connects(**offset))
+            .fold(0, |mask, (bit, _)| mask | 1 << bit);
+        self.reduce(eight_bit)
+    }
+
+    /// Reduces an eight-bit mask to the bits this style uses
+    fn reduce(&self, mask: u8) -> u8 {
+        let edge = |bit: u32| mask & (1 << (bit % 8)) != 0;
+        match self {
+            Self::FourBit => (0..4).filter(|i| edge(i * 2)).fold(0, |m, i| m | 1 << i),
+            Self::EightBit => mask,
+            Self::Blob => (0..8)
+                .filter(|bit| edge(*bit) && (bit % 2 == 0 || (edge(bit - 1) && edge(bit + 1))))
+                .fold(0, |m, bit| m | 1 << bit),
+        }
+    }
+
+    /// Whether a mask can be produced by this style
+    fn is_valid(&self, mask: u8) -> bool {
+        match self {
+            Self::FourBit => mask < 16,
+            _ => self.reduce(mask) == mask,
+        }
+    }
+}
+
+/// Picks the variant of a sprite, placed on a tilemap layer, from its neighbours
+/// on that layer.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AutotileRule {
+    pub sprite:         String,
+    pub layer:          String,
+    pub style:          AutotileStyle,
+    /// Other sprites the sprite joins up with, besides itself and its variants
+    #[serde(default)]
+    pub connects_to:    Vec<String>,
+    /// The variant of the sprite used for each mask, the sprite itself being
+    /// used for any mask without one
+    pub variants:       HashMap<u8, String>,
+}
+
+/// A set of autotile rules, loaded from Rusty Object Notation.
+///
+/// Once given to a `Tilemap`, any tile of a rule's sprite (or its variants)
+/// is replaced with the variant matching its neighbours, whenever it or
+/// one of its neighbours is set or removed.
+///
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use stoneng::model::{spritesheet::SpriteSheet, tilemap::*, autotile::*};
+/// # let img_data = include_bytes!("./autotile.rs");
+/// let sheet = SpriteSheet::new(r#"SpriteSheet(sheet_width: 256, tile_width: 32, sprites: {
+///     "water": (root: 16, variants: { "pool": (root: 17), "north-south": (root: 18) }),
+/// })"#, img_data).unwrap();
+/// let rules = AutotileRules::new(r#"AutotileRules(rules: [
+///     (
+///         sprite: "water",
+///         layer:  "floor",
+///         style:  FourBit,
+///         // N | S
+///         variants: { 0: "pool", 5: "north-south" },
+///     ),
+/// ])"#).unwrap();
+///
+/// let water = sheet.sprites["water"].clone();
+/// let mut map = Tilemap::new(Arc::new(sheet));
+/// map.set_autotile_rules(&rules).unwrap();
+/// let root = |map: &Tilemap, pos| map.get_tile(pos, "floor").unwrap().root;
+///
+/// map.set_tile((0, 0), "floor", water.clone());
+/// assert_eq!(root(&map, (0, 0)), 17);
+/// map.set_tile((0, 1), "floor", water.clone());
+/// map.set_tile((0, -1), "floor", water.clone());
+/// assert_eq!(root(&map, (0, 0)), 18);
+/// // Only a northern neighbour, which has no variant
+/// map.remove_tile((0, -1), "floor");
+/// assert_eq!(root(&map, (0, 0)), 16);
+/// ```
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AutotileRules {
+    pub rules:  Vec<AutotileRule>,
+}
+impl AutotileRules {
+    pub fn new(layout: &str) -> Result<Self, EngineError> {
+        Ok(ron::from_str(layout)?)
+    }
+
+    /// Finds the rules' sprites in a sheet, for a tilemap to use.
+    pub(crate) fn resolve(&self, sheet: &SpriteSheet) -> Result<Autotiler, EngineError> {
+        let mut autotiler = Autotiler { rules: vec![], families: HashMap::new() };
+        for rule in self.rules.iter() {
+            let error = |reason: String| EngineError::AutotileError(format!("\"{}\": {}", rule.sprite, reason));
+            let find = |name: &String| sheet.sprites.get(name)
+                .ok_or_else(|| error(format!("no sprite named \"{}\"", name)));
+            // A sprite and its variants are all part of the same autotile
+            let family = |sprite: &Arc<SpriteSchema>| std::iter::once(key(sprite))
+                .chain(sprite.variants.values().map(key))
+                .collect::<Vec<_>>();
+
+            let sprite = find(&rule.sprite)?;
+            let mut connects: HashSet<usize> = family(sprite).into_iter().collect();
+            for other in rule.connects_to.iter() {
+                connects.extend(family(find(other)?));
+            }
+
+            let mut variants = HashMap::new();
+            for (mask, name) in rule.variants.iter() {
+                if !rule.style.is_valid(*mask) {
+                    return Err(error(format!("mask {} can't occur with the {:?} style", mask, rule.style)));
+                }
+                let variant = sprite.variants.get(name)
+                    .ok_or_else(|| error(format!("no variant named \"{}\"", name)))?;
+                variants.insert(*mask, variant.clone());
+            }
+
+            let families = autotiler.families.entry(rule.layer.clone()).or_default();
+            for member in family(sprite) {
+                if families.insert(member, autotiler.rules.len()).is_some() {
+                    return Err(error(format!("has more than one rule for the \"{}\" layer", rule.layer)));
+                }
+            }
+            autotiler.rules.push(ResolvedRule { style: rule.style, sprite: sprite.clone(), connects, variants });
+        }
+        Ok(autotiler)
+    }
+}
+
+/// Identifies a sprite schema by where it's stored
+fn key(schema: &Arc<SpriteSchema>) -> usize { Arc::as_ptr(schema) as usize }
+
+#[derive(Debug)]
+struct ResolvedRule {
+    style:      AutotileStyle,
+    sprite:     Arc<SpriteSchema>,
+    connects:   HashSet<usize>,
+    variants:   HashMap<u8, Arc<SpriteSchema>>,
+}
+
+/// Autotile rules with their sprites found in the tilemap's sheet.
+///
+/// Sprites are matched by identity, the rules holding on to them so they stay valid.
+#[derive(Debug)]
+pub(crate) struct Autotiler {
+    rules:      Vec<ResolvedRule>,
+    /// The rule each sprite (and variant) belongs to, for each layer
+    families:   HashMap<String, HashMap<usize, usize>>,
+}
+impl Autotiler {
+    /// Picks the sprite a tile should use, given the sprite at each of its neighbour offsets.
+    ///
+    /// Returns None if the tile isn't autotiled.
+    pub(crate) fn pick<'a>(&self, current: &Arc<SpriteSchema>, layer: &str,
+                           neighbour: impl Fn((i32, i32)) -> Option<&'a Arc<SpriteSchema>>)
+            -> Option<Arc<SpriteSchema>> {
+        let rule = &self.rules[*self.families.get(layer)?.get(&key(current))?];
+        let mask = rule.style.mask(|offset| neighbour(offset).is_some_and(|n| rule.connects.contains(&key(n))));
+        Some(rule.variants.get(&mask).unwrap_or(&rule.sprite).clone())
+    }
+}
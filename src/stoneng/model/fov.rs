@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+
+/// What's known of a tile, from what has been in sight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileVisibility {
+    /// Never seen
+    Unseen,
+    /// Seen before, but out of sight now
+    Remembered,
+    /// In sight
+    Visible,
+}
+
+/// Which tiles are in sight of the viewers, and which have been seen before.
+///
+/// The grid is used as a World resource, updated by the FovSys. Until
+/// there's a viewer every tile is visible, so that games without fog of war
+/// needn't do anything.
+///
+/// # Example
+/// ```
+/// # use std::collections::HashSet;
+/// # use stoneng::model::fov::*;
+/// // A wall along x = 2, with a gap at y = 0
+/// let opaque = |(x, y): (i32, i32)| x == 2 && y != 0;
+/// let mut visible = HashSet::new();
+/// field_of_view((0, 0), 8, opaque, |pos| { visible.insert(pos); });
+/// assert!(visible.contains(&(2, 1)) && visible.contains(&(6, 0)));
+/// assert!(!visible.contains(&(4, 3)));
+///
+/// let mut grid = VisibilityGrid::default();
+/// grid.update(visible);
+/// assert_eq!(grid.get((6, 0)), TileVisibility::Visible);
+///
+/// // Moving behind the wall, the corridor is remembered
+/// let mut visible = HashSet::new();
+/// field_of_view((0, 5), 8, opaque, |pos| { visible.insert(pos); });
+/// grid.update(visible);
+/// assert_eq!(grid.get((6, 0)), TileVisibility::Remembered);
+/// assert_eq!(grid.get((4, 3)), TileVisibility::Unseen);
+/// ```
+#[derive(Debug, Clone)]
+pub struct VisibilityGrid {
+    /// How bright remembered tiles are drawn, from 0 to 1
+    pub remembered_brightness:  f32,
+    visible:                    HashSet<(i32, i32)>,
+    seen:                       HashSet<(i32, i32)>,
+    /// Whether there are viewers, without which everything is visible
+    active:                     bool,
+    /// The tiles whose visibility changed with the last revision
+    changed:                    Vec<(i32, i32)>,
+    /// Whether every tile may have changed with the last revision
+    all_changed:                bool,
+    revision:                   u64,
+}
+impl Default for VisibilityGrid {
+    fn default() -> Self {
+        Self {
+            remembered_brightness: 0.35, visible: HashSet::new(), seen: HashSet::new(), active: false,
+            changed: vec![], all_changed: false, revision: 0,
+        }
+    }
+}
+impl VisibilityGrid {
+    pub fn get(&self, pos: (i32, i32)) -> TileVisibility {
+        if !self.active || self.visible.contains(&pos) {
+            TileVisibility::Visible
+        } else if self.seen.contains(&pos) {
+            TileVisibility::Remembered
+        } else {
+            TileVisibility::Unseen
+        }
+    }
+
+    pub fn is_active(&self) -> bool { self.active }
+
+    /// Changes whenever the visibility of any tile does.
+    pub fn revision(&self) -> u64 { self.revision }
+
+    /// The tiles whose visibility changed with the latest revision, or None
+    /// if every tile may have
+    pub fn changed(&self) -> Option<&[(i32, i32)]> {
+        if self.all_changed { None } else { Some(&self.changed) }
+    }
+
+    /// Replaces the tiles in sight, remembering them once they're out of it.
+    pub fn update(&mut self, visible: HashSet<(i32, i32)>) {
+        if !self.active {
+            self.active = true;
+            self.all_changed = true;
+        } else if visible == self.visible {
+            return;
+        } else {
+            self.all_changed = false;
+            self.changed = self.visible.symmetric_difference(&visible).copied().collect();
+        }
+        self.seen.extend(visible.iter().copied());
+        self.visible = visible;
+        self.revision += 1;
+    }
+
+    /// Stops hiding anything, as when there are no viewers.
+    pub fn deactivate(&mut self) {
+        if self.active {
+            self.active = false;
+            self.visible.clear();
+            self.all_changed = true;
+            self.revision += 1;
+        }
+    }
+
+    /// Forgets every tile seen before, as when moving to another level.
+    pub fn forget(&mut self) {
+        self.seen = self.visible.clone();
+        self.all_changed = true;
+        self.revision += 1;
+    }
+}
+
+/// A slope through a quadrant, as a fraction with a positive denominator
+#[derive(Debug, Clone, Copy)]
+struct Slope {
+    num:    i64,
+    den:    i64,
+}
+
+/// A row of tiles, at a depth out from the origin, between two slopes
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    depth:  i64,
+    start:  Slope,
+    end:    Slope,
+}
+impl Row {
+    /// The first and last columns of the row, rounding towards its middle on a tie
+    fn columns(&self) -> (i64, i64) {
+        let (start, end) = (self.start, self.end);
+        let min = (2 * self.depth * start.num + start.den).div_euclid(2 * start.den);
+        let max = -(end.den - 2 * self.depth * end.num).div_euclid(2 * end.den);
+        (min, max)
+    }
+
+    /// Whether a tile's center is within the row's slopes, so that it can
+    /// see the origin as well as be seen from it
+    fn is_symmetric(&self, col: i64) -> bool {
+        col * self.start.den >= self.depth * self.start.num && col * self.end.den <= self.depth * self.end.num
+    }
+
+    fn next(&self) -> Row { Row { depth: self.depth + 1, ..*self } }
+}
+
+/// Turns the (depth, column) of a tile within a quadrant into its position, from the origin
+type Quadrant = fn((i32, i32), i64, i64) -> (i32, i32);
+
+/// Finds the tiles within `radius` of `origin` in sight of it, calling
+/// `reveal` for each, with symmetric shadowcasting.
+///
+/// Any tile in sight of another can see it back. Opaque tiles are revealed,
+/// but block sight of the tiles behind them. Tiles may be revealed more than once.
+pub fn field_of_view(origin: (i32, i32), radius: i32, is_opaque: impl Fn((i32, i32)) -> bool,
+                     mut reveal: impl FnMut((i32, i32))) {
+    reveal(origin);
+    let radius = radius.max(0) as i64;
+
+    // Each quadrant turns a (depth, column) into a tile, looking out one way
+    let quadrants: [Quadrant; 4] = [
+        |(x, y), depth, col| (x + col as i32, y + depth as i32),
+        |(x, y), depth, col| (x + depth as i32, y + col as i32),
+        |(x, y), depth, col| (x + col as i32, y - depth as i32),
+        |(x, y), depth, col| (x - depth as i32, y + col as i32),
+    ];
+    for transform in quadrants {
+        let mut rows = vec![Row { depth: 1, start: Slope { num: -1, den: 1 }, end: Slope { num: 1, den: 1 } }];
+        while let Some(mut row) = rows.pop() {
+            if row.depth > radius { continue; }
+
+            let (min, max) = row.columns();
+            let mut previous_opaque: Option<bool> = None;
+            for col in min..=max {
+                let pos = transform(origin, row.depth, col);
+                let opaque = is_opaque(pos);
+                let in_range = row.depth * row.depth + col * col <= radius * radius;
+                if in_range && (opaque || row.is_symmetric(col)) { reveal(pos); }
+
+                // The slope through the tile's near edge, towards the start of the row
+                let slope = Slope { num: 2 * col - 1, den: 2 * row.depth };
+                match previous_opaque {
+                    // Past a wall, the row starts again
+                    Some(true) if !opaque => row.start = slope,
+                    // Up to a wall, the rows beyond are seen between the walls
+                    Some(false) if opaque => rows.push(Row { end: slope, ..row.next() }),
+                    _ => {},
+                }
+                previous_opaque = Some(opaque);
+            }
+            if previous_opaque == Some(false) { rows.push(row.next()); }
+        }
+    }
+}
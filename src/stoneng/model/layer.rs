@@ -0,0 +1,116 @@
+#![allow(dead_code)]
+use crate::EngineError;
+use crate::ecs::component::RenderLayer;
+
+use std::collections::HashMap;
+use serde::Deserialize;
+
+/// The near and far planes of the renderers' orthographic projection.
+///
+/// Every layer's depth range must fall within these bounds to be drawn.
+pub const DEPTH_RANGE: (f32, f32) = (-25.0, 25.0);
+
+/// A table of named render layers, used to resolve the draw depth of sprites.
+///
+/// Layers are drawn back to front, with higher depths drawn over lower ones.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RenderLayers {
+    /// A map containing all the layer definitions
+    pub layers: HashMap<String, LayerSchema>,
+}
+
+impl RenderLayers {
+    /// Parses a layer table from its Rusty Object Notation layout.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::model::layer::RenderLayers;
+    /// let layout = r#"
+    /// RenderLayers (
+    ///     layers: {
+    ///         // Each layer is a (back, front) depth range
+    ///         "floor":  ( depth: (-20.0, -15.0) ),
+    ///         "actors": ( depth: (-10.0,   0.0) ),
+    ///     }
+    /// )
+    /// "#;
+    /// let layers = RenderLayers::new(layout).unwrap();
+    ///
+    /// assert_eq!(layers.depth("floor", 0), Some(-20.0));
+    /// assert!(layers.depth("actors", 10).unwrap() > layers.depth("actors", 5).unwrap());
+    /// assert_eq!(layers.depth("ui", 0), None);
+    /// ```
+    pub fn new(layout: &str) -> Result<Self, EngineError> {
+        let table = ron::from_str::<RenderLayers>(layout)?;
+        for (name, layer) in table.layers.iter() {
+            layer.validate(name)?;
+        }
+        Ok(table)
+    }
+
+    /// Adds or replaces a layer in the table.
+    pub fn insert(&mut self, name: &str, layer: LayerSchema) -> Result<(), EngineError> {
+        layer.validate(name)?;
+        self.layers.insert(name.into(), layer);
+        Ok(())
+    }
+
+    /// Returns the depth of a sort order within the named layer, if it exists.
+    pub fn depth(&self, name: &str, order: u16) -> Option<f32> {
+        self.layers.get(name).map(|layer| layer.depth(order))
+    }
+
+    /// Resolves the draw depth of a sprite, falling back to `z` when it has no
+    /// (known) layer.
+    pub fn resolve(&self, layer: Option<&RenderLayer>, z: f32) -> f32 {
+        layer.and_then(|l| self.depth(&l.name, l.order)).unwrap_or(z)
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        let layers = [
+            ("floor",   (-20.0, -15.0)),
+            ("decals",  (-15.0, -12.5)),
+            ("walls",   (-12.5, -10.0)),
+            ("actors",  (-10.0,   0.0)),
+            ("fx",      (  0.0,  10.0)),
+            ("ui",      ( 10.0,  20.0)),
+        ];
+
+        Self {
+            layers: layers.iter()
+                .map(|(name, depth)| (String::from(*name), LayerSchema { depth: *depth }))
+                .collect(),
+        }
+    }
+}
+
+/// A description of a single render layer.
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct LayerSchema {
+    /// The (back, front) depths spanned by the layer's sort orders.
+    pub depth: (f32, f32),
+}
+
+impl LayerSchema {
+    /// Maps a sort order onto the layer's depth range.
+    ///
+    /// Order 0 is drawn furthest back, `u16::MAX` the furthest forward.
+    pub fn depth(&self, order: u16) -> f32 {
+        let (back, front) = self.depth;
+        back + (front - back) * (order as f32 / (u16::MAX as f32 + 1.0))
+    }
+
+    fn validate(&self, name: &str) -> Result<(), EngineError> {
+        let (back, front) = self.depth;
+        let (near, far) = DEPTH_RANGE;
+        if back > front || back < near || front > far {
+            return Err(EngineError::LayerRangeError(
+                format!("Layer \"{}\" depth {:?} must be ordered and within {:?}", 
+                        name, self.depth, DEPTH_RANGE)
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Serialize, Deserialize};
+
+/// How far a tile is drawn in front of the tile one world unit above it, for
+/// layouts whose tiles overlap, so that tiles nearer the bottom of the screen
+/// are drawn over those behind them. Maps within ±50000 world units of the
+/// origin stay within half a unit of their layer's depth.
+pub const DEPTH_PER_UNIT: f32 = 1e-5;
+
+/// Whether hexagons have a point or a flat edge at the top.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexOrientation {
+    /// Rows of hexagons, with a point at the top
+    Pointy,
+    /// Columns of hexagons, with a flat edge at the top
+    Flat,
+}
+
+/// How a tilemap's tiles are arranged in the world, for a tile size of (w, h).
+///
+/// Tile positions are always (x, y) pairs, each layout deciding where they go.
+///
+/// # Example
+/// ```
+/// # use stoneng::model::layout::*;
+/// let size = (64.0, 64.0);
+/// for layout in [
+///     TileLayout::Orthogonal, TileLayout::Isometric, TileLayout::Staggered,
+///     TileLayout::Hexagonal(HexOrientation::Pointy), TileLayout::Hexagonal(HexOrientation::Flat),
+/// ] {
+///     for pos in [(0, 0), (3, -2), (-5, 7)] {
+///         let center = layout.to_world(pos, size);
+///         assert_eq!(layout.to_tile(center, size), pos);
+///         // Points between tiles go back to where they were
+///         let point = (pos.0 as f32 + 0.25, pos.1 as f32 - 0.125);
+///         let (x, y) = layout.to_tile_fract(layout.to_world_fract(point, size), size);
+///         assert!((x - point.0).abs() < 1e-4 && (y - point.1).abs() < 1e-4);
+///         // Every neighbour shares an edge, a step away
+///         for next in layout.neighbours(pos) {
+///             assert_eq!(layout.distance(pos, next), 1);
+///         }
+///     }
+/// }
+/// assert_eq!(TileLayout::Isometric.to_world((1, 0), size), (32.0, 16.0));
+/// assert_eq!(TileLayout::Hexagonal(HexOrientation::Pointy).neighbours((0, 0)).len(), 6);
+/// assert_eq!(TileLayout::Hexagonal(HexOrientation::Flat).distance((0, 0), (3, -1)), 3);
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileLayout {
+    /// Squares w x h, x to the right and y up
+    #[default]
+    Orthogonal,
+    /// Diamonds w wide and h/2 tall, x up to the right and y up to the left
+    Isometric,
+    /// Rows of diamonds w wide and h/2 tall, x to the right and y up, each
+    /// odd row shifted right by half a tile so that the rows interlock
+    Staggered,
+    /// Hexagons w wide and h tall, in axial coordinates. Pointy hexagons
+    /// have x to the right and y up to the right, flat hexagons have x up to
+    /// the right and y up.
+    Hexagonal(HexOrientation),
+}
+impl TileLayout {
+    /// The world position of a tile's center
+    pub fn to_world(&self, (x, y): (i32, i32), size: (f32, f32)) -> (f32, f32) {
+        self.to_world_fract((x as f32, y as f32), size)
+    }
+
+    /// The world position of a point between tiles, in fractional tile
+    /// coordinates. Staggered rows are shifted as the nearest row is.
+    pub fn to_world_fract(&self, (fx, fy): (f32, f32), (w, h): (f32, f32)) -> (f32, f32) {
+        match self {
+            Self::Orthogonal => (fx * w, fy * h),
+            Self::Isometric => ((fx - fy) * w / 2.0, (fx + fy) * h / 4.0),
+            Self::Staggered => (fx * w + Self::row_shift(fy) * w, fy * h / 4.0),
+            Self::Hexagonal(HexOrientation::Pointy) => ((fx + fy / 2.0) * w, fy * h * 0.75),
+            Self::Hexagonal(HexOrientation::Flat) => (fx * w * 0.75, (fy + fx / 2.0) * h),
+        }
+    }
+
+    /// The fractional tile coordinates of a world position, undoing `to_world_fract`
+    pub fn to_tile_fract(&self, (px, py): (f32, f32), (w, h): (f32, f32)) -> (f32, f32) {
+        match self {
+            Self::Orthogonal => (px / w, py / h),
+            Self::Isometric => {
+                let (a, b) = (px / (w / 2.0), py / (h / 4.0));
+                ((a + b) / 2.0, (b - a) / 2.0)
+            },
+            Self::Staggered => {
+                let fy = py / (h / 4.0);
+                (px / w - Self::row_shift(fy), fy)
+            },
+            Self::Hexagonal(HexOrientation::Pointy) => {
+                let fy = py / (h * 0.75);
+                (px / w - fy / 2.0, fy)
+            },
+            Self::Hexagonal(HexOrientation::Flat) => {
+                let fx = px / (w * 0.75);
+                (fx, py / h - fx / 2.0)
+            },
+        }
+    }
+
+    /// How many tiles a staggered row is shifted right by
+    fn row_shift(fy: f32) -> f32 { (fy.round() as i32).rem_euclid(2) as f32 / 2.0 }
+
+    /// The tile a world position is within
+    pub fn to_tile(&self, (px, py): (f32, f32), (w, h): (f32, f32)) -> (i32, i32) {
+        let round = |v: f32| (v + 0.5).floor() as i32;
+        match self {
+            Self::Orthogonal => (round(px / w), round(py / h)),
+            Self::Isometric => Self::diamond((px, py), (w, h)),
+            Self::Staggered => {
+                let (i, j) = Self::diamond((px, py), (w, h));
+                let y = i + j;
+                ((i - j - y.rem_euclid(2)).div_euclid(2), y)
+            },
+            Self::Hexagonal(HexOrientation::Pointy) => {
+                let y = py / (h * 0.75);
+                hex_round(px / w - y / 2.0, y)
+            },
+            Self::Hexagonal(HexOrientation::Flat) => {
+                let x = px / (w * 0.75);
+                hex_round(x, py / h - x / 2.0)
+            },
+        }
+    }
+
+    /// The isometric tile a world position is within
+    fn diamond((px, py): (f32, f32), (w, h): (f32, f32)) -> (i32, i32) {
+        let (a, b) = (px / (w / 2.0), py / (h / 4.0));
+        (((a + b) / 2.0 + 0.5).floor() as i32, ((b - a) / 2.0 + 0.5).floor() as i32)
+    }
+
+    /// The tiles sharing an edge with a tile
+    pub fn neighbours(&self, (x, y): (i32, i32)) -> Vec<(i32, i32)> {
+        match self {
+            Self::Orthogonal | Self::Isometric => vec![(x, y + 1), (x + 1, y), (x, y - 1), (x - 1, y)],
+            Self::Staggered => {
+                // Odd rows are shifted right, so their neighbours are too
+                let left = x - 1 + y.rem_euclid(2);
+                vec![(left + 1, y + 1), (left + 1, y - 1), (left, y - 1), (left, y + 1)]
+            },
+            Self::Hexagonal(_) => vec![(x + 1, y), (x, y + 1), (x - 1, y + 1), (x - 1, y), (x, y - 1), (x + 1, y - 1)],
+        }
+    }
+
+    /// The fewest steps between neighbours from one tile to another
+    pub fn distance(&self, a: (i32, i32), b: (i32, i32)) -> i32 {
+        match self {
+            Self::Orthogonal | Self::Isometric => (a.0 - b.0).abs() + (a.1 - b.1).abs(),
+            Self::Staggered => {
+                let (a, b) = (Self::unstagger(a), Self::unstagger(b));
+                (a.0 - b.0).abs() + (a.1 - b.1).abs()
+            },
+            Self::Hexagonal(_) => {
+                let (dx, dy) = (a.0 - b.0, a.1 - b.1);
+                (dx.abs() + dy.abs() + (dx + dy).abs()) / 2
+            },
+        }
+    }
+
+    /// The isometric position of a staggered tile
+    fn unstagger((x, y): (i32, i32)) -> (i32, i32) {
+        let across = 2 * x + y.rem_euclid(2);
+        ((y + across) / 2, (y - across) / 2)
+    }
+
+    /// The corners of a tile, around its center, anticlockwise
+    pub fn corners(&self, (w, h): (f32, f32)) -> Vec<(f32, f32)> {
+        let (hw, hh) = (w / 2.0, h / 2.0);
+        match self {
+            Self::Orthogonal => vec![(hw, hh), (-hw, hh), (-hw, -hh), (hw, -hh)],
+            Self::Isometric | Self::Staggered => vec![(hw, 0.0), (0.0, hh / 2.0), (-hw, 0.0), (0.0, -hh / 2.0)],
+            Self::Hexagonal(HexOrientation::Pointy) => vec![
+                (hw, hh / 2.0), (0.0, hh), (-hw, hh / 2.0), (-hw, -hh / 2.0), (0.0, -hh), (hw, -hh / 2.0),
+            ],
+            Self::Hexagonal(HexOrientation::Flat) => vec![
+                (hw, 0.0), (hw / 2.0, hh), (-hw / 2.0, hh), (-hw, 0.0), (-hw / 2.0, -hh), (hw / 2.0, -hh),
+            ],
+        }
+    }
+
+    /// Where a tile's sprite is drawn from, relative to its center.
+    ///
+    /// Sprites sit a quarter of a tile above their position, so diamonds
+    /// fill the bottom half of their sprite, and hexagons are pulled down to fill it.
+    pub fn sprite_offset(&self, (_, h): (f32, f32)) -> (f32, f32) {
+        match self {
+            Self::Orthogonal | Self::Isometric | Self::Staggered => (0.0, 0.0),
+            Self::Hexagonal(_) => (0.0, -h / 4.0),
+        }
+    }
+
+    /// How far in front of its layer a tile at a world height is drawn, so
+    /// that overlapping tiles are sorted back to front
+    pub fn depth_bias(&self, world_y: f32) -> f32 {
+        match self {
+            Self::Orthogonal => 0.0,
+            _ => -world_y * DEPTH_PER_UNIT,
+        }
+    }
+}
+
+/// Rounds a fractional axial hex position to the hex it's within
+fn hex_round(x: f32, y: f32) -> (i32, i32) {
+    let z = -x - y;
+    let (mut rx, mut ry, rz) = (x.round(), y.round(), z.round());
+    let (dx, dy, dz) = ((rx - x).abs(), (ry - y).abs(), (rz - z).abs());
+    // The three coordinates sum to zero, so the one rounded furthest is fixed from the others
+    if dx > dy && dx > dz {
+        rx = -ry - rz;
+    } else if dy > dz {
+        ry = -rx - rz;
+    }
+    (rx as i32, ry as i32)
+}
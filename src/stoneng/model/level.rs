@@ -0,0 +1,340 @@
+use std::collections::{BTreeMap, HashMap};
+use std::{fs, path::Path, sync::Arc};
+
+use serde::{Serialize, Deserialize};
+
+use crate::EngineError;
+use crate::model::{
+    layout::TileLayout,
+    spritesheet::SpriteSchema,
+    tilemap::{Tilemap, TileDef, LayerSettings},
+    tiled::{Properties, Spawn},
+};
+
+/// A sprite of the sheet, or one of its variants, by name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TileSprite {
+    pub sprite:     String,
+    #[serde(default)]
+    pub variant:    Option<String>,
+}
+
+/// A tile of a layer, as (position, sprite)
+pub type LayerTile = ((i32, i32), Arc<SpriteSchema>);
+
+/// The tiles of a layer, by the names of their sprites, as saved in a level
+/// or a streamed chunk.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaletteLayer {
+    pub name:       String,
+    /// The sprites the layer's tiles use, which the tiles refer to by index
+    pub palette:    Vec<TileSprite>,
+    /// Each tile of the layer, as (x, y, palette index)
+    pub tiles:      Vec<(i32, i32, u32)>,
+}
+impl PaletteLayer {
+    pub fn new(name: &str) -> Self {
+        Self { name: name.into(), palette: vec![], tiles: vec![] }
+    }
+
+    /// Adds a tile, adding its sprite to the palette if no other tile uses it.
+    pub fn add_tile(&mut self, pos: (i32, i32), sprite: &TileSprite) {
+        let index = match self.palette.iter().position(|used| used == sprite) {
+            Some(index) => index,
+            None => {
+                self.palette.push(sprite.clone());
+                self.palette.len() - 1
+            },
+        };
+        self.tiles.push((pos.0, pos.1, index as u32));
+    }
+
+    /// Finds the sprite of each tile in a tilemap's sheet.
+    pub fn resolve(&self, tilemap: &Tilemap) -> Result<Vec<LayerTile>, String> {
+        let palette = self.palette.iter()
+            .map(|tile| tilemap.resolve(&TileDef {
+                sprite: tile.sprite.clone(), variant: tile.variant.clone(), layer: self.name.clone(),
+            }))
+            .collect::<Result<Vec<_>, String>>()?;
+        self.tiles.iter()
+            .map(|(x, y, index)| match palette.get(*index as usize) {
+                Some(schema) => Ok(((*x, *y), schema.clone())),
+                None => Err(format!(
+                    "the tile at ({}, {}) uses palette entry {}, but there are only {}", x, y, index, palette.len()
+                )),
+            })
+            .collect()
+    }
+}
+
+/// A layer of a saved level.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LevelLayer {
+    pub settings:   LayerSettings,
+    pub tiles:      PaletteLayer,
+}
+
+/// A tilemap saved as a Rusty Object Notation level file.
+///
+/// Tiles are stored by the name of their sprite, so that a level still
+/// loads after its sheet's layout changes. Levels also hold the game's
+/// metadata and spawns, and the properties of tiles.
+///
+/// Each level records the version of the game it was saved by. When a sheet's
+/// sprites are renamed or removed, the game's version goes up and a step is
+/// added to its `Migrations`, to bring older levels up to date as they load.
+///
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use stoneng::model::{spritesheet::SpriteSheet, tilemap::*, level::*};
+/// # let img_data = include_bytes!("./level.rs");
+/// let sheet = Arc::new(SpriteSheet::new(r#"SpriteSheet(sheet_width: 100, tile_width: 10, sprites: {
+///     "grass": (root: 0, variants: { "dirt": (root: 1) }),
+///     "stone": (root: 2),
+/// })"#, img_data).unwrap());
+///
+/// let mut map = Tilemap::new(sheet.clone());
+/// map.populate_from_string("\
+/// ,.
+/// ..", &Legend::from([
+///     ('.', vec![TileDef::new("grass", "floor")]),
+///     (',', vec![TileDef::new("grass", "floor").with_variant("dirt")]),
+/// ])).unwrap();
+///
+/// let saved = Level::from_tilemap(&map, 1).unwrap().with_name("meadow").to_ron().unwrap();
+/// // The sprites have since been renamed
+/// let renamed = Arc::new(SpriteSheet::new(r#"SpriteSheet(sheet_width: 100, tile_width: 10, sprites: {
+///     "meadow-grass": (root: 0, variants: { "dirt": (root: 1) }),
+/// })"#, img_data).unwrap());
+/// let migrations = Migrations::new(2)
+///     .with_step(1, |level| { level.rename_sprite("grass", "meadow-grass"); Ok(()) });
+///
+/// let level = Level::from_ron(&saved, &migrations).unwrap();
+/// assert_eq!((level.name.as_str(), level.version), ("meadow", 2));
+/// let mut loaded = Tilemap::new(renamed);
+/// level.populate(&mut loaded).unwrap();
+/// assert_eq!(loaded.get_tile((0, 1), "floor").unwrap().root, 1);
+/// assert_eq!(loaded.get_tile((1, 1), "floor").unwrap().root, 0);
+///
+/// // Levels from newer versions of the game can't be loaded
+/// assert!(Level::from_ron(&saved, &Migrations::new(0)).is_err());
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Level {
+    /// The version of the game the level was saved by
+    pub version:            u32,
+    #[serde(default)]
+    pub name:               String,
+    /// The level's metadata, e.g. its author or music
+    #[serde(default)]
+    pub properties:         Properties,
+    /// The tilemap's scale
+    pub scale:              (f32, f32),
+    /// How the tilemap's tiles are arranged
+    #[serde(default)]
+    pub layout:             TileLayout,
+    pub layers:             Vec<LevelLayer>,
+    /// The properties given to tile positions, rather than to the sprites of their tiles
+    #[serde(default)]
+    pub tile_properties:    BTreeMap<(i32, i32), Properties>,
+    #[serde(default)]
+    pub spawns:             Vec<Spawn>,
+}
+impl Level {
+    /// Captures a tilemap's layers, tiles and tile properties, as saved by a
+    /// version of the game.
+    ///
+    /// Every tile must be a sprite of the tilemap's sheet, or one of its
+    /// variants, for it to be saved by name.
+    pub fn from_tilemap(tilemap: &Tilemap, version: u32) -> Result<Self, EngineError> {
+        let names = SpriteNames::of(tilemap)?;
+        let mut layers = vec![];
+        for layer in tilemap.layers() {
+            let mut tiles: Vec<((i32, i32), &Arc<SpriteSchema>)> = layer.tiles().collect();
+            // Saved a row at a time, for the same map to always give the same file
+            tiles.sort_unstable_by_key(|((x, y), _)| (*y, *x));
+
+            let mut saved = PaletteLayer::new(layer.name());
+            for (pos, schema) in tiles {
+                saved.add_tile(pos, names.name(schema, layer.name(), pos)?);
+            }
+            layers.push(LevelLayer { settings: layer.settings.clone(), tiles: saved });
+        }
+
+        let tile_properties = tilemap.tiles_with_properties()
+            .map(|(pos, properties)| (pos, properties.clone()))
+            .collect();
+        Ok(Self {
+            version, name: String::new(), properties: Properties::new(), scale: tilemap.scale,
+            layout: tilemap.layout, layers, tile_properties, spawns: vec![],
+        })
+    }
+
+    pub fn with_name(self, name: &str) -> Self { Self { name: name.into(), ..self } }
+    pub fn with_spawns(self, spawns: Vec<Spawn>) -> Self { Self { spawns, ..self } }
+
+    /// Parses a level, bringing it up to date with the game's migrations.
+    pub fn from_ron(src: &str, migrations: &Migrations) -> Result<Self, EngineError> {
+        let mut level: Level = ron::from_str(src).map_err(|e| EngineError::LevelError(e.to_string()))?;
+        migrations.migrate(&mut level)?;
+        Ok(level)
+    }
+
+    pub fn to_ron(&self) -> Result<String, EngineError> {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new())
+            .map_err(|e| EngineError::LevelError(e.to_string()))
+    }
+
+    /// Loads a level file, bringing it up to date with the game's migrations.
+    pub fn load(path: impl AsRef<Path>, migrations: &Migrations) -> Result<Self, EngineError> {
+        Self::from_ron(&fs::read_to_string(path)?, migrations)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+        Ok(fs::write(path, self.to_ron()?)?)
+    }
+
+    /// Replaces a tilemap's layers, tiles and tile properties with the level's,
+    /// using the tilemap's sheet.
+    ///
+    /// The tilemap takes the level's scale and layout, and each layer's settings.
+    /// Tiles are placed as they were saved, without being autotiled again.
+    /// Nothing is changed if any tile's sprite isn't in the sheet.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use stoneng::model::{spritesheet::SpriteSheet, tilemap::*, level::*, autotile::AutotileRules, tiled::Property};
+    /// # let img_data = include_bytes!("./level.rs");
+    /// let sheet = Arc::new(SpriteSheet::new(r#"SpriteSheet(sheet_width: 100, tile_width: 10, sprites: {
+    ///     "water": (root: 0, variants: { "pool": (root: 1) }),
+    ///     "stone": (root: 2),
+    /// })"#, img_data).unwrap());
+    /// let mut map = Tilemap::new(sheet.clone()).with_scale(2.0, 2.0);
+    /// map.add_layer("roof", LayerSettings::new(10.0).with_opacity(0.5).as_overhead(0.2));
+    /// map.set_tile((0, 0), "floor", sheet.sprites["water"].clone());
+    /// map.set_tile((3, -2), "roof", sheet.sprites["stone"].clone());
+    /// map.set_tile_properties((0, 0), [("depth".into(), Property::Float(2.5))].into());
+    /// let saved = Level::from_tilemap(&map, 1).unwrap().to_ron().unwrap();
+    ///
+    /// // Loaded over a map with tiles of its own, which would autotile lone water into a pool
+    /// let mut loaded = Tilemap::new(sheet.clone());
+    /// loaded.set_autotile_rules(&AutotileRules::new(r#"AutotileRules(rules: [
+    ///     (sprite: "water", layer: "floor", style: FourBit, variants: { 0: "pool" }),
+    /// ])"#).unwrap()).unwrap();
+    /// loaded.set_tile((5, 5), "walls", sheet.sprites["stone"].clone());
+    /// Level::from_ron(&saved, &Migrations::new(1)).unwrap().populate(&mut loaded).unwrap();
+    ///
+    /// assert_eq!(Level::from_tilemap(&loaded, 1).unwrap(), Level::from_tilemap(&map, 1).unwrap());
+    /// assert_eq!(loaded.get_tile((0, 0), "floor").unwrap().root, 0);
+    /// assert!(loaded.get_tile((5, 5), "walls").is_none());
+    /// assert_eq!(loaded.layer("roof").unwrap().settings, map.layer("roof").unwrap().settings);
+    /// assert_eq!(loaded.tile_property((0, 0), "depth"), Some(&Property::Float(2.5)));
+    /// ```
+    pub fn populate(&self, tilemap: &mut Tilemap) -> Result<(), EngineError> {
+        let resolved = self.layers.iter()
+            .map(|layer| layer.tiles.resolve(tilemap)
+                .map_err(|reason| EngineError::LevelError(format!("layer \"{}\": {}", layer.tiles.name, reason))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        tilemap.clear();
+        tilemap.scale = self.scale;
+        tilemap.layout = self.layout;
+        for (layer, tiles) in self.layers.iter().zip(resolved) {
+            tilemap.add_layer(&layer.tiles.name, layer.settings.clone());
+            for (pos, schema) in tiles {
+                tilemap.place_tile(pos, &layer.tiles.name, schema);
+            }
+        }
+        for (pos, properties) in self.tile_properties.iter() {
+            tilemap.set_tile_properties(*pos, properties.clone());
+        }
+        Ok(())
+    }
+
+    /// Renames a sprite used by the level's tiles, for migrations.
+    pub fn rename_sprite(&mut self, from: &str, to: &str) {
+        self.layers.iter_mut()
+            .flat_map(|layer| layer.tiles.palette.iter_mut())
+            .filter(|tile| tile.sprite == from)
+            .for_each(|tile| tile.sprite = to.into());
+    }
+
+    /// Renames a variant of a sprite used by the level's tiles, or with None,
+    /// uses the sprite itself, for migrations.
+    pub fn rename_variant(&mut self, sprite: &str, from: &str, to: Option<&str>) {
+        self.layers.iter_mut()
+            .flat_map(|layer| layer.tiles.palette.iter_mut())
+            .filter(|tile| tile.sprite == sprite && tile.variant.as_deref() == Some(from))
+            .for_each(|tile| tile.variant = to.map(String::from));
+    }
+}
+
+/// The name of each sprite of a tilemap's sheet, and of each variant, by schema
+pub(crate) struct SpriteNames(HashMap<*const SpriteSchema, TileSprite>);
+impl SpriteNames {
+    pub fn of(tilemap: &Tilemap) -> Result<Self, EngineError> {
+        let sheet = tilemap.spritesheet.as_ref()
+            .ok_or_else(|| EngineError::LevelError("the tilemap has no spritesheet".into()))?;
+        let mut names = HashMap::new();
+        for (name, sprite) in sheet.sprites.iter() {
+            names.insert(Arc::as_ptr(sprite), TileSprite { sprite: name.clone(), variant: None });
+            for (variant, schema) in sprite.variants.iter() {
+                names.insert(Arc::as_ptr(schema), TileSprite { sprite: name.clone(), variant: Some(variant.clone()) });
+            }
+        }
+        Ok(Self(names))
+    }
+
+    /// The name of the sprite of a tile, at a position of the world on a layer.
+    pub fn name(&self, schema: &Arc<SpriteSchema>, layer: &str, pos: (i32, i32)) -> Result<&TileSprite, EngineError> {
+        self.0.get(&Arc::as_ptr(schema)).ok_or_else(|| EngineError::LevelError(format!(
+            "the tile at ({}, {}) of layer \"{}\" isn't a sprite of the sheet", pos.0, pos.1, layer
+        )))
+    }
+}
+
+/// A change to bring a level saved by one version of the game up to the next
+pub type MigrationStep = Box<dyn Fn(&mut Level) -> Result<(), String> + Send + Sync>;
+
+/// The steps to bring levels saved by older versions of a game up to date.
+///
+/// Versions without a step of their own need no changes to load as the next.
+pub struct Migrations {
+    /// The version levels are brought up to, which the game saves levels as
+    pub current:    u32,
+    /// The step from each version to the next
+    steps:          BTreeMap<u32, MigrationStep>,
+}
+impl Migrations {
+    pub fn new(current: u32) -> Self { Self { current, steps: BTreeMap::new() } }
+
+    /// Adds the step to bring a level of version `from` up to `from + 1`.
+    pub fn with_step<F>(mut self, from: u32, step: F) -> Self
+            where F: Fn(&mut Level) -> Result<(), String> + Send + Sync + 'static {
+        self.steps.insert(from, Box::new(step));
+        self
+    }
+
+    /// Brings a level up to the current version, a step at a time.
+    pub fn migrate(&self, level: &mut Level) -> Result<(), EngineError> {
+        if level.version > self.current {
+            return Err(EngineError::LevelError(format!(
+                "the level is from version {}, newer than this game's version {}", level.version, self.current
+            )));
+        }
+        while level.version < self.current {
+            if let Some(step) = self.steps.get(&level.version) {
+                step(level).map_err(|reason| EngineError::LevelError(
+                    format!("migrating from version {}: {}", level.version, reason)
+                ))?;
+            }
+            level.version += 1;
+        }
+        Ok(())
+    }
+}
+impl Default for Migrations {
+    fn default() -> Self { Self::new(0) }
+}
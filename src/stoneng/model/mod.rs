@@ -1,7 +1,18 @@
 pub mod spritesheet;
 pub mod tilemap;
+pub mod layout;
+pub mod tiled;
+pub mod level;
+pub mod autotile;
+pub mod layer;
+pub mod spatial;
+pub mod shape;
+pub mod pathfinding;
+pub mod fov;
+pub mod streaming;
 
 #[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
 pub struct Rect {
     top_left: (f32, f32),
     bottom_right: (f32, f32),
@@ -11,6 +22,28 @@ impl Rect {
         Self { top_left, bottom_right } 
     }
 
+    /// Builds a rect from its center point and half of its width and height.
+    pub fn from_center(center: (f32, f32), half_extents: (f32, f32)) -> Self {
+        let ((x, y), (w, h)) = (center, half_extents);
+        Self::new((x-w, y+h), (x+w, y-h))
+    }
+
+    pub fn left(&self) -> f32 { self.top_left.0 }
+    pub fn right(&self) -> f32 { self.bottom_right.0 }
+    pub fn top(&self) -> f32 { self.top_left.1 }
+    pub fn bottom(&self) -> f32 { self.bottom_right.1 }
+    pub fn width(&self) -> f32 { self.right() - self.left() }
+    pub fn height(&self) -> f32 { self.top() - self.bottom() }
+    pub fn center(&self) -> (f32, f32) {
+        ((self.left() + self.right()) / 2.0, (self.top() + self.bottom()) / 2.0)
+    }
+
+    /// Moves the rect by the given offset.
+    pub fn translate(&mut self, dx: f32, dy: f32) {
+        self.top_left = (self.top_left.0 + dx, self.top_left.1 + dy);
+        self.bottom_right = (self.bottom_right.0 + dx, self.bottom_right.1 + dy);
+    }
+
     pub fn intersects(&self, other: &Rect) -> bool {
         // Decompose the rectangles into components
         let (a_left, a_top) = self.top_left;
@@ -25,7 +58,21 @@ impl Rect {
         return x_overlapping && y_overlapping;
     }
 
+    /// The smallest rect containing both rects.
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect::new(
+            (self.left().min(other.left()), self.top().max(other.top())),
+            (self.right().max(other.right()), self.bottom().min(other.bottom())),
+        )
+    }
+
+    /// Returns the overlapping area of two rects, if they intersect.
     pub fn intersection(&self, other: &Rect) -> Option<Rect> {
-        todo!()
+        if !self.intersects(other) { return None; }
+
+        Some(Rect::new(
+            (self.left().max(other.left()), self.top().min(other.top())),
+            (self.right().min(other.right()), self.bottom().max(other.bottom())),
+        ))
     }
 }
@@ -0,0 +1,437 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::model::{layout::TileLayout, tilemap::Tilemap, tiled::Property};
+
+/// Which steps a path may take between tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Movement {
+    /// Only across the four edges of a tile
+    FourWay,
+    /// Diagonally as well, each diagonal step costing √2 times an edge step
+    EightWay(CornerCutting),
+}
+
+/// Whether a diagonal step may pass the corner of a blocked tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CornerCutting {
+    /// Diagonal steps may squeeze between two blocked tiles
+    Always,
+    /// Diagonal steps may pass one blocked tile, but not squeeze between two
+    OneSide,
+    /// Diagonal steps are only taken when both tiles beside them are open
+    Never,
+}
+
+/// Which tiles can be walked through, and how costly each is to enter, for
+/// finding paths across the tilemap.
+///
+/// Solid tiles are walls, and tiles under static colliders are obstacles,
+/// both kept up to date by the NavGridSys. Every other tile is open, costing
+/// 1 to enter unless its own or its sprite's `move_cost` property, its layer's
+/// cost or a cost set for the tile itself says otherwise. Costs are never below 1.
+///
+/// The map has no edge, so searches give up after visiting `max_nodes` tiles.
+///
+/// Steps follow the tilemap's layout. Staggered and hexagonal tiles only
+/// step to the tiles sharing an edge with them, whatever the movement, and
+/// their paths aren't smoothed.
+///
+/// # Example
+/// ```
+/// # use stoneng::model::pathfinding::*;
+/// let mut grid = NavGrid::default();
+/// // A wall from (2, -2) to (2, 2)
+/// for y in -2..=2 { grid.set_wall((2, y), true); }
+///
+/// let path = grid.find_path((0, 0), (4, 0)).unwrap();
+/// assert_eq!((path[0], *path.last().unwrap()), ((0, 0), (4, 0)));
+/// assert!(path.iter().all(|pos| grid.is_walkable(*pos)));
+///
+/// // Only the corners of the path remain once smoothed
+/// let smoothed = grid.smooth(&path);
+/// assert!(smoothed.len() < path.len());
+///
+/// // Mud is avoided when there's a cheaper way around
+/// let mut grid = NavGrid::new(Movement::FourWay);
+/// grid.set_cost((1, 0), Some(10.0));
+/// assert!(!grid.find_path((0, 0), (2, 0)).unwrap().contains(&(1, 0)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct NavGrid {
+    pub movement:   Movement,
+    /// The layout of the tilemap the grid was built from
+    layout:         TileLayout,
+    /// The most tiles a search will visit before deciding there's no path
+    pub max_nodes:  usize,
+    /// The cost of entering a tile of each tilemap layer, the costliest
+    /// layer of a tile being used
+    layer_costs:    HashMap<String, f32>,
+    walls:          HashSet<(i32, i32)>,
+    obstacles:      HashSet<(i32, i32)>,
+    /// The cost of each tile costing more than 1
+    costs:          HashMap<(i32, i32), f32>,
+    /// Tiles given a cost of their own, rather than by their layers
+    fixed_costs:    HashMap<(i32, i32), f32>,
+    revision:       u64,
+    /// Whether the walls and costs have been built from the tilemap
+    built:          bool,
+}
+impl Default for NavGrid {
+    fn default() -> Self { Self::new(Movement::EightWay(CornerCutting::Never)) }
+}
+impl NavGrid {
+    pub fn new(movement: Movement) -> Self {
+        Self {
+            movement, layout: TileLayout::Orthogonal, max_nodes: 4096, layer_costs: HashMap::new(),
+            walls: HashSet::new(), obstacles: HashSet::new(),
+            costs: HashMap::new(), fixed_costs: HashMap::new(), revision: 0, built: false,
+        }
+    }
+
+    pub fn with_max_nodes(self, max_nodes: usize) -> Self { Self { max_nodes, ..self } }
+    /// Lays the grid out like a tilemap, as it is once built from one.
+    pub fn with_layout(self, layout: TileLayout) -> Self { Self { layout, ..self } }
+
+    /// Makes the tiles of a tilemap layer cost more (or less) to walk through.
+    pub fn with_layer_cost(mut self, layer: &str, cost: f32) -> Self {
+        self.layer_costs.insert(layer.into(), cost.max(1.0));
+        self
+    }
+
+    /// Changes whenever the grid does, for paths to tell when they're out of date.
+    pub fn revision(&self) -> u64 { self.revision }
+
+    /// Whether the grid has been built from a tilemap yet
+    pub fn is_built(&self) -> bool { self.built }
+
+    pub fn layout(&self) -> TileLayout { self.layout }
+
+    pub fn is_walkable(&self, pos: (i32, i32)) -> bool {
+        !self.walls.contains(&pos) && !self.obstacles.contains(&pos)
+    }
+
+    /// The cost of entering a tile
+    pub fn cost(&self, pos: (i32, i32)) -> f32 { self.costs.get(&pos).copied().unwrap_or(1.0) }
+
+    /// Sets whether a tile is a wall.
+    pub fn set_wall(&mut self, pos: (i32, i32), wall: bool) {
+        let changed = if wall { self.walls.insert(pos) } else { self.walls.remove(&pos) };
+        if changed { self.revision += 1; }
+    }
+
+    /// Gives a tile its own cost, or with None, returns it to its layers' cost.
+    pub fn set_cost(&mut self, pos: (i32, i32), cost: Option<f32>) {
+        match cost {
+            Some(cost) => { self.fixed_costs.insert(pos, cost.max(1.0)); },
+            None => { self.fixed_costs.remove(&pos); },
+        }
+        self.costs.remove(&pos);
+        if let Some(cost) = self.fixed_costs.get(&pos).filter(|cost| **cost > 1.0) {
+            self.costs.insert(pos, *cost);
+        }
+        self.revision += 1;
+    }
+
+    /// Replaces the tiles blocked by static colliders.
+    pub fn set_obstacles(&mut self, obstacles: HashSet<(i32, i32)>) {
+        if obstacles != self.obstacles {
+            self.obstacles = obstacles;
+            self.revision += 1;
+        }
+    }
+
+    /// Updates a tile from the tilemap, as a wall if it's solid and costing
+    /// its own `move_cost`, or else as much as its costliest tile, by its
+    /// sprite's `move_cost` or its layer's cost.
+    pub fn update_tile(&mut self, tilemap: &Tilemap, pos: (i32, i32)) {
+        self.set_wall(pos, tilemap.is_solid(pos));
+        if self.fixed_costs.contains_key(&pos) { return; }
+
+        let own_cost = tilemap.tile_properties(pos)
+            .and_then(|properties| properties.get("move_cost"))
+            .and_then(Property::as_float);
+        let cost = match own_cost {
+            Some(cost) => (cost as f32).max(1.0),
+            None => tilemap.layers()
+                .filter_map(|layer| {
+                    let tile = layer.get(pos)?;
+                    tile.property("move_cost").and_then(Property::as_float).map(|cost| cost as f32)
+                        .or_else(|| self.layer_costs.get(layer.name()).copied())
+                })
+                .fold(1.0f32, f32::max),
+        };
+        let previous = if cost > 1.0 { self.costs.insert(pos, cost) } else { self.costs.remove(&pos) };
+        if previous.unwrap_or(1.0) != cost { self.revision += 1; }
+    }
+
+    /// Rebuilds the walls and costs from every tile of the tilemap.
+    pub fn rebuild(&mut self, tilemap: &Tilemap) {
+        self.walls.clear();
+        self.costs.retain(|pos, _| self.fixed_costs.contains_key(pos));
+        self.revision += 1;
+        self.built = true;
+        self.layout = tilemap.layout;
+
+        for pos in tilemap.positions() {
+            self.update_tile(tilemap, pos);
+        }
+    }
+
+    /// The tiles a step may move to from a tile, with the distance of each step
+    pub(crate) fn steps(&self, (x, y): (i32, i32)) -> Vec<((i32, i32), f32)> {
+        let mut steps: Vec<((i32, i32), f32)> = self.layout.neighbours((x, y)).into_iter()
+            .filter(|pos| self.is_walkable(*pos))
+            .map(|pos| (pos, 1.0))
+            .collect();
+
+        if let (Movement::EightWay(cutting), true) = (self.movement, self.is_square()) {
+            for (dx, dy) in [(1, 1), (1, -1), (-1, -1), (-1, 1)] {
+                let open_sides = [(x + dx, y), (x, y + dy)].iter().filter(|pos| self.is_walkable(**pos)).count();
+                let allowed = match cutting {
+                    CornerCutting::Always => true,
+                    CornerCutting::OneSide => open_sides >= 1,
+                    CornerCutting::Never => open_sides == 2,
+                };
+                let pos = (x + dx, y + dy);
+                if allowed && self.is_walkable(pos) {
+                    steps.push((pos, std::f32::consts::SQRT_2));
+                }
+            }
+        }
+        steps
+    }
+
+    /// Whether the tiles are laid out as a square grid, however it's turned
+    fn is_square(&self) -> bool { matches!(self.layout, TileLayout::Orthogonal | TileLayout::Isometric) }
+
+    /// The least a path between two tiles could cost
+    fn estimate(&self, a: (i32, i32), b: (i32, i32)) -> f32 {
+        if !self.is_square() { return self.layout.distance(a, b) as f32; }
+
+        let (dx, dy) = ((a.0 - b.0).abs() as f32, (a.1 - b.1).abs() as f32);
+        match self.movement {
+            Movement::FourWay => dx + dy,
+            // Octile distance, moving diagonally as far as possible
+            Movement::EightWay(_) => dx.max(dy) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dy),
+        }
+    }
+
+    /// Finds the cheapest path between two tiles with A*, including both ends.
+    ///
+    /// The start may be blocked, e.g. by the collider of whatever is
+    /// pathing, but the goal must be walkable.
+    pub fn find_path(&self, start: (i32, i32), goal: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+        if !self.is_walkable(goal) { return None; }
+
+        let mut open = BinaryHeap::from([Node { pos: start, cost: 0.0, estimate: self.estimate(start, goal) }]);
+        let mut costs = HashMap::from([(start, 0.0f32)]);
+        let mut came_from: HashMap<(i32, i32), (i32, i32)> = HashMap::new();
+        let mut visited = 0;
+
+        while let Some(Node { pos, cost, .. }) = open.pop() {
+            if pos == goal {
+                let mut path = vec![goal];
+                while let Some(previous) = came_from.get(path.last().unwrap()) {
+                    path.push(*previous);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            // A cheaper way here was already explored
+            if cost > costs[&pos] { continue; }
+
+            visited += 1;
+            if visited > self.max_nodes { return None; }
+
+            for (next, distance) in self.steps(pos) {
+                let next_cost = cost + distance * self.cost(next);
+                if costs.get(&next).is_some_and(|known| *known <= next_cost) { continue; }
+                costs.insert(next, next_cost);
+                came_from.insert(next, pos);
+                open.push(Node { pos: next, cost: next_cost, estimate: next_cost + self.estimate(next, goal) });
+            }
+        }
+        None
+    }
+
+    /// Removes the waypoints of a path which can be skipped by walking
+    /// straight between the others.
+    ///
+    /// A straight line is only taken if every tile it touches is walkable,
+    /// and costs no more than the tiles of the path it replaces.
+    pub fn smooth(&self, path: &[(i32, i32)]) -> Vec<(i32, i32)> {
+        // Straight lines are only traced across square grids
+        if !self.is_square() { return path.to_vec(); }
+
+        let mut smoothed: Vec<(i32, i32)> = path.iter().take(1).copied().collect();
+        let mut anchor = 0;
+        while anchor + 1 < path.len() {
+            // The furthest waypoint which can be seen from the anchor
+            let mut furthest = anchor + 1;
+            for end in (anchor + 2..path.len()).rev() {
+                let highest = path[anchor..=end].iter().map(|pos| self.cost(*pos)).fold(1.0, f32::max);
+                // The anchor is on the path already, even if it's the blocked start
+                let clear = line_tiles(path[anchor], path[end]).skip(1)
+                    .all(|pos| self.is_walkable(pos) && self.cost(pos) <= highest);
+                if clear {
+                    furthest = end;
+                    break;
+                }
+            }
+            smoothed.push(path[furthest]);
+            anchor = furthest;
+        }
+        smoothed
+    }
+}
+
+/// The way to the nearest of a set of goal tiles, from every tile around them.
+///
+/// Built once for any number of agents, by searching out from the goals
+/// across a NavGrid, after which each agent only needs to look up the tile
+/// it's on. Goal tiles lead to themselves, and tiles the search didn't reach
+/// have no way to a goal.
+///
+/// # Example
+/// ```
+/// # use stoneng::model::pathfinding::*;
+/// let mut grid = NavGrid::new(Movement::FourWay);
+/// for y in -2..=2 { grid.set_wall((2, y), true); }
+///
+/// let mut field = FlowField::default();
+/// field.set_goals([(4, 0)]);
+/// field.build(&grid);
+/// // Around the wall, each step a tile nearer
+/// assert_eq!(field.distance((0, 0)), Some(10.0));
+/// let next = field.next_tile((0, 0)).unwrap();
+/// assert_eq!(field.distance(next), Some(9.0));
+/// assert_eq!(field.distance((2, 0)), None);
+/// assert_eq!(field.next_tile((4, 0)), Some((4, 0)));
+/// assert!(!field.is_outdated(&grid));
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlowField {
+    /// The most tiles the field reaches out to, nearest the goals first
+    pub max_nodes:  usize,
+    goals:          Vec<(i32, i32)>,
+    /// The cost of the way from each reached tile to its nearest goal
+    distances:      HashMap<(i32, i32), f32>,
+    /// The tile to step to from each reached tile
+    next:           HashMap<(i32, i32), (i32, i32)>,
+    /// The grid revision the field was built from, None when the goals have changed since
+    built_for:      Option<u64>,
+}
+impl Default for FlowField {
+    fn default() -> Self { Self::new(16384) }
+}
+impl FlowField {
+    pub fn new(max_nodes: usize) -> Self {
+        Self { max_nodes, goals: vec![], distances: HashMap::new(), next: HashMap::new(), built_for: None }
+    }
+
+    pub fn goals(&self) -> &[(i32, i32)] { &self.goals }
+
+    /// Sets the tiles to lead to, the field needing to be built again if they've changed.
+    pub fn set_goals(&mut self, goals: impl IntoIterator<Item = (i32, i32)>) {
+        let goals: Vec<(i32, i32)> = goals.into_iter().collect();
+        if goals != self.goals {
+            self.goals = goals;
+            self.built_for = None;
+        }
+    }
+
+    /// Whether the goals or the grid have changed since the field was built
+    pub fn is_outdated(&self, grid: &NavGrid) -> bool { self.built_for != Some(grid.revision()) }
+
+    /// Builds the field across a grid with Dijkstra's algorithm.
+    pub fn build(&mut self, grid: &NavGrid) {
+        self.distances.clear();
+        self.next.clear();
+        self.built_for = Some(grid.revision());
+
+        let mut open = BinaryHeap::new();
+        for goal in self.goals.iter() {
+            self.distances.insert(*goal, 0.0);
+            self.next.insert(*goal, *goal);
+            open.push(Node { pos: *goal, cost: 0.0, estimate: 0.0 });
+        }
+
+        let mut visited = 0;
+        while let Some(Node { pos, cost, .. }) = open.pop() {
+            // A cheaper way here was already explored
+            if cost > self.distances[&pos] { continue; }
+
+            visited += 1;
+            if visited > self.max_nodes { break; }
+
+            // Searching backwards, so each step costs as much as the tile it leaves
+            for (from, distance) in grid.steps(pos) {
+                let from_cost = cost + distance * grid.cost(pos);
+                if self.distances.get(&from).is_some_and(|known| *known <= from_cost) { continue; }
+                self.distances.insert(from, from_cost);
+                self.next.insert(from, pos);
+                open.push(Node { pos: from, cost: from_cost, estimate: from_cost });
+            }
+        }
+    }
+
+    /// The cost of the way from a tile to its nearest goal, if it has one
+    pub fn distance(&self, pos: (i32, i32)) -> Option<f32> { self.distances.get(&pos).copied() }
+
+    /// The tile to step to from a tile, on the way to its nearest goal
+    pub fn next_tile(&self, pos: (i32, i32)) -> Option<(i32, i32)> { self.next.get(&pos).copied() }
+}
+
+/// Every tile a line between two tile centers touches, both tiles beside
+/// the line being included where it passes exactly through a corner.
+fn line_tiles(from: (i32, i32), to: (i32, i32)) -> impl Iterator<Item = (i32, i32)> {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let (nx, ny) = (dx.abs(), dy.abs());
+    let (step_x, step_y) = (dx.signum(), dy.signum());
+    let (mut x, mut y) = from;
+    let (mut ix, mut iy) = (0, 0);
+
+    let mut tiles = vec![from];
+    while ix < nx || iy < ny {
+        // Compare where the next vertical and horizontal grid lines are crossed
+        let crossing = (1 + 2 * ix) * ny - (1 + 2 * iy) * nx;
+        match crossing.cmp(&0) {
+            Ordering::Equal => {
+                tiles.push((x + step_x, y));
+                tiles.push((x, y + step_y));
+                x += step_x;
+                y += step_y;
+                ix += 1;
+                iy += 1;
+            },
+            Ordering::Less => { x += step_x; ix += 1; },
+            Ordering::Greater => { y += step_y; iy += 1; },
+        }
+        tiles.push((x, y));
+    }
+    tiles.into_iter()
+}
+
+/// A tile waiting to be explored by A*, ordered so the heap pops the lowest estimate
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    pos:        (i32, i32),
+    cost:       f32,
+    /// The cost so far, plus the least the rest of the path could cost
+    estimate:   f32,
+}
+impl PartialEq for Node {
+    fn eq(&self, other: &Self) -> bool { self.cmp(other) == Ordering::Equal }
+}
+impl Eq for Node {}
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Ties go to the node furthest along, which is nearer the goal
+        other.estimate.total_cmp(&self.estimate)
+            .then_with(|| self.cost.total_cmp(&other.cost))
+    }
+}
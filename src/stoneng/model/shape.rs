@@ -0,0 +1,451 @@
+use glm::{Vec2, vec2};
+
+use crate::EngineError;
+use crate::model::Rect;
+
+/// The geometry of a collider, relative to its entity's position.
+///
+/// Shapes are rotated about the entity's position by its Rotation component.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColliderShape {
+    /// A box, given by half of its width and height.
+    Aabb { half_extents: (f32, f32) },
+    Circle { radius: f32 },
+    /// A vertical line segment of length `2 * half_height`, rounded by `radius`.
+    Capsule { half_height: f32, radius: f32 },
+    /// A convex polygon, made by `ColliderShape::polygon`.
+    Polygon(ConvexPoints),
+}
+
+/// The points of a convex polygon, wound anticlockwise, which can only be
+/// made once they're checked.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvexPoints(Vec<(f32, f32)>);
+impl ConvexPoints {
+    pub fn points(&self) -> &[(f32, f32)] { &self.0 }
+}
+
+impl ColliderShape {
+    /// A convex polygon, from its points wound either way.
+    ///
+    /// The points must have an area and turn the same way at every corner,
+    /// once around.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::model::shape::ColliderShape;
+    /// // Wound clockwise
+    /// let wedge = ColliderShape::polygon(vec![(0.0, 0.0), (0.0, 10.0), (10.0, 0.0)]).unwrap();
+    /// match wedge {
+    ///     ColliderShape::Polygon(wedge) => assert_eq!(wedge.points(), [(10.0, 0.0), (0.0, 10.0), (0.0, 0.0)]),
+    ///     _ => unreachable!(),
+    /// }
+    ///
+    /// assert!(ColliderShape::polygon(vec![]).is_err());
+    /// assert!(ColliderShape::polygon(vec![(0.0, 0.0), (5.0, 5.0), (10.0, 10.0)]).is_err());
+    /// // An arrowhead, with a notch in its back
+    /// assert!(ColliderShape::polygon(vec![(0.0, 0.0), (10.0, 5.0), (0.0, 10.0), (3.0, 5.0)]).is_err());
+    /// // A star, turning the same way at every point, but twice around
+    /// let star = (0..5).map(|i| (i as f32 * 4.0 * std::f32::consts::PI / 5.0).sin_cos()).collect();
+    /// assert!(ColliderShape::polygon(star).is_err());
+    /// ```
+    pub fn polygon(points: Vec<(f32, f32)>) -> Result<Self, EngineError> {
+        let error = |reason: &str| Err(EngineError::ShapeError(format!("polygon {:?}: {}", points, reason)));
+        if points.len() < 3 { return error("needs at least 3 points"); }
+        if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) { return error("has a point at infinity"); }
+
+        let n = points.len();
+        let edge = |i: usize| {
+            let (a, b) = (points[i % n], points[(i + 1) % n]);
+            vec2(b.0 - a.0, b.1 - a.1)
+        };
+        // The way the polygon turns, and how far it turns in all
+        let mut winding: f32 = 0.0;
+        let mut turned = 0.0;
+        for i in 0..n {
+            let (a, b) = (edge(i), edge(i + 1));
+            if a.norm_squared() == 0.0 { return error("has the same point twice in a row"); }
+            let cross = a.x * b.y - a.y * b.x;
+            // Nearly straight corners could turn either way
+            if cross.abs() > 1e-6 * a.norm() * b.norm() {
+                if cross * winding < 0.0 { return error("isn't convex"); }
+                winding = cross.signum();
+            }
+            turned += cross.atan2(a.dot(&b));
+        }
+        if winding == 0.0 { return error("has no area"); }
+        if (turned.abs() - std::f32::consts::TAU).abs() > 0.01 { return error("isn't convex, crossing itself"); }
+
+        let mut points = points;
+        if winding < 0.0 { points.reverse(); }
+        Ok(Self::Polygon(ConvexPoints(points)))
+    }
+
+    /// How far the shape reaches from its origin at most, whatever its rotation
+    pub fn reach(&self) -> f32 {
+        match self {
+            Self::Aabb { half_extents: (w, h) } => w.hypot(*h),
+            Self::Circle { radius } => *radius,
+            Self::Capsule { half_height, radius } => half_height + radius,
+            Self::Polygon(points) => points.0.iter().map(|(x, y)| x.hypot(*y)).fold(0.0, f32::max),
+        }
+    }
+
+    /// Places the shape in the world.
+    ///
+    /// The local offset is applied before rotating by `rotation` degrees
+    /// (counter-clockwise) about `position`.
+    pub fn to_world(&self, offset: (f32, f32), position: (f32, f32), rotation: f32) -> Convex {
+        let (points, radius) = match self {
+            Self::Aabb { half_extents: (w, h) } =>
+                (vec![vec2(-w, -h), vec2(*w, -h), vec2(*w, *h), vec2(-w, *h)], 0.0),
+            Self::Circle { radius } => (vec![vec2(0.0, 0.0)], *radius),
+            Self::Capsule { half_height, radius } =>
+                (vec![vec2(0.0, -half_height), vec2(0.0, *half_height)], *radius),
+            Self::Polygon(points) =>
+                (points.0.iter().map(|p| vec2(p.0, p.1)).collect(), 0.0),
+        };
+
+        let rotation = rotation.to_radians();
+        let (sin, cos) = rotation.sin_cos();
+        let origin = vec2(position.0, position.1);
+        let offset = vec2(offset.0, offset.1);
+        let points = points.into_iter()
+            .map(|p| {
+                let p = p + offset;
+                origin + vec2(p.x * cos - p.y * sin, p.x * sin + p.y * cos)
+            })
+            .collect();
+
+        Convex { points, radius }
+    }
+}
+
+/// A convex shape in world space, being the hull of its points expanded by a radius.
+///
+/// A single point is a circle, two points make a capsule and three or more
+/// form a (possibly rounded) polygon.
+#[derive(Debug, Clone)]
+pub struct Convex {
+    pub points: Vec<Vec2>,
+    pub radius: f32,
+}
+
+impl Convex {
+    /// The axis-aligned bounding box of the shape
+    pub fn bounds(&self) -> Rect {
+        let first = match self.points.first() {
+            Some(first) => *first,
+            None => return Rect::new((0.0, 0.0), (0.0, 0.0)),
+        };
+        let (min, max) = self.points.iter()
+            .fold((first, first), |(min, max), p| (glm::min2(&min, p), glm::max2(&max, p)));
+        let r = self.radius;
+        Rect::new((min.x - r, max.y + r), (max.x + r, min.y - r))
+    }
+
+    pub fn translate(&mut self, offset: Vec2) {
+        for p in self.points.iter_mut() { *p += offset; }
+    }
+
+    /// The average of the shape's points
+    pub fn centroid(&self) -> Vec2 {
+        self.points.iter().fold(vec2(0.0, 0.0), |sum, p| sum + p) / self.points.len().max(1) as f32
+    }
+
+    /// The edges of the hull, a single point being a zero length edge
+    fn edges(&self) -> impl Iterator<Item = (Vec2, Vec2)> + '_ {
+        let n = self.points.len();
+        // A segment only has one edge, rather than one in each direction
+        let count = if n == 2 { 1 } else { n };
+        (0..count).map(move |i| (self.points[i], self.points[(i + 1) % n]))
+    }
+
+    /// Projects the hull (without radius) onto an axis
+    fn project(&self, axis: &Vec2) -> (f32, f32) {
+        self.points.iter()
+            .map(|p| p.dot(axis))
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), d| (min.min(d), max.max(d)))
+    }
+
+    /// Finds the contact normal (pointing from self into other) and penetration depth
+    /// of two overlapping shapes.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::model::shape::ColliderShape;
+    /// let circle = ColliderShape::Circle { radius: 10.0 }.to_world((0.0, 0.0), (0.0, 0.0), 0.0);
+    /// let square = ColliderShape::Aabb { half_extents: (5.0, 5.0) };
+    ///
+    /// // A box resting 2 units into the circle, to its right
+    /// let (normal, depth) = circle.contact(&square.to_world((0.0, 0.0), (13.0, 0.0), 0.0)).unwrap();
+    /// assert_eq!(normal, (1.0, 0.0));
+    /// assert!((depth - 2.0).abs() < 1e-5);
+    ///
+    /// // Rotated 45 degrees the box's corner reaches further out
+    /// assert!(circle.contact(&square.to_world((0.0, 0.0), (16.0, 0.0), 0.0)).is_none());
+    /// assert!(circle.contact(&square.to_world((0.0, 0.0), (16.0, 0.0), 45.0)).is_some());
+    /// ```
+    pub fn contact(&self, other: &Convex) -> Option<((f32, f32), f32)> {
+        let radii = self.radius + other.radius;
+        let to_other = other.centroid() - self.centroid();
+
+        // Separating axis test of the hulls, tracking the axis of least overlap
+        let mut least: Option<(Vec2, f32)> = None;
+        for axis in self.axes().chain(other.axes()).chain(std::iter::once(to_other)) {
+            if axis.norm_squared() < f32::EPSILON { continue; }
+            let axis = axis.normalize();
+
+            let (a_min, a_max) = self.project(&axis);
+            let (b_min, b_max) = other.project(&axis);
+            let overlap = a_max.min(b_max) - a_min.max(b_min);
+
+            // The hulls are apart, but their radii may still reach each other
+            if overlap < 0.0 {
+                let axis = if axis.dot(&to_other) < 0.0 { -axis } else { axis };
+                return self.rounded_contact(other, radii, axis);
+            }
+            if least.is_none_or(|(_, least)| overlap < least) {
+                least = Some((axis, overlap));
+            }
+        }
+
+        // The hulls overlap (or are coincident points), push apart along the shallowest axis
+        let (axis, overlap) = least.unwrap_or((vec2(0.0, 1.0), 0.0));
+        // Hulls which only touch aren't overlapping, unless rounded
+        if overlap + radii <= 0.0 { return None; }
+        let normal = if axis.dot(&to_other) < 0.0 { -axis } else { axis };
+        Some(((normal.x, normal.y), overlap + radii))
+    }
+
+    /// The candidate separating axes of the hull's edges
+    fn axes(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let along = if self.points.len() == 2 { Some(self.points[1] - self.points[0]) } else { None };
+        self.edges()
+            .map(|(a, b)| vec2(a.y - b.y, b.x - a.x))
+            .chain(along)
+    }
+
+    /// Casts a ray from `origin` along the unit vector `dir`, up to `max_dist`.
+    ///
+    /// Returns the distance along the ray and the surface normal where it
+    /// first enters the shape. A ray starting inside the shape hits immediately,
+    /// with a normal facing back along the ray.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::model::shape::ColliderShape;
+    /// # use nalgebra_glm::vec2;
+    /// let circle = ColliderShape::Circle { radius: 5.0 }.to_world((0.0, 0.0), (20.0, 0.0), 0.0);
+    ///
+    /// let (dist, normal) = circle.raycast(vec2(0.0, 0.0), vec2(1.0, 0.0), 100.0).unwrap();
+    /// assert_eq!((dist, normal), (15.0, vec2(-1.0, 0.0)));
+    /// assert!(circle.raycast(vec2(0.0, 0.0), vec2(1.0, 0.0), 10.0).is_none());
+    /// assert!(circle.raycast(vec2(0.0, 0.0), vec2(0.0, 1.0), 100.0).is_none());
+    /// ```
+    pub fn raycast(&self, origin: Vec2, dir: Vec2, max_dist: f32) -> Option<(f32, Vec2)> {
+        if self.contains(&origin) { return Some((0.0, -dir)); }
+
+        let mut nearest: Option<(f32, Vec2)> = None;
+        let mut consider = |dist: f32, normal: Vec2| {
+            if dist >= 0.0 && dist <= max_dist && nearest.is_none_or(|(d, _)| dist < d) {
+                nearest = Some((dist, normal));
+            }
+        };
+
+        // The rounded corners
+        if self.radius > 0.0 {
+            for p in self.points.iter() {
+                if let Some(dist) = ray_circle(&origin, &dir, p, self.radius) {
+                    consider(dist, (origin + dir * dist - p).normalize());
+                }
+            }
+        }
+
+        // The edges, pushed out by the radius
+        for (a, b, normal) in self.outward_edges() {
+            // Only faces turned towards the ray can be entered
+            if normal.dot(&dir) >= 0.0 { continue; }
+            let offset = normal * self.radius;
+            if let Some(dist) = ray_segment(&origin, &dir, &(a + offset), &(b + offset)) {
+                consider(dist, normal);
+            }
+        }
+
+        nearest
+    }
+
+    /// Whether a point lies within the shape, including its radius
+    pub fn contains(&self, point: &Vec2) -> bool {
+        if self.points.len() >= 3 
+                && self.outward_edges().all(|(a, _, normal)| normal.dot(&(point - a)) <= 0.0) {
+            return true;
+        }
+        self.edges()
+            .any(|(a, b)| (closest_on_segment(point, &a, &b) - point).norm() <= self.radius)
+    }
+
+    /// The edges of the hull with their outward facing unit normals.
+    ///
+    /// A segment's edge faces both ways, so is given once for each side.
+    fn outward_edges(&self) -> impl Iterator<Item = (Vec2, Vec2, Vec2)> + '_ {
+        let centroid = self.centroid();
+        let segment = self.points.len() == 2;
+        self.edges()
+            .filter(|(a, b)| a != b)
+            .flat_map(move |(a, b)| {
+                let normal = vec2(a.y - b.y, b.x - a.x).normalize();
+                let normal = if normal.dot(&(centroid - a)) > 0.0 { -normal } else { normal };
+                let back = if segment { Some((a, b, -normal)) } else { None };
+                std::iter::once((a, b, normal)).chain(back)
+            })
+    }
+
+    /// Whether the hulls (without radius) of two shapes overlap or touch
+    fn hulls_overlap(&self, other: &Convex) -> bool {
+        let to_other = other.centroid() - self.centroid();
+        self.axes().chain(other.axes()).chain(std::iter::once(to_other))
+            .filter(|axis| axis.norm_squared() >= f32::EPSILON)
+            .all(|axis| {
+                let axis = axis.normalize();
+                let (a_min, a_max) = self.project(&axis);
+                let (b_min, b_max) = other.project(&axis);
+                a_max.min(b_max) - a_min.max(b_min) >= 0.0
+            })
+    }
+
+    /// The gap between two shapes, with the unit vector pointing from self to other.
+    ///
+    /// Returns None when the hulls overlap, as the gap is then not defined.
+    pub fn distance(&self, other: &Convex) -> Option<(f32, Vec2)> {
+        if self.hulls_overlap(other) { return None; }
+        let (dist, offset) = self.closest(other);
+        let dir = if dist > 0.0 { offset / dist } else { other.centroid() - self.centroid() };
+        Some((dist - self.radius - other.radius, dir))
+    }
+
+    /// Sweeps the shape along the unit vector `dir`, up to `max_dist`, until it
+    /// touches `other`.
+    ///
+    /// Returns the distance travelled and the normal of other's surface at the
+    /// point of contact, facing back towards self. Shapes already touching
+    /// only hit if self is moving into other, not along or away from it.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::model::shape::ColliderShape;
+    /// # use nalgebra_glm::vec2;
+    /// let ball = ColliderShape::Circle { radius: 2.0 }.to_world((0.0, 0.0), (0.0, 0.0), 0.0);
+    /// let wall = ColliderShape::Aabb { half_extents: (1.0, 50.0) }.to_world((0.0, 0.0), (20.0, 0.0), 0.0);
+    ///
+    /// let (dist, normal) = ball.sweep(vec2(1.0, 0.0), 100.0, &wall).unwrap();
+    /// assert!((dist - 17.0).abs() < 0.01);
+    /// assert_eq!(normal, vec2(-1.0, 0.0));
+    /// assert!(ball.sweep(vec2(-1.0, 0.0), 100.0, &wall).is_none());
+    ///
+    /// // Grazing a long floor at a shallow angle
+    /// let ball = ColliderShape::Circle { radius: 1.0 }.to_world((0.0, 0.0), (-400.0, 30.0), 0.0);
+    /// let floor = ColliderShape::Aabb { half_extents: (500.0, 5.0) }.to_world((0.0, 0.0), (0.0, 0.0), 0.0);
+    /// let dir = vec2(1.0, -0.05).normalize();
+    ///
+    /// let (dist, normal) = ball.sweep(dir, 1000.0, &floor).unwrap();
+    /// assert!((dist - 24.0 / -dir.y).abs() < 0.1);
+    /// assert_eq!(normal, vec2(0.0, 1.0));
+    /// assert!(ball.sweep(dir, 450.0, &floor).is_none());
+    /// ```
+    pub fn sweep(&self, dir: Vec2, max_dist: f32, other: &Convex) -> Option<(f32, Vec2)> {
+        const MAX_STEPS: usize = 64;
+        const TOLERANCE: f32 = 1e-3;
+
+        // Conservative advancement: the gap between two translating convex
+        // shapes is a convex function of the distance travelled, so following
+        // its slope never steps past the point of contact
+        let mut shape = self.clone();
+        let mut travelled = 0.0;
+        let mut normal = -dir;
+        for _ in 0..MAX_STEPS {
+            let (gap, to_other) = match shape.distance(other) {
+                Some(gap) => gap,
+                // Starting overlapped, push back out the way we came unless already leaving
+                None => return shape.contact(other)
+                    .map(|(n, _)| vec2(n.0, n.1))
+                    .filter(|n| n.dot(&dir) > 0.0)
+                    .map(|n| (travelled, -n)),
+            };
+            normal = -to_other;
+
+            // Moving away from, or along, other, even if touching it
+            let closing = to_other.dot(&dir);
+            if closing <= 0.0 { return None; }
+            if gap <= TOLERANCE { return Some((travelled, normal)); }
+            let step = gap / closing;
+            if travelled + step > max_dist { return None; }
+            travelled += step;
+            shape.translate(dir * step);
+        }
+        // Not yet within tolerance, but every step taken was safe
+        Some((travelled, normal))
+    }
+
+    /// The shortest offset between the hulls of two separate shapes, and its length
+    fn closest(&self, other: &Convex) -> (f32, Vec2) {
+        // The closest points of two separate convex hulls are always between a
+        // vertex of one and an edge of the other
+        let mut closest = (f32::INFINITY, vec2(0.0, 0.0));
+        for (from, onto, sign) in [(self, other, 1.0), (other, self, -1.0)] {
+            for p in from.points.iter() {
+                for (a, b) in onto.edges() {
+                    let offset = closest_on_segment(p, &a, &b) - p;
+                    let dist = offset.norm();
+                    if dist < closest.0 { closest = (dist, offset * sign); }
+                }
+            }
+        }
+        closest
+    }
+
+    /// The contact between two shapes with separate hulls, using the closest points.
+    ///
+    /// The separating axis is used as the normal should the hulls be touching.
+    fn rounded_contact(&self, other: &Convex, radii: f32, separating_axis: Vec2) 
+            -> Option<((f32, f32), f32)> {
+        if radii <= 0.0 { return None; }
+
+        let (dist, offset) = self.closest(other);
+        if dist >= radii { return None; }
+        let normal = if dist > 0.0 { offset / dist } else { separating_axis };
+        Some(((normal.x, normal.y), radii - dist))
+    }
+}
+
+/// Finds the closest point on the segment a-b to p
+fn closest_on_segment(p: &Vec2, a: &Vec2, b: &Vec2) -> Vec2 {
+    let ab = b - a;
+    let len_sq = ab.norm_squared();
+    if len_sq <= 0.0 { return *a; }
+    let t = ((p - a).dot(&ab) / len_sq).clamp(0.0, 1.0);
+    a + ab * t
+}
+
+/// The distance along a ray at which it enters a circle
+fn ray_circle(origin: &Vec2, dir: &Vec2, center: &Vec2, radius: f32) -> Option<f32> {
+    let to_origin = origin - center;
+    let b = to_origin.dot(dir);
+    let c = to_origin.norm_squared() - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 { return None; }
+    Some(-b - discriminant.sqrt())
+}
+
+/// The distance along a ray at which it crosses the segment a-b
+fn ray_segment(origin: &Vec2, dir: &Vec2, a: &Vec2, b: &Vec2) -> Option<f32> {
+    let cross = |u: &Vec2, v: &Vec2| u.x * v.y - u.y * v.x;
+    let edge = b - a;
+    let denom = cross(dir, &edge);
+    if denom.abs() < f32::EPSILON { return None; }
+
+    let to_a = a - origin;
+    let dist = cross(&to_a, &edge) / denom;
+    let along = cross(&to_a, dir) / denom;
+    if (0.0..=1.0).contains(&along) { Some(dist) } else { None }
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::model::Rect;
+
+/// The default width and height of a SpatialHash cell, in world units.
+pub const DEFAULT_CELL_SIZE: f32 = 128.0;
+
+/// The most cells an item may cover before it's kept out of the grid.
+pub const MAX_ITEM_CELLS: i64 = 1024;
+
+/// The inclusive range of cells covered by an item's bounds.
+#[derive(Debug, Clone, Copy)]
+struct CellRange {
+    min: (i32, i32),
+    max: (i32, i32),
+}
+
+/// A uniform grid broadphase, used to quickly find items that may overlap.
+///
+/// Items are inserted by their bounding rect and identified by their insertion
+/// order. The grid is intended to be cleared and rebuilt each update.
+///
+/// Items covering more than `MAX_ITEM_CELLS` cells, or without finite bounds,
+/// are kept in a list of their own and paired with every other item.
+///
+/// # Example
+/// ```
+/// # use stoneng::model::{Rect, spatial::SpatialHash};
+/// let mut grid = SpatialHash::new(10.0);
+/// // Two overlapping boxes that span several of the same cells
+/// grid.insert(&Rect::from_center((0.0, 0.0), (8.0, 8.0)));
+/// grid.insert(&Rect::from_center((4.0, 4.0), (8.0, 8.0)));
+/// // And one far away
+/// grid.insert(&Rect::from_center((500.0, 0.0), (1.0, 1.0)));
+///
+/// let mut pairs = vec![];
+/// grid.for_each_pair(|a, b| pairs.push((a, b)));
+/// // Each unordered pair is only reported once
+/// assert_eq!(pairs, vec![(0, 1)]);
+///
+/// // A boundless item is paired with everything
+/// grid.insert(&Rect::from_center((0.0, 0.0), (f32::INFINITY, f32::INFINITY)));
+/// let mut pairs = vec![];
+/// grid.for_each_pair(|a, b| pairs.push((a, b)));
+/// pairs.sort();
+/// assert_eq!(pairs, vec![(0, 1), (0, 3), (1, 3), (2, 3)]);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SpatialHash {
+    cell_size:  f32,
+    cells:      HashMap<(i32, i32), Vec<usize>>,
+    /// The cells of each item, or None for those too big for the grid
+    ranges:     Vec<Option<CellRange>>,
+    oversized:  Vec<usize>,
+}
+
+impl SpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        Self { cell_size, cells: HashMap::new(), ranges: Vec::new(), oversized: Vec::new() }
+    }
+
+    /// Removes all items from the grid.
+    ///
+    /// Cells that were used are kept allocated, as the next set of items will
+    /// likely occupy them again.
+    pub fn clear(&mut self) {
+        self.cells.retain(|_, items| {
+            let used = !items.is_empty();
+            items.clear();
+            used
+        });
+        self.ranges.clear();
+        self.oversized.clear();
+    }
+
+    /// Adds an item to every cell its bounds overlap, returning the item's id.
+    pub fn insert(&mut self, bounds: &Rect) -> usize {
+        let id = self.ranges.len();
+        let range = self.cell_range(bounds);
+        match range {
+            Some(range) => for x in range.min.0..=range.max.0 {
+                for y in range.min.1..=range.max.1 {
+                    self.cells.entry((x, y)).or_default().push(id);
+                }
+            },
+            None => self.oversized.push(id),
+        }
+        self.ranges.push(range);
+        id
+    }
+
+    /// Calls `f` once for each unordered pair of items sharing a cell, with the
+    /// lower id first.
+    ///
+    /// These are only candidates, the caller must still test the bounds.
+    pub fn for_each_pair<F: FnMut(usize, usize)>(&self, mut f: F) {
+        for (cell, items) in self.cells.iter() {
+            for (i, &a) in items.iter().enumerate() {
+                for &b in items[i+1..].iter() {
+                    // Pairs sharing many cells are only reported from the
+                    // first cell of their overlap
+                    let (ra, rb) = (self.ranges[a].unwrap(), self.ranges[b].unwrap());
+                    let owner = (ra.min.0.max(rb.min.0), ra.min.1.max(rb.min.1));
+                    if *cell == owner {
+                        f(a, b);
+                    }
+                }
+            }
+        }
+        for &a in self.oversized.iter() {
+            // Pairs of oversized items are only reported by the lower id
+            let others = (0..self.ranges.len()).filter(|&b| b != a && (self.ranges[b].is_some() || b > a));
+            for b in others {
+                f(a.min(b), a.max(b));
+            }
+        }
+    }
+
+    /// The cells an item's bounds cover, unless there are too many
+    fn cell_range(&self, bounds: &Rect) -> Option<CellRange> {
+        let edges = [bounds.left(), bounds.bottom(), bounds.right(), bounds.top()];
+        if edges.iter().any(|v| !v.is_finite()) { return None; }
+
+        let cell = |v: f32| i32::try_from((v / self.cell_size).floor() as i64).ok();
+        let (min, max) = ((cell(edges[0])?, cell(edges[1])?), (cell(edges[2])?, cell(edges[3])?));
+        let span = |lo: i32, hi: i32| (hi as i64 - lo as i64 + 1).max(0);
+        if span(min.0, max.0).saturating_mul(span(min.1, max.1)) > MAX_ITEM_CELLS { return None; }
+        Some(CellRange { min, max })
+    }
+}
+
+impl Default for SpatialHash {
+    fn default() -> Self { Self::new(DEFAULT_CELL_SIZE) }
+}
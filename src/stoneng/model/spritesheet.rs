@@ -1,5 +1,6 @@
 #![allow(dead_code)]
 use crate::EngineError;
+use crate::model::tiled::{Property, Properties};
 
 use std::{
     path,
@@ -56,6 +57,16 @@ impl SpriteSheet {
     ///         ),
     ///         "water": (
     ///             root: 3,
+    ///             // Typed properties of the sprite's tiles, for the game and
+    ///             // the engine's systems to look up
+    ///             properties: {
+    ///                 "move_cost":        Float(4.0),
+    ///                 "footstep_sound":   String("splash"),
+    ///             },
+    ///             // Variants have their sprite's properties, unless they set their own
+    ///             variants: {
+    ///                 "deep": (root: 6, properties: { "solid": Bool(true) }),
+    ///             },
     ///             // Note that animations is a map
     ///             animations: {
     ///                 // Each animation is the struct AnimationSchema
@@ -66,6 +77,8 @@ impl SpriteSheet {
     ///                     frames:     3,
     ///                     fps:        5,
     ///                     mode:       Loop,
+    ///                     // As tiles, each starts at a different frame
+    ///                     random_phase: true,
     ///                 ),
     ///             }
     ///         )
@@ -78,10 +91,21 @@ impl SpriteSheet {
     /// assert_eq!(sheet.sheet_width, 256);
     /// assert_eq!(sheet.sprites["arch"].root, 9);
     /// assert!(sheet.sprites["water"].animations.contains_key("idle"));
+    /// let deep = &sheet.sprites["water"].variants["deep"];
+    /// assert_eq!(deep.property("solid").and_then(|solid| solid.as_bool()), Some(true));
+    /// assert_eq!(deep.property("footstep_sound").and_then(|sound| sound.as_str()), Some("splash"));
     /// ```
     pub fn new(layout: &'static str, img_ref: &'static [u8]) -> Result<Self, EngineError> {
         let mut sheet = ron::from_str::<SpriteSheet>(layout)?;
         sheet.img_ref = Some(img_ref);
+        for sprite in sheet.sprites.values_mut() {
+            let sprite = Arc::make_mut(sprite);
+            for variant in sprite.variants.values_mut() {
+                for (name, property) in sprite.properties.iter() {
+                    Arc::make_mut(variant).properties.entry(name.clone()).or_insert_with(|| property.clone());
+                }
+            }
+        }
 
         if sheet.sheet_width / sheet.tile_width > 255 {
             return Err(EngineError::SheetSizeError("Maximum tiles per row is 255".into()));
@@ -121,6 +145,11 @@ pub struct AnimationSchema {
     /// How many seconds between each frame 
     #[serde(default)]
     pub frame_time:     f32,
+
+    /// For tiles, whether each tile starts the animation at a random point,
+    /// rather than all being in step
+    #[serde(default)]
+    pub random_phase:   bool,
 }
 impl PartialEq for AnimationSchema {
     fn eq(&self, other: &Self) -> bool {
@@ -153,4 +182,13 @@ pub struct SpriteSchema {
     /// A map of animation schema that the sprite can use.
     #[serde(default)]
     pub animations:      HashMap<String, Arc<AnimationSchema>>,
+
+    /// Typed properties of the sprite, such as `solid`, `opaque` or
+    /// `move_cost` which the engine's systems use for tiles, or anything else
+    /// the game needs to know of them, e.g. a `footstep_sound`.
+    #[serde(default)]
+    pub properties:     Properties,
+}
+impl SpriteSchema {
+    pub fn property(&self, name: &str) -> Option<&Property> { self.properties.get(name) }
 }
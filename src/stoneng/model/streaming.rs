@@ -0,0 +1,253 @@
+use std::collections::HashSet;
+use std::{fs, path::PathBuf, sync::Arc};
+
+use rand::{SeedableRng, rngs::StdRng};
+use serde::{Serialize, Deserialize};
+
+use crate::EngineError;
+use crate::procgen::TerrainGenerator;
+use crate::model::{
+    level::{TileSprite, PaletteLayer, SpriteNames},
+    spritesheet::SpriteSchema,
+    tilemap::{Tilemap, TileDef, Legend, CHUNK_SIZE},
+    tiled::Spawn,
+};
+
+/// A tile to place, as (layer, position in the world, sprite)
+pub type PlacedTile<'a> = (&'a str, (i32, i32), Arc<SpriteSchema>);
+
+/// The tiles and spawns of one chunk of a streamed world, as a `ChunkProvider`
+/// gives and keeps them.
+///
+/// Tiles are stored by the name of their sprite, as in a saved `Level`, at
+/// their position within the chunk, from (0, 0) to (CHUNK_SIZE - 1, CHUNK_SIZE - 1).
+/// Spawns are in tile coordinates of the world.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ChunkData {
+    pub layers: Vec<PaletteLayer>,
+    #[serde(default)]
+    pub spawns: Vec<Spawn>,
+}
+impl ChunkData {
+    /// Places a tile, at a position within the chunk.
+    pub fn add_tile(&mut self, pos: (i32, i32), def: &TileDef) {
+        let layer = match self.layers.iter().position(|layer| layer.name == def.layer) {
+            Some(index) => &mut self.layers[index],
+            None => {
+                self.layers.push(PaletteLayer::new(&def.layer));
+                self.layers.last_mut().unwrap()
+            },
+        };
+        layer.add_tile(pos, &TileSprite { sprite: def.sprite.clone(), variant: def.variant.clone() });
+    }
+
+    /// Captures the tiles of a chunk of a tilemap, along with the spawns of
+    /// the entities within it.
+    pub fn from_tilemap(tilemap: &Tilemap, coord: (i32, i32), spawns: Vec<Spawn>) -> Result<Self, EngineError> {
+        let names = SpriteNames::of(tilemap)?;
+        let mut data = Self { layers: vec![], spawns };
+        for layer in tilemap.layers() {
+            let chunk = match layer.chunk(coord) { Some(chunk) => chunk, None => continue };
+            let mut saved = PaletteLayer::new(layer.name());
+            for ((x, y), schema) in chunk.tiles() {
+                let world = (coord.0 * CHUNK_SIZE + x, coord.1 * CHUNK_SIZE + y);
+                saved.add_tile((x, y), names.name(schema, layer.name(), world)?);
+            }
+            data.layers.push(saved);
+        }
+        Ok(data)
+    }
+
+    /// Finds the sprite of each tile in a tilemap's sheet, for `Tilemap::load_chunk`.
+    pub fn resolve(&self, tilemap: &Tilemap, coord: (i32, i32)) -> Result<Vec<PlacedTile<'_>>, EngineError> {
+        let origin = (coord.0 * CHUNK_SIZE, coord.1 * CHUNK_SIZE);
+        let mut tiles = vec![];
+        for layer in self.layers.iter() {
+            let error = |reason: String| EngineError::LevelError(
+                format!("chunk ({}, {}), layer \"{}\": {}", coord.0, coord.1, layer.name, reason)
+            );
+            for ((x, y), schema) in layer.resolve(tilemap).map_err(error)? {
+                if !(0..CHUNK_SIZE).contains(&x) || !(0..CHUNK_SIZE).contains(&y) {
+                    return Err(error(format!("the tile at ({}, {}) is outside the chunk", x, y)));
+                }
+                tiles.push((&layer.name[..], (origin.0 + x, origin.1 + y), schema));
+            }
+        }
+        Ok(tiles)
+    }
+}
+
+/// Where the chunks of a streamed world come from, and are kept once
+/// they're unloaded.
+///
+/// Providers are run on a thread of their own by the ChunkStreamSys, so
+/// they may take their time without holding up the game.
+pub trait ChunkProvider: Send {
+    /// Generates or loads a chunk, or with None, leaves it empty.
+    fn load(&mut self, coord: (i32, i32)) -> Result<Option<ChunkData>, EngineError>;
+
+    /// Keeps a chunk which has been unloaded, with any changes made to it,
+    /// for it to be loaded again as it was. Providers which only generate
+    /// chunks needn't keep anything.
+    fn save(&mut self, _coord: (i32, i32), _chunk: &ChunkData) -> Result<(), EngineError> { Ok(()) }
+}
+
+/// Builds a chunk from its coordinate, with a random number generator seeded
+/// by the chunk and the world's seed.
+pub type ChunkGenerateFn = Box<dyn Fn((i32, i32), &mut StdRng) -> ChunkData + Send>;
+
+/// Generates every chunk of an endless world, the same each time for the same seed.
+///
+/// # Example
+/// ```
+/// # use stoneng::procgen::TerrainGenerator;
+/// # use stoneng::model::{tilemap::*, streaming::*};
+/// let legend = Legend::from([
+///     ('~', vec![TileDef::new("water", "floor")]),
+///     ('.', vec![TileDef::new("grass", "floor")]),
+/// ]);
+/// let terrain = TerrainGenerator::default().with_bands(vec![(0.4, '~'), (1.0, '.')]);
+/// let mut world = GeneratedChunks::from_terrain(terrain, 42, legend);
+///
+/// let chunk = world.load((-3, 7)).unwrap().unwrap();
+/// let tiles: usize = chunk.layers.iter().map(|layer| layer.tiles.len()).sum();
+/// assert_eq!(tiles, (CHUNK_SIZE * CHUNK_SIZE) as usize);
+/// assert_eq!(world.load((-3, 7)).unwrap(), Some(chunk));
+/// ```
+pub struct GeneratedChunks {
+    pub seed:   u64,
+    generate:   ChunkGenerateFn,
+}
+impl GeneratedChunks {
+    pub fn new<F>(seed: u64, generate: F) -> Self
+            where F: Fn((i32, i32), &mut StdRng) -> ChunkData + Send + 'static {
+        Self { seed, generate: Box::new(generate) }
+    }
+
+    /// Generates chunks from a terrain's bands, placing each cell's tiles
+    /// through a legend. Cells missing from the legend are left empty.
+    pub fn from_terrain(terrain: TerrainGenerator, seed: u64, legend: Legend) -> Self {
+        Self::new(seed, move |coord, _| {
+            let mut chunk = ChunkData::default();
+            for x in 0..CHUNK_SIZE {
+                for y in 0..CHUNK_SIZE {
+                    let cell = terrain.cell((coord.0 * CHUNK_SIZE + x, coord.1 * CHUNK_SIZE + y), seed);
+                    for def in legend.get(&cell).into_iter().flatten() {
+                        chunk.add_tile((x, y), def);
+                    }
+                }
+            }
+            chunk
+        })
+    }
+
+    /// The seed of a chunk's random number generator
+    fn chunk_seed(&self, (x, y): (i32, i32)) -> u64 {
+        self.seed ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+    }
+}
+impl ChunkProvider for GeneratedChunks {
+    fn load(&mut self, coord: (i32, i32)) -> Result<Option<ChunkData>, EngineError> {
+        let mut rng = StdRng::seed_from_u64(self.chunk_seed(coord));
+        Ok(Some((self.generate)(coord, &mut rng)))
+    }
+}
+
+/// Keeps chunks as Rusty Object Notation files in a directory, one per chunk.
+///
+/// Chunks without a file yet are loaded from the fallback provider, such
+/// as a `GeneratedChunks`, so that a generated world keeps any changes made
+/// to it once they've been saved.
+pub struct DiskChunks {
+    pub dir:    PathBuf,
+    fallback:   Option<Box<dyn ChunkProvider>>,
+}
+impl DiskChunks {
+    pub fn new(dir: impl Into<PathBuf>) -> Self { Self { dir: dir.into(), fallback: None } }
+
+    pub fn with_fallback(self, fallback: impl ChunkProvider + 'static) -> Self {
+        Self { fallback: Some(Box::new(fallback)), ..self }
+    }
+
+    /// The file a chunk is kept in
+    pub fn path(&self, (x, y): (i32, i32)) -> PathBuf { self.dir.join(format!("chunk_{}_{}.ron", x, y)) }
+}
+impl ChunkProvider for DiskChunks {
+    fn load(&mut self, coord: (i32, i32)) -> Result<Option<ChunkData>, EngineError> {
+        let path = self.path(coord);
+        if !path.exists() {
+            return match self.fallback.as_mut() {
+                Some(fallback) => fallback.load(coord),
+                None => Ok(None),
+            };
+        }
+        let chunk = ron::from_str(&fs::read_to_string(&path)?)
+            .map_err(|e| EngineError::LevelError(format!("{}: {}", path.display(), e)))?;
+        Ok(Some(chunk))
+    }
+
+    fn save(&mut self, coord: (i32, i32), chunk: &ChunkData) -> Result<(), EngineError> {
+        fs::create_dir_all(&self.dir)?;
+        let src = ron::ser::to_string_pretty(chunk, ron::ser::PrettyConfig::new())
+            .map_err(|e| EngineError::LevelError(e.to_string()))?;
+        Ok(fs::write(self.path(coord), src)?)
+    }
+}
+
+/// Which chunks of a streamed world are loaded, and how far around the view
+/// they're loaded to.
+///
+/// The streaming is done by the ChunkStreamSys, which this is a World
+/// resource of. Radii are in chunks, from the chunk at the center of the view.
+#[derive(Debug)]
+pub struct ChunkStreaming {
+    /// How near chunks must be to be loaded
+    pub load_radius:    f32,
+    /// How far loaded chunks may be before they're unloaded, a little beyond
+    /// the load radius so that chunks on its edge don't load and unload again
+    /// as the view moves back and forth
+    pub unload_radius:  f32,
+    /// The most chunks placed, and the most unloaded, each update, spreading
+    /// the work over several updates when the view moves quickly
+    pub max_per_update: usize,
+    /// Whether unloaded chunks are given back to the provider to keep
+    pub save_unloaded:  bool,
+    pub(crate) loaded:  HashSet<(i32, i32)>,
+    /// The saves asked of the provider which it hasn't finished
+    pub(crate) saving:  usize,
+    pub(crate) save_requested: bool,
+    pub(crate) errors:  Vec<((i32, i32), EngineError)>,
+}
+impl Default for ChunkStreaming {
+    fn default() -> Self {
+        Self {
+            load_radius: 3.0, unload_radius: 4.5, max_per_update: 2, save_unloaded: true,
+            loaded: HashSet::new(), saving: 0, save_requested: false, errors: vec![],
+        }
+    }
+}
+impl ChunkStreaming {
+    pub fn with_radius(self, load_radius: f32, unload_radius: f32) -> Self {
+        Self { load_radius, unload_radius, ..self }
+    }
+    pub fn with_max_per_update(self, max_per_update: usize) -> Self { Self { max_per_update, ..self } }
+    pub fn without_saving(self) -> Self { Self { save_unloaded: false, ..self } }
+
+    pub fn is_loaded(&self, coord: (i32, i32)) -> bool { self.loaded.contains(&coord) }
+
+    /// The chunks placed into the tilemap
+    pub fn loaded(&self) -> impl Iterator<Item = (i32, i32)> + '_ { self.loaded.iter().copied() }
+
+    /// Has every loaded chunk saved on the next update, as when quitting.
+    pub fn save_loaded(&mut self) { self.save_requested = true; }
+
+    /// Whether the provider is still saving any chunks, which should be
+    /// waited for before quitting
+    pub fn is_saving(&self) -> bool { self.save_requested || self.saving > 0 }
+
+    /// Takes the chunks which couldn't be loaded or saved, and why.
+    ///
+    /// Chunks which couldn't be loaded are left empty, and not saved over,
+    /// until they go out of range and come back into it.
+    pub fn take_errors(&mut self) -> Vec<((i32, i32), EngineError)> { std::mem::take(&mut self.errors) }
+}
@@ -0,0 +1,977 @@
+use std::collections::{HashMap, hash_map::Entry};
+use std::{fs, path::Path, str::FromStr, sync::Arc};
+
+use serde::{Serialize, Deserialize};
+use serde_json::Value;
+use roxmltree::Node;
+
+use crate::EngineError;
+use crate::model::{
+    Rect,
+    spritesheet::{SpriteSheet, SpriteSchema, AnimationSchema, AnimMode},
+    tilemap::{Tilemap, TileDef, LayerSettings},
+};
+use crate::ecs::component::Color;
+
+/// The high bits of a gid, which Tiled uses to flip and rotate tiles
+const FLIP_FLAGS: u32 = 0xF000_0000;
+
+/// A custom property set on a map, layer, tile or object in Tiled.
+///
+/// Colors and files are kept as their text, and object references as the object's id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+}
+impl Property {
+    pub fn as_bool(&self) -> Option<bool> { if let Self::Bool(v) = self { Some(*v) } else { None } }
+    pub fn as_int(&self) -> Option<i64> { if let Self::Int(v) = self { Some(*v) } else { None } }
+    /// The property as a float, converting ints
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Self::Float(v) => Some(*v),
+            Self::Int(v) => Some(*v as f64),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> Option<&str> { if let Self::String(v) = self { Some(v) } else { None } }
+
+    /// Parses a property from its Tiled type name and textual value
+    fn parse(ty: &str, value: &str) -> Result<Self, String> {
+        let invalid = || format!("\"{}\" is not a valid {}", value, ty);
+        Ok(match ty {
+            "" | "string" | "color" | "file" => Self::String(value.into()),
+            "bool"   => Self::Bool(value.parse().map_err(|_| invalid())?),
+            "int" | "object" => Self::Int(value.parse().map_err(|_| invalid())?),
+            "float"  => Self::Float(value.parse().map_err(|_| invalid())?),
+            _ => return Err(format!("{} properties aren't supported", ty)),
+        })
+    }
+
+    fn from_json(ty: &str, value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(text) => Self::parse(ty, text),
+            Value::Bool(v) if ty == "bool" => Ok(Self::Bool(*v)),
+            Value::Number(v) if ty == "float" => v.as_f64().map(Self::Float).ok_or_else(|| format!("{} is not a float", v)),
+            Value::Number(v) => Self::parse(ty, &v.to_string()),
+            _ => Err(format!("{} is not a valid {}", value, ty)),
+        }
+    }
+}
+
+/// The custom properties of something in a Tiled map, by name.
+pub type Properties = HashMap<String, Property>;
+
+/// A prefab the game should spawn, from a Tiled object or a saved `Level`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Spawn {
+    /// The object's name, which may be empty
+    pub name:       String,
+    /// The object's class, or its name if it has no class
+    pub prefab:     String,
+    /// The center of the object, in tile coordinates
+    pub position:   (f32, f32),
+    pub properties: Properties,
+}
+impl Spawn {
+    /// The world position of the spawn, on the tilemap it was loaded into,
+    /// in the tilemap's layout.
+    ///
+    /// # Example
+    /// ```
+    /// # use stoneng::model::{tilemap::Tilemap, layout::TileLayout, tiled::*};
+    /// let map = Tilemap::default().with_layout(TileLayout::Isometric);
+    /// let (w, h) = map.tile_size();
+    /// let mut spawn = Spawn { name: "".into(), prefab: "chest".into(), position: (1.0, 0.0), properties: Properties::new() };
+    /// assert_eq!(spawn.world_position(&map), map.tile_to_world((1, 0)));
+    ///
+    /// spawn.set_world_position((w / 4.0, h / 8.0), &map);
+    /// assert_eq!(spawn.position, (0.5, 0.0));
+    /// ```
+    pub fn world_position(&self, tilemap: &Tilemap) -> (f32, f32) { to_world(self.position, tilemap) }
+
+    /// Moves the spawn to a world position, on the tilemap it's loaded into.
+    pub fn set_world_position(&mut self, pos: (f32, f32), tilemap: &Tilemap) {
+        self.position = tilemap.layout.to_tile_fract(pos, tilemap.tile_size());
+    }
+}
+
+/// An area of the map, from a Tiled object with the class "trigger", which the
+/// game should react to things entering.
+#[derive(Debug, Clone)]
+pub struct TriggerRegion {
+    pub name:       String,
+    /// The object's bounds, in tile coordinates
+    pub area:       Rect,
+    pub properties: Properties,
+}
+impl TriggerRegion {
+    /// The world area of the region, on the tilemap it was loaded into.
+    ///
+    /// For layouts other than `TileLayout::Orthogonal`, this is the smallest
+    /// world rect containing the region's corners.
+    pub fn world_area(&self, tilemap: &Tilemap) -> Rect {
+        let area = self.area;
+        let corners = [(area.left(), area.top()), (area.right(), area.top()),
+                       (area.left(), area.bottom()), (area.right(), area.bottom())];
+        corners.iter()
+            .map(|corner| {
+                let (x, y) = to_world(*corner, tilemap);
+                Rect::new((x, y), (x, y))
+            })
+            .reduce(|a, b| a.union(&b))
+            .unwrap()
+    }
+}
+
+/// The tiles of one of a Tiled map's tile layers.
+#[derive(Debug, Clone)]
+pub struct TiledLayer {
+    pub name:       String,
+    /// The tilemap layer the tiles are placed on, from the layer's `layer`
+    /// property or else its name in lower case, e.g. "floor" or "walls"
+    pub layer:      String,
+    /// The settings of the tilemap layer, should the tilemap not have it.
+    ///
+    /// These are the layer's opacity, tint, parallax and visibility in Tiled,
+    /// along with its `depth` (float), `solid` (bool) and `overhead` (float,
+    /// the faded opacity) properties.
+    pub settings:   LayerSettings,
+    /// The gid of each of the layer's tiles, by tile position
+    pub tiles:      Vec<((i32, i32), u32)>,
+}
+
+/// A level made in the Tiled map editor, loaded from a TMX (XML) or TMJ (JSON) file.
+///
+/// Tile layers become tiles of a `Tilemap`, the top left of the map being
+/// tile (0, height - 1). Each tile layer is placed on the tilemap layer of the same name.
+/// Object layer entries become `Spawn`s for the game to place prefabs at,
+/// or `TriggerRegion`s when their class is "trigger".
+///
+/// Tilesets should be cut from the tilemap's sprite sheet image, so that
+/// each tile becomes the sheet tile at the same position, with the tile's
+/// properties as its sprite's `properties`. A tile with a
+/// `sprite` (and optionally `variant`) property instead becomes that sprite
+/// of the sheet. Animated tiles become an "idle" animation, their frames
+/// needing to be consecutive tiles of the same duration, and a `random_phase`
+/// property stops them animating in step.
+///
+/// Only orthogonal maps are supported, layer data must be CSV or uncompressed
+/// Base64, and flipped tiles are placed unflipped.
+///
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use stoneng::model::{spritesheet::SpriteSheet, tilemap::*, tiled::*};
+/// # let img_data = include_bytes!("./tiled.rs");
+/// let sheet = SpriteSheet::new(r#"SpriteSheet(sheet_width: 256, tile_width: 32, sprites: {
+///     "grass": (root: 0, variants: { "dirt": (root: 1) }),
+/// })"#, img_data).unwrap();
+/// let sheet = Arc::new(sheet);
+///
+/// let level = TiledMap::load("assets/test/maps/crypt.tmx").unwrap();
+/// let mut map = Tilemap::new(sheet.clone());
+/// level.populate(&mut map).unwrap();
+///
+/// // Brick walls, from the tileset's third tile
+/// assert_eq!(map.get_tile((0, 0), "walls").unwrap().root, 2);
+/// // Animated water, and a tile naming the sprite it uses
+/// let water = map.get_tile((2, 2), "floor").unwrap();
+/// assert_eq!(water.animations["idle"].frames, 3);
+/// assert_eq!(map.get_tile((1, 1), "floor").unwrap().root, 1);
+/// assert_eq!(level.tile_properties[&(1, 1)]["footstep"], Property::String("mud".into()));
+/// assert_eq!(map.tile_property((1, 1), "footstep").and_then(Property::as_str), Some("mud"));
+///
+/// assert_eq!(level.spawns[0].prefab, "player");
+/// assert_eq!(level.spawns[0].position, (1.0, 2.0));
+/// assert_eq!(level.spawns[1].properties["count"].as_int(), Some(3));
+/// assert_eq!(level.triggers[0].area.center(), (4.5, 1.0));
+///
+/// // The same map saved as JSON, with an external tileset
+/// let json = TiledMap::load("assets/test/maps/crypt.tmj").unwrap();
+/// let mut json_map = Tilemap::new(sheet);
+/// json.populate(&mut json_map).unwrap();
+/// for x in 0..6 {
+///     for y in 0..4 {
+///         let root = |map: &Tilemap| map.get_tile((x, y), "floor").map(|s| s.root);
+///         assert_eq!(root(&map), root(&json_map));
+///         assert_eq!(map.is_solid((x, y)), json_map.is_solid((x, y)));
+///     }
+/// }
+/// assert_eq!(json.spawns, level.spawns);
+///
+/// // Malformed maps are rejected as they're loaded
+/// # use stoneng::EngineError;
+/// let error = |path: &str| match TiledMap::load(path) {
+///     Err(EngineError::MapLoadError(reason)) => reason,
+///     other => panic!("{:?}", other),
+/// };
+/// assert!(error("assets/test/maps/no_width.tmj").contains("width and height"));
+/// assert!(error("assets/test/maps/short_layer.tmx").contains("have 5 gids"));
+/// assert!(error("assets/test/maps/unknown_gid.tmx").contains("gid 99"));
+/// assert!(error("assets/test/maps/missing_tileset.tmj").contains("missing.tsj"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TiledMap {
+    /// The map's width and height, in tiles
+    pub width:              i32,
+    pub height:             i32,
+    pub properties:         Properties,
+    pub layers:             Vec<TiledLayer>,
+    /// The properties of the tiles placed at each tile position, merged across layers
+    pub tile_properties:    HashMap<(i32, i32), Properties>,
+    pub spawns:             Vec<Spawn>,
+    pub triggers:           Vec<TriggerRegion>,
+    tilesets:               Vec<Tileset>,
+}
+
+impl TiledMap {
+    /// Loads a `.tmx` or `.tmj` map file, along with any external tilesets it uses.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, EngineError> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let external = |source: &str| fs::read_to_string(dir.join(source))
+            .map_err(|e| EngineError::MapLoadError(format!("the external tileset \"{}\": {}", source, e)));
+        let src = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("tmx") => Self::parse_tmx(&src, &external),
+            Some("tmj") | Some("json") => Self::parse_tmj(&src, &external),
+            _ => Err(EngineError::MapLoadError(format!("{} is not a .tmx or .tmj file", path.display()))),
+        }
+    }
+
+    /// Parses a TMX map, which can only use embedded tilesets.
+    pub fn from_tmx(src: &str) -> Result<Self, EngineError> { Self::parse_tmx(src, &no_external) }
+
+    /// Parses a TMJ map, which can only use embedded tilesets.
+    pub fn from_tmj(src: &str) -> Result<Self, EngineError> { Self::parse_tmj(src, &no_external) }
+
+    /// Places the map's tiles into a tilemap, using its sprite sheet.
+    ///
+    /// Layers the tilemap doesn't have are added with the Tiled layer's settings,
+    /// and the `tile_properties` are given to their positions of the tilemap.
+    /// Nothing is changed if any tile can't be found in the sheet.
+    pub fn populate(&self, tilemap: &mut Tilemap) -> Result<(), EngineError> {
+        let sheet = tilemap.spritesheet.clone()
+            .ok_or_else(|| EngineError::MapLoadError("the tilemap has no spritesheet".into()))?;
+
+        let mut resolved: HashMap<u32, Arc<SpriteSchema>> = HashMap::new();
+        for layer in self.layers.iter() {
+            for (_, gid) in layer.tiles.iter() {
+                if let Entry::Vacant(entry) = resolved.entry(*gid) {
+                    entry.insert(self.resolve(*gid, &layer.layer, tilemap, &sheet)?);
+                }
+            }
+        }
+
+        for layer in self.layers.iter() {
+            if tilemap.layer(&layer.layer).is_none() {
+                tilemap.add_layer(&layer.layer, layer.settings.clone());
+            }
+            for (pos, gid) in layer.tiles.iter() {
+                tilemap.set_tile(*pos, &layer.layer, resolved[gid].clone());
+            }
+        }
+        for (pos, properties) in self.tile_properties.iter() {
+            let mut merged = tilemap.tile_properties(*pos).cloned().unwrap_or_default();
+            merged.extend(properties.clone());
+            tilemap.set_tile_properties(*pos, merged);
+        }
+        Ok(())
+    }
+
+    /// Finds the tileset a gid belongs to, and the tile's id within it
+    fn tileset(&self, gid: u32) -> Option<(&Tileset, u32)> {
+        self.tilesets.iter()
+            .filter(|tileset| tileset.firstgid <= gid)
+            .max_by_key(|tileset| tileset.firstgid)
+            .map(|tileset| (tileset, gid - tileset.firstgid))
+            .filter(|(tileset, id)| *id < tileset.tile_count)
+    }
+
+    /// Finds, or builds, the sprite schema of a tile
+    fn resolve(&self, gid: u32, layer: &str, tilemap: &Tilemap, sheet: &SpriteSheet)
+            -> Result<Arc<SpriteSchema>, EngineError> {
+        // Every gid was checked against the tilesets when the map was parsed
+        let (tileset, id) = self.tileset(gid).unwrap();
+        let error = |reason: String| EngineError::MapLoadError(
+            format!("tile {} of tileset \"{}\": {}", id, tileset.name, reason)
+        );
+        let tile = tileset.tiles.get(&id);
+
+        let property = |name: &str| tile.and_then(|tile| tile.properties.get(name)).and_then(Property::as_str);
+        if let Some(sprite) = property("sprite") {
+            let def = TileDef::new(sprite, layer);
+            let def = match property("variant") { Some(variant) => def.with_variant(variant), None => def };
+            return tilemap.resolve(&def).map_err(error);
+        }
+
+        if tileset.tile_width != sheet.tile_width {
+            return Err(error(format!("the tileset's tiles are {}px wide, but the sprite sheet's are {}px",
+                tileset.tile_width, sheet.tile_width)));
+        }
+        let sheet_columns = sheet.sheet_width / sheet.tile_width;
+        let sheet_tile = |id: u32| (id / tileset.columns) * sheet_columns + id % tileset.columns;
+
+        let mut animations = HashMap::new();
+        if let Some(frames) = tile.map(|tile| &tile.frames).filter(|frames| !frames.is_empty()) {
+            let (first, duration) = frames[0];
+            let consecutive = frames.iter().enumerate()
+                .all(|(i, (id, time))| sheet_tile(*id) == sheet_tile(first) + i as u32 && *time == duration);
+            if !consecutive || frames.len() > u8::MAX as usize {
+                return Err(error("animation frames must be consecutive tiles of the same duration".into()));
+            }
+            animations.insert("idle".to_string(), Arc::new(AnimationSchema {
+                root:       sheet_tile(first),
+                frames:     frames.len() as u8,
+                mode:       AnimMode::Loop,
+                frame_time: duration as f32 / 1000.0,
+                random_phase: tile.and_then(|tile| tile.properties.get("random_phase"))
+                    .and_then(Property::as_bool).unwrap_or(false),
+            }));
+        }
+
+        Ok(Arc::new(SpriteSchema {
+            root: sheet_tile(id), variants: HashMap::new(), dimensions: (0, 0), animations,
+            properties: tile.map(|tile| tile.properties.clone()).unwrap_or_default(),
+        }))
+    }
+
+    /// Builds the map from its parsed parts, checking each layer's tiles against the tilesets
+    fn build(header: Header, tilesets: Vec<Tileset>, layers: Vec<Layer>) -> Result<Self, EngineError> {
+        let mut map = Self {
+            width: header.width, height: header.height, properties: header.properties,
+            layers: vec![], tile_properties: HashMap::new(), spawns: vec![], triggers: vec![], tilesets,
+        };
+        let tile_size = (header.tile_size.0 as f32, header.tile_size.1 as f32);
+        // From Tiled's pixels, down from the top left, to tiles centered on their position
+        let height = header.height as f32;
+        let to_tiles = |x: f32, y: f32| (x / tile_size.0 - 0.5, height - y / tile_size.1 - 0.5);
+
+        for layer in layers {
+            match layer {
+                Layer::Tiles { name, properties, style, chunks } => {
+                    let settings = layer_settings(&name, style, &properties)?;
+                    let layer = properties.get("layer").and_then(Property::as_str)
+                        .map_or_else(|| name.to_lowercase(), String::from);
+                    let mut tiles = vec![];
+                    for chunk in chunks {
+                        if chunk.width <= 0 || chunk.height < 0 || chunk.gids.len() != (chunk.width * chunk.height) as usize {
+                            return Err(EngineError::MapLoadError(format!(
+                                "layer \"{}\": the {}x{} tiles at ({}, {}) have {} gids",
+                                name, chunk.width, chunk.height, chunk.x, chunk.y, chunk.gids.len()
+                            )));
+                        }
+                        let placed = chunk.gids.iter().enumerate().filter(|(_, gid)| *gid & !FLIP_FLAGS != 0);
+                        for (i, gid) in placed {
+                            let gid = gid & !FLIP_FLAGS;
+                            let (column, row) = (chunk.x + i as i32 % chunk.width, chunk.y + i as i32 / chunk.width);
+                            let pos = (column, header.height - 1 - row);
+
+                            let (tileset, id) = map.tileset(gid).ok_or_else(|| EngineError::MapLoadError(
+                                format!("layer \"{}\": gid {} isn't in any tileset", name, gid)
+                            ))?;
+                            let properties = tileset.tiles.get(&id)
+                                .map(|tile| tile.properties.clone())
+                                .filter(|properties| !properties.is_empty());
+                            if let Some(properties) = properties {
+                                map.tile_properties.entry(pos).or_default().extend(properties);
+                            }
+                            tiles.push((pos, gid));
+                        }
+                    }
+                    map.layers.push(TiledLayer { name, layer, settings, tiles });
+                },
+                Layer::Objects { objects } => for object in objects {
+                    // Tile objects are placed by their bottom left corner
+                    let (x, y) = if object.tile { (object.x, object.y - object.height) } else { (object.x, object.y) };
+                    let points = if object.points.is_empty() {
+                        vec![(0.0, 0.0), (object.width, object.height)]
+                    } else {
+                        object.points
+                    };
+                    let (min_x, min_y) = points.iter().fold((f32::MAX, f32::MAX), |a, p| (a.0.min(p.0), a.1.min(p.1)));
+                    let (max_x, max_y) = points.iter().fold((f32::MIN, f32::MIN), |a, p| (a.0.max(p.0), a.1.max(p.1)));
+                    let area = Rect::new(to_tiles(x + min_x, y + min_y), to_tiles(x + max_x, y + max_y));
+
+                    if object.class.eq_ignore_ascii_case("trigger") {
+                        map.triggers.push(TriggerRegion { name: object.name, area, properties: object.properties });
+                        continue;
+                    }
+                    let prefab = if object.class.is_empty() { object.name.clone() } else { object.class };
+                    if prefab.is_empty() {
+                        return Err(EngineError::MapLoadError(
+                            format!("object {} has no class or name to spawn as", object.id)
+                        ));
+                    }
+                    map.spawns.push(Spawn { name: object.name, prefab, position: area.center(), properties: object.properties });
+                },
+            }
+        }
+        Ok(map)
+    }
+}
+
+/// The settings of a tile layer, from how it's drawn in Tiled and its properties
+fn layer_settings(name: &str, style: LayerStyle, properties: &Properties) -> Result<LayerSettings, EngineError> {
+    let error = |reason: String| EngineError::MapLoadError(format!("layer \"{}\": {}", name, reason));
+    let property = |name: &str| properties.get(name);
+
+    let depth = property("depth").map(|depth| depth.as_float().ok_or_else(|| error("depth should be a float".into())))
+        .transpose()?;
+    let mut settings = LayerSettings::new(depth.unwrap_or_default() as f32)
+        .with_opacity(style.opacity)
+        .with_parallax(style.parallaxx, style.parallaxy);
+    settings.visible = style.visible;
+    settings.solid = property("solid").and_then(Property::as_bool).unwrap_or(false);
+    settings.overhead = property("overhead").and_then(Property::as_float).map(|faded| faded as f32);
+    if let Some(tint) = style.tintcolor {
+        // Tiled writes colours as #aarrggbb, or #rrggbb
+        let digits = tint.trim_start_matches('#');
+        let rgba = if digits.len() == 8 { format!("{}{}", &digits[2..], &digits[..2]) } else { digits.into() };
+        settings.tint = Color::from_hex(&rgba).map_err(|_| error(format!("\"{}\" is not a colour", tint)))?;
+    }
+    Ok(settings)
+}
+
+fn to_world(pos: (f32, f32), tilemap: &Tilemap) -> (f32, f32) {
+    tilemap.layout.to_world_fract(pos, tilemap.tile_size())
+}
+
+/// Loads the text of an external tileset, by its path relative to the map
+type External<'a> = dyn Fn(&str) -> Result<String, EngineError> + 'a;
+
+fn no_external(source: &str) -> Result<String, EngineError> {
+    Err(EngineError::MapLoadError(format!(
+        "the external tileset \"{}\" can only be used when loading the map from a file", source
+    )))
+}
+
+    // The parts of a map common to both formats
+
+struct Header {
+    width:      i32,
+    height:     i32,
+    tile_size:  (u32, u32),
+    properties: Properties,
+}
+
+#[derive(Debug, Clone)]
+struct Tileset {
+    name:       String,
+    firstgid:   u32,
+    tile_width: u32,
+    columns:    u32,
+    tile_count: u32,
+    /// The tiles with properties or animations, by id
+    tiles:      HashMap<u32, TilesetTile>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TilesetTile {
+    properties: Properties,
+    /// The (tile id, milliseconds) of each animation frame
+    frames:     Vec<(u32, u32)>,
+}
+
+enum Layer {
+    Tiles { name: String, properties: Properties, style: LayerStyle, chunks: Vec<GidChunk> },
+    Objects { objects: Vec<Object> },
+}
+
+/// How a layer is drawn in Tiled
+#[derive(Deserialize)]
+#[serde(default)]
+struct LayerStyle {
+    opacity:    f32,
+    visible:    bool,
+    tintcolor:  Option<String>,
+    parallaxx:  f32,
+    parallaxy:  f32,
+}
+impl Default for LayerStyle {
+    fn default() -> Self {
+        Self { opacity: 1.0, visible: true, tintcolor: None, parallaxx: 1.0, parallaxy: 1.0 }
+    }
+}
+
+/// A rectangle of a tile layer, the whole layer unless the map is infinite
+struct GidChunk {
+    x:      i32,
+    y:      i32,
+    width:  i32,
+    height: i32,
+    gids:   Vec<u32>,
+}
+
+struct Object {
+    id:         u32,
+    name:       String,
+    class:      String,
+    x:          f32,
+    y:          f32,
+    width:      f32,
+    height:     f32,
+    /// Whether the object is a tile, rather than a shape
+    tile:       bool,
+    /// A polygon or polyline's points, relative to its position
+    points:     Vec<(f32, f32)>,
+    properties: Properties,
+}
+
+/// Reads the gids of Base64 encoded layer data
+fn base64_gids(text: &str, compression: Option<&str>) -> Result<Vec<u32>, String> {
+    if let Some(compression) = compression.filter(|c| !c.is_empty()) {
+        return Err(format!("{} compressed layer data isn't supported, use CSV or uncompressed Base64", compression));
+    }
+    let mut bytes = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return Err(format!("'{}' is not valid Base64", c as char)),
+        };
+        bits = (bits << 6) | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    if bytes.len() % 4 != 0 {
+        return Err("Base64 layer data is not a whole number of gids".into());
+    }
+    Ok(bytes.chunks_exact(4).map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]])).collect())
+}
+
+    // TMX
+
+impl TiledMap {
+    fn parse_tmx(src: &str, external: &External) -> Result<Self, EngineError> {
+        let doc = roxmltree::Document::parse(src)
+            .map_err(|e| EngineError::MapLoadError(e.to_string()))?;
+        let root = doc.root_element();
+        if !root.has_tag_name("map") {
+            return Err(xml_error(root, "expected a <map>".into()));
+        }
+        if attr_or(root, "orientation", String::from("orthogonal"))? != "orthogonal" {
+            return Err(xml_error(root, "only orthogonal maps are supported".into()));
+        }
+        let header = Header {
+            width:      attr(root, "width")?,
+            height:     attr(root, "height")?,
+            tile_size:  (attr(root, "tilewidth")?, attr(root, "tileheight")?),
+            properties: xml_properties(root)?,
+        };
+
+        let mut tilesets = vec![];
+        for node in root.children().filter(|node| node.has_tag_name("tileset")) {
+            let firstgid = attr(node, "firstgid")?;
+            tilesets.push(match node.attribute("source") {
+                Some(source) => external_tileset(source, firstgid, external)?,
+                None => xml_tileset(node, firstgid)?,
+            });
+        }
+
+        let mut layers = vec![];
+        xml_layers(root, &mut layers)?;
+        Self::build(header, tilesets, layers)
+    }
+}
+
+/// Loads an external `.tsx` or `.tsj` tileset
+fn external_tileset(source: &str, firstgid: u32, external: &External) -> Result<Tileset, EngineError> {
+    let src = external(source)?;
+    if source.ends_with(".tsj") || source.ends_with(".json") {
+        let tileset: JsonTileset = serde_json::from_str(&src)?;
+        return tileset.into_tileset(firstgid);
+    }
+    let doc = roxmltree::Document::parse(&src)
+        .map_err(|e| EngineError::MapLoadError(format!("{}: {}", source, e)))?;
+    xml_tileset(doc.root_element(), firstgid)
+}
+
+fn xml_tileset(node: Node, firstgid: u32) -> Result<Tileset, EngineError> {
+    let columns = attr(node, "columns")?;
+    if columns == 0 {
+        return Err(xml_error(node, "image collection tilesets aren't supported".into()));
+    }
+    let mut tiles = HashMap::new();
+    for tile in node.children().filter(|n| n.has_tag_name("tile")) {
+        let frames = tile.children().filter(|n| n.has_tag_name("animation"))
+            .flat_map(|animation| animation.children().filter(|n| n.has_tag_name("frame")))
+            .map(|frame| Ok((attr(frame, "tileid")?, attr(frame, "duration")?)))
+            .collect::<Result<_, EngineError>>()?;
+        tiles.insert(attr(tile, "id")?, TilesetTile { properties: xml_properties(tile)?, frames });
+    }
+    Ok(Tileset {
+        name: attr_or(node, "name", String::new())?, firstgid, tile_width: attr(node, "tilewidth")?,
+        columns, tile_count: attr(node, "tilecount")?, tiles,
+    })
+}
+
+/// Reads the layers within a map or group, in order
+fn xml_layers(parent: Node, layers: &mut Vec<Layer>) -> Result<(), EngineError> {
+    for node in parent.children().filter(|n| n.is_element()) {
+        match node.tag_name().name() {
+            "group" => xml_layers(node, layers)?,
+            "layer" => {
+                let data = node.children().find(|n| n.has_tag_name("data"))
+                    .ok_or_else(|| xml_error(node, "the layer has no <data>".into()))?;
+                let chunks = if data.children().any(|n| n.has_tag_name("chunk")) {
+                    data.children().filter(|n| n.has_tag_name("chunk"))
+                        .map(|chunk| Ok(GidChunk {
+                            x: attr(chunk, "x")?, y: attr(chunk, "y")?,
+                            width: attr(chunk, "width")?, height: attr(chunk, "height")?,
+                            gids: xml_gids(data, chunk)?,
+                        }))
+                        .collect::<Result<_, EngineError>>()?
+                } else {
+                    vec![GidChunk {
+                        x: 0, y: 0, width: attr(node, "width")?, height: attr(node, "height")?,
+                        gids: xml_gids(data, data)?,
+                    }]
+                };
+                let style = LayerStyle {
+                    opacity:    attr_or(node, "opacity", 1.0)?,
+                    // Booleans are written as 0 or 1
+                    visible:    attr_or(node, "visible", 1)? != 0,
+                    tintcolor:  node.attribute("tintcolor").map(String::from),
+                    parallaxx:  attr_or(node, "parallaxx", 1.0)?,
+                    parallaxy:  attr_or(node, "parallaxy", 1.0)?,
+                };
+                layers.push(Layer::Tiles {
+                    name: attr_or(node, "name", String::new())?, properties: xml_properties(node)?, style, chunks,
+                });
+            },
+            "objectgroup" => {
+                let objects = node.children().filter(|n| n.has_tag_name("object"))
+                    .map(xml_object)
+                    .collect::<Result<_, EngineError>>()?;
+                layers.push(Layer::Objects { objects });
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+/// Reads the gids of a layer's data, or of one of its chunks, in the data's encoding
+fn xml_gids(data: Node, node: Node) -> Result<Vec<u32>, EngineError> {
+    let text = node.children().filter(|n| n.is_text()).filter_map(|n| n.text()).collect::<String>();
+    match data.attribute("encoding") {
+        Some("csv") => text.split(',')
+            .map(|gid| gid.trim().parse().map_err(|_| xml_error(node, format!("\"{}\" is not a gid", gid.trim()))))
+            .collect(),
+        Some("base64") => base64_gids(&text, data.attribute("compression")).map_err(|e| xml_error(node, e)),
+        Some(encoding) => Err(xml_error(data, format!("unknown encoding \"{}\"", encoding))),
+        None => node.children().filter(|n| n.has_tag_name("tile"))
+            .map(|tile| attr_or(tile, "gid", 0))
+            .collect(),
+    }
+}
+
+fn xml_object(node: Node) -> Result<Object, EngineError> {
+    let points = match node.children().find(|n| n.has_tag_name("polygon") || n.has_tag_name("polyline")) {
+        Some(shape) => attr::<String>(shape, "points")?.split_whitespace()
+            .map(|point| {
+                let (x, y) = point.split_once(',').unwrap_or((point, ""));
+                match (x.parse(), y.parse()) {
+                    (Ok(x), Ok(y)) => Ok((x, y)),
+                    _ => Err(xml_error(shape, format!("\"{}\" is not a point", point))),
+                }
+            })
+            .collect::<Result<_, _>>()?,
+        None => vec![],
+    };
+    // Objects have a class since Tiled 1.9, and a type before and after it
+    let class = match node.attribute("class") {
+        Some(class) => class.to_string(),
+        None => attr_or(node, "type", String::new())?,
+    };
+    Ok(Object {
+        id: attr_or(node, "id", 0)?, name: attr_or(node, "name", String::new())?, class,
+        x: attr(node, "x")?, y: attr(node, "y")?,
+        width: attr_or(node, "width", 0.0)?, height: attr_or(node, "height", 0.0)?,
+        tile: node.has_attribute("gid"), points, properties: xml_properties(node)?,
+    })
+}
+
+/// Reads the <properties> of an element
+fn xml_properties(node: Node) -> Result<Properties, EngineError> {
+    node.children().filter(|n| n.has_tag_name("properties"))
+        .flat_map(|properties| properties.children().filter(|n| n.has_tag_name("property")))
+        .map(|property| {
+            // Multi-line strings are stored as the element's text instead
+            let value = property.attribute("value").or_else(|| property.text()).unwrap_or_default();
+            let ty = property.attribute("type").unwrap_or_default();
+            let value = Property::parse(ty, value).map_err(|e| xml_error(property, e))?;
+            Ok((attr(property, "name")?, value))
+        })
+        .collect()
+}
+
+fn attr<T: FromStr>(node: Node, name: &str) -> Result<T, EngineError> {
+    let value = node.attribute(name)
+        .ok_or_else(|| xml_error(node, format!("<{}> has no {} attribute", node.tag_name().name(), name)))?;
+    value.parse().map_err(|_| xml_error(node, format!("{}=\"{}\" is not valid", name, value)))
+}
+
+fn attr_or<T: FromStr>(node: Node, name: &str, default: T) -> Result<T, EngineError> {
+    if node.has_attribute(name) { attr(node, name) } else { Ok(default) }
+}
+
+/// An error at an element of the document, giving its line and column
+fn xml_error(node: Node, reason: String) -> EngineError {
+    let pos = node.document().text_pos_at(node.range().start);
+    EngineError::MapLoadError(format!("line {}, column {}: {}", pos.row, pos.col, reason))
+}
+
+    // TMJ
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width:          i32,
+    height:         i32,
+    tilewidth:      u32,
+    tileheight:     u32,
+    #[serde(default)]
+    orientation:    String,
+    #[serde(default)]
+    properties:     Vec<JsonProperty>,
+    #[serde(default)]
+    tilesets:       Vec<JsonTilesetRef>,
+    #[serde(default)]
+    layers:         Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonProperty {
+    name:   String,
+    #[serde(rename = "type", default)]
+    ty:     String,
+    value:  Value,
+}
+
+#[derive(Deserialize)]
+struct JsonTilesetRef {
+    firstgid:   u32,
+    source:     Option<String>,
+    #[serde(flatten)]
+    tileset:    Option<JsonTileset>,
+}
+
+#[derive(Deserialize)]
+struct JsonTileset {
+    #[serde(default)]
+    name:       String,
+    tilewidth:  u32,
+    columns:    u32,
+    tilecount:  u32,
+    #[serde(default)]
+    tiles:      Vec<JsonTile>,
+}
+
+#[derive(Deserialize)]
+struct JsonTile {
+    id:         u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+    #[serde(default)]
+    animation:  Vec<JsonFrame>,
+}
+
+#[derive(Deserialize)]
+struct JsonFrame {
+    tileid:     u32,
+    duration:   u32,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonLayer {
+    TileLayer {
+        name:           String,
+        /// Only needed for the layer's data, chunks having their own size
+        width:          Option<i32>,
+        height:         Option<i32>,
+        data:           Option<Value>,
+        #[serde(default)]
+        chunks:         Vec<JsonChunk>,
+        encoding:       Option<String>,
+        compression:    Option<String>,
+        #[serde(default)]
+        properties:     Vec<JsonProperty>,
+        #[serde(flatten)]
+        style:          LayerStyle,
+    },
+    ObjectGroup {
+        #[serde(default)]
+        objects:        Vec<JsonObject>,
+    },
+    Group {
+        #[serde(default)]
+        layers:         Vec<JsonLayer>,
+    },
+    ImageLayer {},
+}
+
+#[derive(Deserialize)]
+struct JsonChunk {
+    x:      i32,
+    y:      i32,
+    width:  i32,
+    height: i32,
+    data:   Value,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    id:         u32,
+    #[serde(default)]
+    name:       String,
+    #[serde(rename = "type", default)]
+    ty:         String,
+    #[serde(default)]
+    class:      String,
+    x:          f32,
+    y:          f32,
+    #[serde(default)]
+    width:      f32,
+    #[serde(default)]
+    height:     f32,
+    gid:        Option<u32>,
+    polygon:    Option<Vec<JsonPoint>>,
+    polyline:   Option<Vec<JsonPoint>>,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Deserialize)]
+struct JsonPoint {
+    x:  f32,
+    y:  f32,
+}
+
+impl From<serde_json::Error> for EngineError {
+    fn from(error: serde_json::Error) -> Self {
+        Self::MapLoadError(error.to_string())
+    }
+}
+
+impl TiledMap {
+    fn parse_tmj(src: &str, external: &External) -> Result<Self, EngineError> {
+        let map: JsonMap = serde_json::from_str(src)?;
+        if !map.orientation.is_empty() && map.orientation != "orthogonal" {
+            return Err(EngineError::MapLoadError("only orthogonal maps are supported".into()));
+        }
+        let header = Header {
+            width: map.width, height: map.height, tile_size: (map.tilewidth, map.tileheight),
+            properties: json_properties(map.properties)?,
+        };
+
+        let tilesets = map.tilesets.into_iter()
+            .map(|tileset| match (tileset.source, tileset.tileset) {
+                (Some(source), _) => external_tileset(&source, tileset.firstgid, external),
+                (None, Some(embedded)) => embedded.into_tileset(tileset.firstgid),
+                (None, None) => Err(EngineError::MapLoadError(
+                    format!("the tileset at gid {} has no source or tiles", tileset.firstgid)
+                )),
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut layers = vec![];
+        json_layers(map.layers, &mut layers)?;
+        Self::build(header, tilesets, layers)
+    }
+}
+
+impl JsonTileset {
+    fn into_tileset(self, firstgid: u32) -> Result<Tileset, EngineError> {
+        if self.columns == 0 {
+            return Err(EngineError::MapLoadError(
+                format!("tileset \"{}\": image collection tilesets aren't supported", self.name)
+            ));
+        }
+        let tiles = self.tiles.into_iter()
+            .map(|tile| Ok((tile.id, TilesetTile {
+                properties: json_properties(tile.properties)?,
+                frames: tile.animation.iter().map(|frame| (frame.tileid, frame.duration)).collect(),
+            })))
+            .collect::<Result<_, EngineError>>()?;
+        Ok(Tileset {
+            name: self.name, firstgid, tile_width: self.tilewidth, columns: self.columns,
+            tile_count: self.tilecount, tiles,
+        })
+    }
+}
+
+/// Reads the layers within a map or group, in order
+fn json_layers(json: Vec<JsonLayer>, layers: &mut Vec<Layer>) -> Result<(), EngineError> {
+    for layer in json {
+        match layer {
+            JsonLayer::Group { layers: group } => json_layers(group, layers)?,
+            JsonLayer::TileLayer { name, width, height, data, chunks, encoding, compression, properties, style } => {
+                let error = |reason: String| EngineError::MapLoadError(format!("layer \"{}\": {}", name, reason));
+                let gids = |data: &Value| json_gids(data, encoding.as_deref(), compression.as_deref()).map_err(error);
+                let chunks = match (data, width, height) {
+                    (Some(data), Some(width), Some(height)) => vec![GidChunk { x: 0, y: 0, width, height, gids: gids(&data)? }],
+                    (Some(_), _, _) => return Err(error("the layer's data needs its width and height".into())),
+                    (None, _, _) => chunks.iter()
+                        .map(|chunk| Ok(GidChunk {
+                            x: chunk.x, y: chunk.y, width: chunk.width, height: chunk.height, gids: gids(&chunk.data)?,
+                        }))
+                        .collect::<Result<_, EngineError>>()?,
+                };
+                layers.push(Layer::Tiles { name, properties: json_properties(properties)?, style, chunks });
+            },
+            JsonLayer::ObjectGroup { objects } => {
+                let objects = objects.into_iter()
+                    .map(|object| Ok(Object {
+                        id: object.id, name: object.name,
+                        class: if object.class.is_empty() { object.ty } else { object.class },
+                        x: object.x, y: object.y, width: object.width, height: object.height,
+                        tile: object.gid.is_some(),
+                        points: object.polygon.or(object.polyline).unwrap_or_default()
+                            .iter().map(|point| (point.x, point.y)).collect(),
+                        properties: json_properties(object.properties)?,
+                    }))
+                    .collect::<Result<_, EngineError>>()?;
+                layers.push(Layer::Objects { objects });
+            },
+            JsonLayer::ImageLayer {} => {},
+        }
+    }
+    Ok(())
+}
+
+/// Reads layer data, either an array of gids or a Base64 string
+fn json_gids(data: &Value, encoding: Option<&str>, compression: Option<&str>) -> Result<Vec<u32>, String> {
+    match (data, encoding) {
+        (Value::String(text), Some("base64")) => base64_gids(text, compression),
+        (Value::Array(gids), None) | (Value::Array(gids), Some("csv")) => gids.iter()
+            .map(|gid| gid.as_u64().map(|gid| gid as u32).ok_or_else(|| format!("{} is not a gid", gid)))
+            .collect(),
+        _ => Err("the layer data doesn't match its encoding".into()),
+    }
+}
+
+fn json_properties(properties: Vec<JsonProperty>) -> Result<Properties, EngineError> {
+    properties.into_iter()
+        .map(|property| {
+            let value = Property::from_json(&property.ty, &property.value)
+                .map_err(|e| EngineError::MapLoadError(format!("property \"{}\": {}", property.name, e)))?;
+            Ok((property.name, value))
+        })
+        .collect()
+}
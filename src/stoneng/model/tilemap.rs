@@ -1,45 +1,771 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque, hash_map::Entry};
+use std::sync::Arc;
 
-use specs::Entity;
+use serde::{Serialize, Deserialize};
 
-use crate::model::spritesheet::SpriteSheet;
+use crate::EngineError;
+use crate::model::{
+    Rect, spritesheet::SpriteSheet, layout::TileLayout, tiled::{Property, Properties},
+    autotile::{AutotileRules, Autotiler},
+};
+use crate::ecs::component::Color;
 
 use super::spritesheet::SpriteSchema;
 
+/// The width and height of a chunk, in tiles.
+pub const CHUNK_SIZE: i32 = 16;
+
+/// The width and height of an unscaled tile, in world units, for maps without a sprite sheet.
+pub const TILE_SIZE: f32 = 10.0;
+
+/// A change to a tilemap, for systems which cache anything about the map.
+#[derive(Debug, Clone)]
+pub enum TileEvent {
+    /// A tile was placed, replaced or removed
+    Changed {
+        layer:      String,
+        pos:        (i32, i32),
+        previous:   Option<Arc<SpriteSchema>>,
+        current:    Option<Arc<SpriteSchema>>,
+    },
+    /// A layer was added or removed, or its settings may have changed
+    LayerChanged(String),
+    /// A position's own properties were set or removed
+    PropertiesChanged((i32, i32)),
+    /// The map was replaced, anything known about the old one is out of date
+    Reset,
+}
+
+/// A sprite, or one of its variants, placed on a layer of the map.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct TileDef {
+    pub sprite:     String,
+    #[serde(default)]
+    pub variant:    Option<String>,
+    pub layer:      String,
+}
+impl TileDef {
+    pub fn new(sprite: &str, layer: &str) -> Self {
+        Self { sprite: sprite.into(), variant: None, layer: layer.into() }
+    }
+    pub fn with_variant(self, variant: &str) -> Self { Self { variant: Some(variant.into()), ..self } }
+}
+
+/// Maps each character of a text level to the tiles placed in its cell.
+///
+/// A character may stack tiles on several layers, e.g. a floor beneath a
+/// wall, or none to leave the cell empty.
+pub type Legend = HashMap<char, Vec<TileDef>>;
+
+/// How a layer of the tilemap is drawn, and how its tiles affect the world.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LayerSettings {
+    /// The depth the layer's tiles are drawn at, within `layer::DEPTH_RANGE`
+    pub depth:          f32,
+    /// Multiplies the colour of the layer's tiles
+    pub tint:           Color,
+    pub opacity:        f32,
+    /// How far the layer moves with the view, (1, 1) moving with the world
+    /// and less than that appearing further away
+    pub parallax:       (f32, f32),
+    pub visible:        bool,
+    /// Whether the layer's tiles block movement and sight, unless their
+    /// sprite's `solid` or `opaque` property says otherwise
+    pub solid:          bool,
+    /// The opacity the layer fades to while a `RevealsOverhead` entity is
+    /// beneath one of its tiles, for roofs and treetops
+    pub overhead:       Option<f32>,
+}
+impl LayerSettings {
+    pub fn new(depth: f32) -> Self {
+        Self {
+            depth, tint: Color::rgb(1.0, 1.0, 1.0), opacity: 1.0, parallax: (1.0, 1.0),
+            visible: true, solid: false, overhead: None,
+        }
+    }
+    pub fn with_tint(self, tint: Color) -> Self { Self { tint, ..self } }
+    pub fn with_opacity(self, opacity: f32) -> Self { Self { opacity, ..self } }
+    pub fn with_parallax(self, x: f32, y: f32) -> Self { Self { parallax: (x, y), ..self } }
+    pub fn as_solid(self) -> Self { Self { solid: true, ..self } }
+    pub fn as_overhead(self, faded_opacity: f32) -> Self { Self { overhead: Some(faded_opacity), ..self } }
+}
+impl Default for LayerSettings {
+    fn default() -> Self { Self::new(0.0) }
+}
+
+/// A square block of CHUNK_SIZE x CHUNK_SIZE tiles of a layer.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    tiles:      Vec<Option<Arc<SpriteSchema>>>,
+}
+impl Chunk {
+    fn new() -> Self {
+        Self { tiles: vec![None; (CHUNK_SIZE * CHUNK_SIZE) as usize] }
+    }
+
+    /// The chunk's placed tiles with their position within the chunk
+    pub fn tiles(&self) -> impl Iterator<Item = ((i32, i32), &Arc<SpriteSchema>)> {
+        self.tiles.iter().enumerate()
+            .filter_map(|(i, tile)| Some(((i as i32 % CHUNK_SIZE, i as i32 / CHUNK_SIZE), tile.as_ref()?)))
+    }
+}
+
+/// A named layer of tiles, stored in chunks.
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    name:           String,
+    pub settings:   LayerSettings,
+    chunks:         HashMap<(i32, i32), Chunk>,
+}
+impl TileLayer {
+    pub fn name(&self) -> &str { &self.name }
+
+    /// Returns the tile at a position, if there is one.
+    pub fn get(&self, pos: (i32, i32)) -> Option<&Arc<SpriteSchema>> {
+        self.chunks.get(&Tilemap::chunk_coord(pos))?.tiles[Tilemap::tile_index(pos)].as_ref()
+    }
+
+    pub fn chunk(&self, coord: (i32, i32)) -> Option<&Chunk> { self.chunks.get(&coord) }
+
+    /// All of the layer's chunks, by chunk coordinate
+    pub fn chunks(&self) -> impl Iterator<Item = ((i32, i32), &Chunk)> {
+        self.chunks.iter().map(|(coord, chunk)| (*coord, chunk))
+    }
+
+    /// Every placed tile of the layer, by tile position
+    pub fn tiles(&self) -> impl Iterator<Item = ((i32, i32), &Arc<SpriteSchema>)> {
+        self.chunks().flat_map(|(coord, chunk)| chunk.tiles()
+            .map(move |((x, y), tile)| ((coord.0 * CHUNK_SIZE + x, coord.1 * CHUNK_SIZE + y), tile)))
+    }
+
+    /// Places (or with None, removes) a tile, creating its chunk if needed
+    fn put(&mut self, pos: (i32, i32), schema: Option<Arc<SpriteSchema>>) -> Option<Arc<SpriteSchema>> {
+        let chunk = self.chunks.entry(Tilemap::chunk_coord(pos)).or_insert_with(Chunk::new);
+        std::mem::replace(&mut chunk.tiles[Tilemap::tile_index(pos)], schema)
+    }
+}
+
 /// A tilemap container to manage the world in a 2D game.
 ///
-/// The tiles must still be fed into the ECS, however, this provides a convenient
-/// way to build, access and modify the tiles.
+/// The tilemap is used as a World resource. It holds any number of named
+/// layers, each drawn with its own `LayerSettings`, starting with a "floor",
+/// "decals" and a solid "walls" layer.
+/// Tiles are stored in chunks, created as tiles are placed, so that any tile
+/// can be found in constant time. Tiles can also be autotiled, see `AutotileRules`.
+///
+/// Every tile is its sheet's `tile_width` across, at the map's scale, and
+/// placed in the world by the map's `TileLayout`, square by default.
+/// Changes to the map are kept as TileEvents, until the TileEventSys sends
+/// them on to the systems which cache the map, such as its renderer.
+///
+/// # Example
+/// ```
+/// # use std::sync::Arc;
+/// # use stoneng::model::{spritesheet::SpriteSchema, tilemap::*};
+/// # let brick: Arc<SpriteSchema> = Arc::new(SpriteSchema {
+/// #     root: 2, variants: Default::default(), dimensions: (0, 0), animations: Default::default(),
+/// #     properties: Default::default(),
+/// # });
+/// let mut map = Tilemap::default();
+/// map.set_tile((-3, 40), "walls", brick.clone());
+///
+/// assert!(map.is_solid((-3, 40)));
+/// assert!(map.get_tile((-3, 41), "walls").is_none());
+/// // Without a sheet, tiles are TILE_SIZE across
+/// assert_eq!(map.world_to_tile((-148.0, 2010.0)), (-3, 40));
+/// assert_eq!(map.tile_to_world((-3, 40)), (-150.0, 2000.0));
+///
+/// // A roof, drawn above everything else, fading as the player walks beneath
+/// map.add_layer("roofs", LayerSettings::new(5.0).as_overhead(0.25));
+/// map.fill_rect((-4, 41), (-2, 43), "roofs", Some(brick.clone()));
+/// assert!(!map.is_solid((-3, 41)));
+///
+/// // Every change is recorded, for systems to keep up with the map
+/// map.drain_events().for_each(drop);
+/// map.remove_tile((-3, 40), "walls");
+/// match map.drain_events().collect::<Vec<_>>().as_slice() {
+///     [TileEvent::Changed { pos: (-3, 40), current: None, .. }] => {},
+///     other => panic!("{:?}", other),
+/// }
+/// ```
 pub struct Tilemap {
-    pub tiles:          Vec<Entity>
+    /// The sheet the tiles' sprites are taken from
+    pub spritesheet:    Option<Arc<SpriteSheet>>,
+    /// How many times larger than its sprite each tile is in the world
+    pub scale:          (f32, f32),
+    /// How the tiles are arranged in the world
+    pub layout:         TileLayout,
+    layers:             Vec<TileLayer>,
+    /// The properties given to positions of the map, whichever tiles are there
+    tile_properties:    HashMap<(i32, i32), Properties>,
+    autotiler:          Option<Arc<Autotiler>>,
+    /// The changes made since the events were last drained
+    events:             Vec<TileEvent>,
+}
+impl Default for Tilemap {
+    fn default() -> Self {
+        let mut map = Self {
+            spritesheet: None, scale: (5.0, 5.0), layout: TileLayout::Orthogonal, layers: vec![],
+            tile_properties: HashMap::new(), autotiler: None, events: vec![TileEvent::Reset],
+        };
+        map.add_layer("floor", LayerSettings::new(-20.0));
+        map.add_layer("decals", LayerSettings::new(-15.0));
+        map.add_layer("walls", LayerSettings::new(-12.5).as_solid());
+        map
+    }
 }
 impl Tilemap {
-    pub fn new(spritesheet: SpriteSheet) -> Self {
-        Self { tiles: Vec::new() }
+    pub fn new(spritesheet: Arc<SpriteSheet>) -> Self {
+        Self { spritesheet: Some(spritesheet), ..Default::default() }
+    }
+
+    pub fn with_scale(self, x: f32, y: f32) -> Self { Self { scale: (x, y), ..self } }
+    pub fn with_layout(self, layout: TileLayout) -> Self { Self { layout, ..self } }
+
+    /// Adds a layer, or changes the settings of an existing one.
+    pub fn add_layer(&mut self, name: &str, settings: LayerSettings) -> &mut TileLayer {
+        let index = self.layer_index(name);
+        self.events.push(TileEvent::LayerChanged(name.into()));
+        let layer = &mut self.layers[index];
+        layer.settings = settings;
+        layer
     }
-    
-    /// Builds a Tilemap from a string and a char->sprite hashmap
+
+    /// Removes a layer, and its tiles, from the map.
+    pub fn remove_layer(&mut self, name: &str) -> Option<TileLayer> {
+        let index = self.layers.iter().position(|layer| layer.name == name)?;
+        self.events.push(TileEvent::LayerChanged(name.into()));
+        Some(self.layers.remove(index))
+    }
+
+    pub fn layer(&self, name: &str) -> Option<&TileLayer> {
+        self.layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Returns a layer, for its settings to be changed.
+    pub fn layer_mut(&mut self, name: &str) -> Option<&mut TileLayer> {
+        let layer = self.layers.iter_mut().find(|layer| layer.name == name)?;
+        self.events.push(TileEvent::LayerChanged(name.into()));
+        Some(layer)
+    }
+
+    /// The map's layers, in the order they were added
+    pub fn layers(&self) -> impl Iterator<Item = &TileLayer> { self.layers.iter() }
+
+    /// Every position with a tile on any layer, or with properties of its own
+    pub fn positions(&self) -> HashSet<(i32, i32)> {
+        self.layers.iter()
+            .flat_map(|layer| layer.tiles().map(|(pos, _)| pos))
+            .chain(self.tile_properties.keys().copied())
+            .collect()
+    }
+
+    /// Removes every layer, with their tiles, and the properties of every position.
+    pub fn clear(&mut self) {
+        self.layers.clear();
+        self.tile_properties.clear();
+        self.events.push(TileEvent::Reset);
+    }
+
+    /// Takes the changes made to the map since this was last called.
+    ///
+    /// This is done by the TileEventSys each update.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, TileEvent> { self.events.drain(..) }
+
+    /// Finds a layer, adding it with default settings if it doesn't exist
+    fn layer_index(&mut self, name: &str) -> usize {
+        match self.layers.iter().position(|layer| layer.name == name) {
+            Some(index) => index,
+            None => {
+                self.layers.push(TileLayer {
+                    name: name.into(), settings: LayerSettings::default(), chunks: HashMap::new(),
+                });
+                self.events.push(TileEvent::LayerChanged(name.into()));
+                self.layers.len() - 1
+            },
+        }
+    }
+
+    /// The chunk containing a tile
+    pub fn chunk_coord(pos: (i32, i32)) -> (i32, i32) {
+        (pos.0.div_euclid(CHUNK_SIZE), pos.1.div_euclid(CHUNK_SIZE))
+    }
+
+    /// The index of a tile within its chunk
+    fn tile_index(pos: (i32, i32)) -> usize {
+        (pos.1.rem_euclid(CHUNK_SIZE) * CHUNK_SIZE + pos.0.rem_euclid(CHUNK_SIZE)) as usize
+    }
+
+    /// Returns the tile on a layer at a position, if there is one.
+    pub fn get_tile(&self, pos: (i32, i32), layer: &str) -> Option<&Arc<SpriteSchema>> {
+        self.layer(layer)?.get(pos)
+    }
+
+    /// Places a tile on a layer, returning the one it replaced.
+    ///
+    /// Placing a tile on a layer the map doesn't have adds the layer, with
+    /// default settings. The tile and its neighbours on the layer are then
+    /// autotiled.
+    pub fn set_tile(&mut self, pos: (i32, i32), layer: &str, schema: Arc<SpriteSchema>)
+            -> Option<Arc<SpriteSchema>> {
+        let index = self.layer_index(layer);
+        let previous = self.put(index, pos, Some(schema));
+        self.autotile_around(pos, index);
+        previous
+    }
+
+    /// Places a tile on a layer as it is, without autotiling it or its neighbours
+    pub(crate) fn place_tile(&mut self, pos: (i32, i32), layer: &str, schema: Arc<SpriteSchema>) {
+        let index = self.layer_index(layer);
+        self.put(index, pos, Some(schema));
+    }
+
+    /// Removes a tile from a layer, autotiling its neighbours.
+    pub fn remove_tile(&mut self, pos: (i32, i32), layer: &str) -> Option<Arc<SpriteSchema>> {
+        let index = self.layers.iter().position(|l| l.name == layer)?;
+        let previous = self.put(index, pos, None)?;
+        self.autotile_around(pos, index);
+        Some(previous)
+    }
+
+    /// Places (or with None, removes) the same tile across a rect of tiles,
+    /// between two corners inclusive.
+    pub fn fill_rect(&mut self, from: (i32, i32), to: (i32, i32), layer: &str,
+                     schema: Option<Arc<SpriteSchema>>) {
+        let index = self.layer_index(layer);
+        let (min, max) = ((from.0.min(to.0), from.1.min(to.1)), (from.0.max(to.0), from.1.max(to.1)));
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                self.put(index, (x, y), schema.clone());
+            }
+        }
+        if self.autotiler.is_some() {
+            for x in min.0-1..=max.0+1 {
+                for y in min.1-1..=max.1+1 {
+                    self.autotile((x, y), index);
+                }
+            }
+        }
+    }
+
+    /// Replaces the tile at `start`, and every tile of the same sprite joined to
+    /// it by an edge, with another tile (or with None, removes them).
+    ///
+    /// Filling an empty tile fills the empty space around it, as far as the
+    /// chunks of the map reach on any layer.
+    /// Returns how many tiles were filled.
+    pub fn flood_fill(&mut self, start: (i32, i32), layer: &str, schema: Option<Arc<SpriteSchema>>)
+            -> usize {
+        let (min, max) = match self.extent() { Some(extent) => extent, None => return 0 };
+        let index = self.layer_index(layer);
+        let tiles = &self.layers[index];
+        let target = tiles.get(start).cloned();
+        let same = |a: Option<&Arc<SpriteSchema>>, b: Option<&Arc<SpriteSchema>>| match (a, b) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        let within = |pos: (i32, i32)| pos.0 >= min.0 && pos.0 <= max.0 && pos.1 >= min.1 && pos.1 <= max.1;
+        if !within(start) || same(target.as_ref(), schema.as_ref()) { return 0; }
+
+        let mut region = HashSet::from([start]);
+        let mut open = VecDeque::from([start]);
+        while let Some((x, y)) = open.pop_front() {
+            for next in [(x, y + 1), (x + 1, y), (x, y - 1), (x - 1, y)] {
+                if within(next) && !region.contains(&next) && same(tiles.get(next), target.as_ref()) {
+                    region.insert(next);
+                    open.push_back(next);
+                }
+            }
+        }
+
+        for pos in region.iter() {
+            self.put(index, *pos, schema.clone());
+        }
+        if self.autotiler.is_some() {
+            let around: HashSet<(i32, i32)> = region.iter()
+                .flat_map(|(x, y)| (x-1..=x+1).flat_map(move |nx| (y-1..=y+1).map(move |ny| (nx, ny))))
+                .collect();
+            for pos in around {
+                self.autotile(pos, index);
+            }
+        }
+        region.len()
+    }
+
+    /// Places the tiles of a chunk, as (layer, position, sprite), autotiling
+    /// them and the tiles around the chunk.
+    ///
+    /// Tiles outside the chunk are placed as well, but the tiles around them
+    /// aren't autotiled.
+    pub fn load_chunk<'a>(&mut self, coord: (i32, i32),
+                          tiles: impl IntoIterator<Item = (&'a str, (i32, i32), Arc<SpriteSchema>)>) {
+        let mut layers = HashSet::new();
+        for (layer, pos, schema) in tiles {
+            let index = self.layer_index(layer);
+            self.put(index, pos, Some(schema));
+            layers.insert(index);
+        }
+        if self.autotiler.is_some() {
+            let (min_x, min_y) = (coord.0 * CHUNK_SIZE, coord.1 * CHUNK_SIZE);
+            for index in layers {
+                for x in min_x-1..=min_x+CHUNK_SIZE {
+                    for y in min_y-1..=min_y+CHUNK_SIZE {
+                        self.autotile((x, y), index);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes every layer's tiles within a chunk, freeing the chunk.
+    ///
+    /// The tiles around the chunk aren't autotiled, as they'd only change
+    /// back once the chunk is loaded again.
+    /// Returns how many tiles were removed.
+    pub fn unload_chunk(&mut self, coord: (i32, i32)) -> usize {
+        let mut removed = 0;
+        for layer in self.layers.iter_mut() {
+            let chunk = match layer.chunks.remove(&coord) { Some(chunk) => chunk, None => continue };
+            for ((x, y), schema) in chunk.tiles() {
+                self.events.push(TileEvent::Changed {
+                    layer: layer.name.clone(), pos: (coord.0 * CHUNK_SIZE + x, coord.1 * CHUNK_SIZE + y),
+                    previous: Some(schema.clone()), current: None,
+                });
+                removed += 1;
+            }
+        }
+        removed
+    }
+
+    /// The inclusive (min, max) range of tiles covered by the chunks of every layer
+    fn extent(&self) -> Option<((i32, i32), (i32, i32))> {
+        self.layers.iter()
+            .flat_map(|layer| layer.chunks.keys())
+            .map(|(cx, cy)| ((cx * CHUNK_SIZE, cy * CHUNK_SIZE),
+                             ((cx + 1) * CHUNK_SIZE - 1, (cy + 1) * CHUNK_SIZE - 1)))
+            .reduce(|(a_min, a_max), (b_min, b_max)| (
+                (a_min.0.min(b_min.0), a_min.1.min(b_min.1)),
+                (a_max.0.max(b_max.0), a_max.1.max(b_max.1)),
+            ))
+    }
+
+    /// Places (or removes) a tile of a layer, recording the change
+    fn put(&mut self, index: usize, pos: (i32, i32), schema: Option<Arc<SpriteSchema>>)
+            -> Option<Arc<SpriteSchema>> {
+        let layer = &mut self.layers[index];
+        let unchanged = match (layer.get(pos), schema.as_ref()) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        if unchanged { return schema; }
+
+        let previous = layer.put(pos, schema.clone());
+        self.events.push(TileEvent::Changed {
+            layer: layer.name.clone(), pos, previous: previous.clone(), current: schema,
+        });
+        previous
+    }
+
+    /// A property of a position, from those given to the position itself or
+    /// else from the sprite of the topmost tile there which has it.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use stoneng::model::{spritesheet::SpriteSheet, tiled::Property, tilemap::*};
+    /// # let img_data = include_bytes!("./tilemap.rs");
+    /// let sheet = Arc::new(SpriteSheet::new(r#"SpriteSheet(sheet_width: 100, tile_width: 10, sprites: {
+    ///     "mud":      (root: 0, properties: { "move_cost": Float(3.0), "footstep_sound": String("squelch") }),
+    ///     "boulder":  (root: 1, properties: { "solid": Bool(true), "opaque": Bool(false) }),
+    ///     "hedge":    (root: 2, properties: { "opaque": Bool(true) }),
+    /// })"#, img_data).unwrap());
+    ///
+    /// let mut map = Tilemap::new(sheet.clone());
+    /// map.set_tile((0, 0), "floor", sheet.sprites["mud"].clone());
+    /// map.set_tile((1, 0), "decals", sheet.sprites["boulder"].clone());
+    /// map.set_tile((2, 0), "decals", sheet.sprites["hedge"].clone());
+    ///
+    /// let footsteps = map.tile_property((0, 0), "footstep_sound").and_then(Property::as_str);
+    /// assert_eq!(footsteps, Some("squelch"));
+    /// // Boulders can be seen over, but not walked through, and hedges the opposite
+    /// assert!(map.is_solid((1, 0)) && !map.is_opaque((1, 0)));
+    /// assert!(!map.is_solid((2, 0)) && map.is_opaque((2, 0)));
+    ///
+    /// // A patch of mud which has dried out
+    /// map.set_tile_properties((0, 0), [("footstep_sound".into(), Property::String("crunch".into()))].into());
+    /// assert_eq!(map.tile_property((0, 0), "footstep_sound").and_then(Property::as_str), Some("crunch"));
+    /// assert_eq!(map.tile_property((0, 0), "move_cost").and_then(Property::as_float), Some(3.0));
+    ///
+    /// // Fenced off, which is seen through but not walked through, whatever the tiles
+    /// map.drain_events().for_each(drop);
+    /// map.set_tile_properties((0, 0), [("solid".into(), Property::Bool(true)), ("opaque".into(), Property::Bool(false))].into());
+    /// assert!(map.is_solid((0, 0)) && !map.is_opaque((0, 0)));
+    /// assert!(matches!(map.drain_events().next(), Some(TileEvent::PropertiesChanged((0, 0)))));
+    /// ```
+    pub fn tile_property(&self, pos: (i32, i32), name: &str) -> Option<&Property> {
+        if let Some(property) = self.tile_properties.get(&pos).and_then(|properties| properties.get(name)) {
+            return Some(property);
+        }
+        let mut layers: Vec<&TileLayer> = self.layers.iter().collect();
+        layers.sort_by(|a, b| b.settings.depth.total_cmp(&a.settings.depth));
+        layers.into_iter().find_map(|layer| layer.get(pos)?.property(name))
+    }
+
+    /// The properties given to a position, rather than to the sprites of its tiles
+    pub fn tile_properties(&self, pos: (i32, i32)) -> Option<&Properties> { self.tile_properties.get(&pos) }
+
+    /// Every position given properties of its own, with its properties
+    pub fn tiles_with_properties(&self) -> impl Iterator<Item = ((i32, i32), &Properties)> {
+        self.tile_properties.iter().map(|(pos, properties)| (*pos, properties))
+    }
+
+    /// Gives a position properties of its own, replacing any it had, which
+    /// are kept as its tiles change. Empty properties remove them.
+    pub fn set_tile_properties(&mut self, pos: (i32, i32), properties: Properties) {
+        let previous = if properties.is_empty() {
+            self.tile_properties.remove(&pos)
+        } else {
+            self.tile_properties.insert(pos, properties)
+        };
+        if previous.as_ref() != self.tile_properties.get(&pos) {
+            self.events.push(TileEvent::PropertiesChanged(pos));
+        }
+    }
+
+    /// A boolean property given to a position itself
+    fn position_flag(&self, pos: (i32, i32), name: &str) -> Option<bool> {
+        self.tile_properties.get(&pos)?.get(name)?.as_bool()
+    }
+
+    /// Whether a position is solid, by its own `solid` property, or else by
+    /// any tile there being solid through its sprite's `solid` property or
+    /// otherwise by being on a solid layer
+    pub fn is_solid(&self, pos: (i32, i32)) -> bool {
+        self.position_flag(pos, "solid").unwrap_or_else(|| {
+            self.layers.iter().any(|layer| layer.get(pos).is_some_and(|tile| Self::solid(layer, tile)))
+        })
+    }
+
+    /// Whether a position blocks sight, by its own `opaque` property, or else
+    /// by any tile there with an `opaque` sprite property, or otherwise by
+    /// being solid
+    pub fn is_opaque(&self, pos: (i32, i32)) -> bool {
+        if let Some(opaque) = self.position_flag(pos, "opaque") { return opaque; }
+        let solid = self.position_flag(pos, "solid");
+        let mut tiles = self.layers.iter().filter_map(|layer| Some((layer, layer.get(pos)?))).peekable();
+        if tiles.peek().is_none() { return solid.unwrap_or(false); }
+        tiles.any(|(layer, tile)| tile.property("opaque").and_then(Property::as_bool)
+            .unwrap_or_else(|| solid.unwrap_or_else(|| Self::solid(layer, tile))))
+    }
+
+    /// Whether a tile of a layer is solid
+    fn solid(layer: &TileLayer, tile: &SpriteSchema) -> bool {
+        tile.property("solid").and_then(Property::as_bool).unwrap_or(layer.settings.solid)
+    }
+
+    /// Autotiles the map with a set of rules, using the map's sprite sheet.
+    ///
+    /// Every tile already placed is updated to match its neighbours.
+    pub fn set_autotile_rules(&mut self, rules: &AutotileRules) -> Result<(), EngineError> {
+        let sheet = self.spritesheet.as_ref()
+            .ok_or_else(|| EngineError::AutotileError("the tilemap has no spritesheet".into()))?;
+        self.autotiler = Some(Arc::new(rules.resolve(sheet)?));
+
+        for index in 0..self.layers.len() {
+            let placed: Vec<(i32, i32)> = self.layers[index].tiles().map(|(pos, _)| pos).collect();
+            for pos in placed {
+                self.autotile(pos, index);
+            }
+        }
+        Ok(())
+    }
+
+    /// Autotiles a tile, and its neighbours, of a layer
+    fn autotile_around(&mut self, pos: (i32, i32), layer: usize) {
+        if self.autotiler.is_none() { return; }
+        for x in pos.0-1..=pos.0+1 {
+            for y in pos.1-1..=pos.1+1 {
+                self.autotile((x, y), layer);
+            }
+        }
+    }
+
+    /// Replaces a tile with the sprite its autotile rule picks, if it has one
+    fn autotile(&mut self, pos: (i32, i32), layer: usize) {
+        let autotiler = match self.autotiler.clone() { Some(autotiler) => autotiler, None => return };
+        let tiles = &self.layers[layer];
+        let picked = tiles.get(pos).and_then(|current| {
+            autotiler.pick(current, &tiles.name, |(x, y)| tiles.get((pos.0 + x, pos.1 + y)))
+                .filter(|picked| !Arc::ptr_eq(picked, current))
+        });
+        if let Some(picked) = picked {
+            self.put(layer, pos, Some(picked));
+        }
+    }
+
+    /// The width and height of a tile in the world, its sheet's `tile_width` at the map's scale.
+    pub fn tile_size(&self) -> (f32, f32) {
+        let width = self.spritesheet.as_ref().map_or(TILE_SIZE, |sheet| sheet.tile_width as f32);
+        (width * self.scale.0, width * self.scale.1)
+    }
+
+    /// The world position of a tile's center.
+    pub fn tile_to_world(&self, pos: (i32, i32)) -> (f32, f32) { self.layout.to_world(pos, self.tile_size()) }
+
+    /// The tile covering a world position.
+    pub fn world_to_tile(&self, point: (f32, f32)) -> (i32, i32) { self.layout.to_tile(point, self.tile_size()) }
+
+    /// The corners of the area a tile covers in the world, anticlockwise.
+    pub fn tile_shape(&self, pos: (i32, i32)) -> Vec<(f32, f32)> {
+        let center = self.tile_to_world(pos);
+        self.layout.corners(self.tile_size()).into_iter()
+            .map(|(x, y)| (center.0 + x, center.1 + y))
+            .collect()
+    }
+
+    /// The bounds of the area a tile covers in the world.
+    pub fn tile_bounds(&self, pos: (i32, i32)) -> Rect {
+        let (w, h) = self.tile_size();
+        let half_height = match self.layout {
+            TileLayout::Isometric | TileLayout::Staggered => h / 4.0,
+            _ => h / 2.0,
+        };
+        Rect::from_center(self.tile_to_world(pos), (w / 2.0, half_height))
+    }
+
+    /// The inclusive (min, max) range of tiles which overlap a world area.
+    ///
+    /// For layouts other than `TileLayout::Orthogonal` the range may include
+    /// tiles around the area as well.
+    pub fn tiles_in(&self, area: &Rect) -> ((i32, i32), (i32, i32)) {
+        if self.layout == TileLayout::Orthogonal {
+            return (self.world_to_tile((area.left(), area.bottom())), self.world_to_tile((area.right(), area.top())));
+        }
+        // Tile positions aren't aligned with the world, so take every corner,
+        // and a tile around for the tiles only partly within the area
+        let corners = [(area.left(), area.bottom()), (area.right(), area.bottom()),
+                       (area.right(), area.top()), (area.left(), area.top())]
+            .map(|corner| self.world_to_tile(corner));
+        let min = corners.iter().fold((i32::MAX, i32::MAX), |min, pos| (min.0.min(pos.0), min.1.min(pos.1)));
+        let max = corners.iter().fold((i32::MIN, i32::MIN), |max, pos| (max.0.max(pos.0), max.1.max(pos.1)));
+        ((min.0 - 1, min.1 - 1), (max.0 + 1, max.1 + 1))
+    }
+
+    /// The tiles sharing an edge with a tile, in the map's layout.
+    pub fn neighbours(&self, pos: (i32, i32)) -> Vec<(i32, i32)> { self.layout.neighbours(pos) }
+
+    /// The fewest steps between neighbouring tiles from one tile to another.
+    pub fn tile_distance(&self, a: (i32, i32), b: (i32, i32)) -> i32 { self.layout.distance(a, b) }
+
+    /// Builds the tiles of a text level into the map.
+    ///
+    /// Each character is one cell, looked up in the legend. The first line is
+    /// the top row and the bottom left cell is tile (0, 0). Blank lines before
+    /// and after the level are ignored.
     ///
     /// This is used to provide a (de)serializable format for levels
     /// and allows for text-based level building tooling.
-    pub fn populate_from_string(&mut self, level: String, atlas_map: HashMap<char, SpriteSchema>) {
-        if level.is_empty() {return;}
+    /// Nothing is changed if the level is invalid, the error giving the 1-based
+    /// line and column of the problem.
+    ///
+    /// # Example
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use stoneng::{EngineError, model::{spritesheet::SpriteSheet, tilemap::*}};
+    /// # let img_data = include_bytes!("./tilemap.rs");
+    /// # let sheet = SpriteSheet::new(r#"SpriteSheet(sheet_width: 100, tile_width: 10, sprites: {
+    /// #     "grass": (root: 0, variants: { "dirt": (root: 1) }),
+    /// #     "brick": (root: 2),
+    /// # })"#, img_data).unwrap();
+    /// let mut legend = Legend::new();
+    /// legend.insert('.', vec![TileDef::new("grass", "floor")]);
+    /// legend.insert(',', vec![TileDef::new("grass", "floor").with_variant("dirt")]);
+    /// legend.insert('W', vec![
+    ///     TileDef::new("grass", "floor"),
+    ///     TileDef::new("brick", "walls"),
+    /// ]);
+    ///
+    /// let mut map = Tilemap::new(Arc::new(sheet));
+    /// map.populate_from_string("
+    /// WWWW
+    /// W.,W
+    /// WWWW
+    /// ", &legend).unwrap();
+    /// assert!(map.is_solid((0, 0)));
+    /// assert_eq!(map.get_tile((2, 1), "floor").unwrap().root, 1);
+    ///
+    /// // Errors point at the offending character
+    /// match map.populate_from_string("W.W\nWxW", &legend) {
+    ///     Err(EngineError::LevelParseError { line: 2, column: 2, .. }) => {},
+    ///     other => panic!("{:?}", other),
+    /// }
+    /// assert!(map.populate_from_string("WWW\nWW", &legend).is_err());
+    /// ```
+    pub fn populate_from_string(&mut self, level: &str, legend: &Legend) -> Result<(), EngineError> {
+        let lines: Vec<(usize, &str)> = level.lines().enumerate().collect();
+        let first = lines.iter().position(|(_, l)| !l.trim().is_empty());
+        let last = lines.iter().rposition(|(_, l)| !l.trim().is_empty());
+        let rows = match (first, last) {
+            (Some(first), Some(last)) => &lines[first..=last],
+            _ => return Ok(()),
+        };
+
+        // Check the whole level before changing anything
+        let level_width = rows[0].1.chars().count();
+        let mut resolved: HashMap<char, Vec<(&str, Arc<SpriteSchema>)>> = HashMap::new();
+        let mut cells = Vec::with_capacity(level_width * rows.len());
+        for (row, (line_no, line)) in rows.iter().enumerate() {
+            let error = |column: usize, reason: String| EngineError::LevelParseError {
+                line: line_no + 1, column, reason
+            };
 
-        let map: Vec<Vec<SpriteSchema>> = vec![];
-        
-        let mut level_width = 0;
-        for line in level.lines() {
             // Ensure standard line width on level
-            if level_width != line.len() {
-                if level_width == 0 {
-                    level_width = line.len() 
-                } else {
-                    // Malformed level
+            let width = line.chars().count();
+            if width != level_width {
+                return Err(error(width.min(level_width) + 1, format!(
+                    "line is {} characters wide, expected {}", width, level_width
+                )));
+            }
+
+            let y = (rows.len() - 1 - row) as i32;
+            for (x, c) in line.chars().enumerate() {
+                if let Entry::Vacant(entry) = resolved.entry(c) {
+                    let defs = legend.get(&c)
+                        .ok_or_else(|| error(x + 1, format!("'{}' is not in the legend", c)))?;
+                    let tiles = defs.iter()
+                        .map(|def| self.resolve(def).map(|schema| (&def.layer[..], schema)))
+                        .collect::<Result<Vec<_>, String>>()
+                        .map_err(|reason| error(x + 1, format!("'{}': {}", c, reason)))?;
+                    entry.insert(tiles);
                 }
+                cells.push(((x as i32, y), c));
+            }
+        }
+
+        for (pos, c) in cells.iter() {
+            for (layer, schema) in resolved[c].iter() {
+                let index = self.layer_index(layer);
+                self.put(index, *pos, Some(schema.clone()));
             }
+        }
+        for (pos, c) in cells {
+            for (layer, _) in resolved[&c].iter() {
+                let index = self.layer_index(layer);
+                self.autotile_around(pos, index);
+            }
+        }
+        Ok(())
+    }
 
-            
+    /// Finds the sprite schema a tile definition refers to
+    pub(crate) fn resolve(&self, def: &TileDef) -> Result<Arc<SpriteSchema>, String> {
+        let sheet = self.spritesheet.as_ref().ok_or("the tilemap has no spritesheet")?;
+        let sprite = sheet.sprites.get(&def.sprite)
+            .ok_or_else(|| format!("no sprite named \"{}\"", def.sprite))?;
+        match &def.variant {
+            None => Ok(sprite.clone()),
+            Some(variant) => sprite.variants.get(variant).cloned()
+                .ok_or_else(|| format!("sprite \"{}\" has no variant \"{}\"", def.sprite, variant)),
         }
     }
 }
-
@@ -0,0 +1,51 @@
+use rand::Rng;
+
+use super::{Generator, Grid, seeded, WALL, FLOOR};
+
+/// Generates caves by smoothing random noise with a cellular automaton.
+///
+/// Each cell starts as a wall with the `fill` chance, then each step a floor
+/// becomes a wall with at least `birth` wall neighbours and a wall stays one
+/// with at least `survive`, the edge of the grid counting as wall. Any cave
+/// not joined to the largest is filled in, so the caves are always connected.
+#[derive(Debug, Clone, Copy)]
+pub struct CaveGenerator {
+    pub fill:       f32,
+    pub steps:      u32,
+    pub birth:      usize,
+    pub survive:    usize,
+}
+impl Default for CaveGenerator {
+    fn default() -> Self { Self { fill: 0.45, steps: 5, birth: 5, survive: 4 } }
+}
+impl CaveGenerator {
+    pub fn with_fill(self, fill: f32) -> Self { Self { fill, ..self } }
+    pub fn with_steps(self, steps: u32) -> Self { Self { steps, ..self } }
+    pub fn with_rule(self, birth: usize, survive: usize) -> Self { Self { birth, survive, ..self } }
+}
+impl Generator for CaveGenerator {
+    fn generate(&self, width: usize, height: usize, seed: u64) -> Grid {
+        let mut rng = seeded(seed);
+        let mut grid = Grid::new(width, height, WALL);
+        let (w, h) = (width as i32, height as i32);
+        for x in 1..w - 1 {
+            for y in 1..h - 1 {
+                let cell = if rng.gen_bool(self.fill.clamp(0.0, 1.0) as f64) { WALL } else { FLOOR };
+                grid.set((x, y), cell);
+            }
+        }
+
+        for _ in 0..self.steps {
+            let mut next = grid.clone();
+            for (pos, cell) in grid.cells() {
+                let walls = grid.neighbours(pos, true, |n| n == WALL);
+                let wall = if cell == WALL { walls >= self.survive } else { walls >= self.birth };
+                next.set(pos, if wall { WALL } else { FLOOR });
+            }
+            grid = next;
+        }
+
+        grid.keep_largest_region(|cell| cell == FLOOR, WALL);
+        grid
+    }
+}
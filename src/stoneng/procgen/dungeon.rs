@@ -0,0 +1,99 @@
+use rand::Rng;
+
+use super::{Generator, Grid, seeded, WALL, FLOOR};
+
+/// A rect of cells, by its bottom left cell and size
+#[derive(Debug, Clone, Copy)]
+struct Area {
+    x:  i32,
+    y:  i32,
+    w:  i32,
+    h:  i32,
+}
+impl Area {
+    fn center(&self) -> (i32, i32) { (self.x + self.w / 2, self.y + self.h / 2) }
+}
+
+/// Generates rooms joined by corridors, by binary space partitioning.
+///
+/// The grid is split in two, across its longer side, and each half split
+/// again until no part can be split without becoming narrower than `min_leaf`.
+/// A room of at least `min_room` cells across is carved within each part, and
+/// the two halves of every split are joined by a corridor between rooms.
+#[derive(Debug, Clone, Copy)]
+pub struct DungeonGenerator {
+    pub min_leaf:   i32,
+    pub min_room:   i32,
+}
+impl Default for DungeonGenerator {
+    fn default() -> Self { Self { min_leaf: 10, min_room: 4 } }
+}
+impl DungeonGenerator {
+    pub fn with_min_leaf(self, min_leaf: i32) -> Self { Self { min_leaf, ..self } }
+    pub fn with_min_room(self, min_room: i32) -> Self { Self { min_room, ..self } }
+
+    /// Splits an area until it's too small, carving its rooms, and returns a
+    /// room center from within it to join it to the rest
+    fn split(&self, grid: &mut Grid, area: Area, rng: &mut impl Rng) -> (i32, i32) {
+        let across = if area.w == area.h { rng.gen_bool(0.5) } else { area.w > area.h };
+        let length = if across { area.w } else { area.h };
+        // Any smaller and a part would have no room inside its walls
+        let min_leaf = self.min_leaf.max(3);
+
+        if length < min_leaf * 2 {
+            return self.carve_room(grid, area, rng);
+        }
+
+        let at = rng.gen_range(min_leaf..=length - min_leaf);
+        let (a, b) = if across {
+            (Area { w: at, ..area }, Area { x: area.x + at, w: area.w - at, ..area })
+        } else {
+            (Area { h: at, ..area }, Area { y: area.y + at, h: area.h - at, ..area })
+        };
+        let (from, to) = (self.split(grid, a, rng), self.split(grid, b, rng));
+        Self::carve_corridor(grid, from, to, rng.gen_bool(0.5));
+        if rng.gen_bool(0.5) { from } else { to }
+    }
+
+    /// Carves a room within an area, leaving a wall around it, returning its center
+    fn carve_room(&self, grid: &mut Grid, area: Area, rng: &mut impl Rng) -> (i32, i32) {
+        // The space within the area's walls
+        let (max_w, max_h) = ((area.w - 2).max(1), (area.h - 2).max(1));
+        let w = rng.gen_range(self.min_room.clamp(1, max_w)..=max_w);
+        let h = rng.gen_range(self.min_room.clamp(1, max_h)..=max_h);
+        let room = Area {
+            x: area.x + 1 + rng.gen_range(0..=max_w - w),
+            y: area.y + 1 + rng.gen_range(0..=max_h - h),
+            w, h,
+        };
+        for x in room.x..room.x + room.w {
+            for y in room.y..room.y + room.h {
+                grid.set((x, y), FLOOR);
+            }
+        }
+        room.center()
+    }
+
+    /// Carves an L shaped corridor between two cells
+    fn carve_corridor(grid: &mut Grid, from: (i32, i32), to: (i32, i32), x_first: bool) {
+        let corner = if x_first { (to.0, from.1) } else { (from.0, to.1) };
+        for (a, b) in [(from, corner), (corner, to)] {
+            for x in a.0.min(b.0)..=a.0.max(b.0) {
+                for y in a.1.min(b.1)..=a.1.max(b.1) {
+                    grid.set((x, y), FLOOR);
+                }
+            }
+        }
+    }
+}
+impl Generator for DungeonGenerator {
+    fn generate(&self, width: usize, height: usize, seed: u64) -> Grid {
+        let mut rng = seeded(seed);
+        let mut grid = Grid::new(width, height, WALL);
+        if width < 3 || height < 3 { return grid; }
+
+        let area = Area { x: 0, y: 0, w: width as i32, h: height as i32 };
+        self.split(&mut grid, area, &mut rng);
+        grid
+    }
+}
@@ -0,0 +1,224 @@
+//! Seeded generators for tilemap levels.
+//!
+//! Each generator fills a `Grid` of characters, the same grid every time for
+//! the same seed, which is then checked and loaded into a `Tilemap`.
+pub mod caves;
+pub mod dungeon;
+pub mod walk;
+pub mod terrain;
+
+pub use caves::CaveGenerator;
+pub use dungeon::DungeonGenerator;
+pub use walk::WalkGenerator;
+pub use terrain::TerrainGenerator;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use rand::{SeedableRng, rngs::StdRng, seq::SliceRandom};
+
+use crate::EngineError;
+use crate::model::{spritesheet::SpriteSchema, tilemap::{Tilemap, Legend}};
+
+/// The cell generators use for solid rock.
+pub const WALL: char = '#';
+/// The cell generators use for open ground.
+pub const FLOOR: char = '.';
+
+/// Generates a level, as a grid of characters.
+pub trait Generator {
+    /// Generates a width x height grid, the same each time for the same seed.
+    fn generate(&self, width: usize, height: usize, seed: u64) -> Grid;
+}
+
+/// Creates the random number generator of a seed, for generators to share.
+pub(crate) fn seeded(seed: u64) -> StdRng { StdRng::seed_from_u64(seed) }
+
+/// A generated level, one character per cell.
+///
+/// Cells use tile coordinates, with (0, 0) the bottom left cell, and are
+/// written out with the top row first as for `Tilemap::populate_from_string`.
+///
+/// # Example
+/// ```
+/// # use stoneng::procgen::*;
+/// let caves = CaveGenerator::default().generate(48, 32, 1234);
+/// // The same seed always gives the same level
+/// assert_eq!(caves, CaveGenerator::default().generate(48, 32, 1234));
+///
+/// let open = |cell| cell == FLOOR;
+/// assert!(caves.is_connected(open));
+/// let spawns = caves.spawn_points(4, 6.0, open, 1234);
+/// assert!(spawns.iter().all(|pos| caves.get(*pos) == Some(FLOOR)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grid {
+    width:  usize,
+    height: usize,
+    cells:  Vec<char>,
+}
+impl Grid {
+    /// Creates a grid with every cell set to `fill`.
+    pub fn new(width: usize, height: usize, fill: char) -> Self {
+        Self { width, height, cells: vec![fill; width * height] }
+    }
+
+    pub fn width(&self) -> usize { self.width }
+    pub fn height(&self) -> usize { self.height }
+
+    pub fn contains(&self, pos: (i32, i32)) -> bool {
+        pos.0 >= 0 && pos.1 >= 0 && (pos.0 as usize) < self.width && (pos.1 as usize) < self.height
+    }
+
+    /// Returns the cell at a position, if it's within the grid.
+    pub fn get(&self, pos: (i32, i32)) -> Option<char> {
+        if !self.contains(pos) { return None; }
+        Some(self.cells[self.index(pos)])
+    }
+
+    /// Sets the cell at a position, doing nothing outside the grid.
+    pub fn set(&mut self, pos: (i32, i32), cell: char) {
+        if self.contains(pos) {
+            let index = self.index(pos);
+            self.cells[index] = cell;
+        }
+    }
+
+    fn index(&self, pos: (i32, i32)) -> usize { pos.1 as usize * self.width + pos.0 as usize }
+
+    /// Every cell, by position
+    pub fn cells(&self) -> impl Iterator<Item = ((i32, i32), char)> + '_ {
+        let width = self.width;
+        self.cells.iter().enumerate().map(move |(i, cell)| (((i % width) as i32, (i / width) as i32), *cell))
+    }
+
+    /// How many cells hold a character
+    pub fn count(&self, cell: char) -> usize { self.cells.iter().filter(|c| **c == cell).count() }
+
+    /// How many of a cell's eight neighbours pass a test, counting those off
+    /// the grid as `outside`.
+    pub fn neighbours(&self, pos: (i32, i32), outside: bool, test: impl Fn(char) -> bool) -> usize {
+        let mut count = 0;
+        for x in pos.0-1..=pos.0+1 {
+            for y in pos.1-1..=pos.1+1 {
+                if (x, y) == pos { continue; }
+                if self.get((x, y)).map_or(outside, &test) { count += 1; }
+            }
+        }
+        count
+    }
+
+    /// The groups of passable cells joined by their edges, largest first.
+    pub fn regions(&self, passable: impl Fn(char) -> bool) -> Vec<Vec<(i32, i32)>> {
+        let mut seen = HashSet::new();
+        let mut regions = vec![];
+        for (start, cell) in self.cells() {
+            if !passable(cell) || !seen.insert(start) { continue; }
+
+            let mut region = vec![start];
+            let mut open = VecDeque::from([start]);
+            while let Some((x, y)) = open.pop_front() {
+                for next in [(x, y + 1), (x + 1, y), (x, y - 1), (x - 1, y)] {
+                    if self.get(next).is_some_and(&passable) && seen.insert(next) {
+                        region.push(next);
+                        open.push_back(next);
+                    }
+                }
+            }
+            regions.push(region);
+        }
+        regions.sort_by_key(|region| std::cmp::Reverse(region.len()));
+        regions
+    }
+
+    /// Whether every passable cell can be reached from every other.
+    pub fn is_connected(&self, passable: impl Fn(char) -> bool) -> bool {
+        self.regions(passable).len() <= 1
+    }
+
+    /// Fills every passable region but the largest, so that the level is connected.
+    ///
+    /// Returns how many cells were filled.
+    pub fn keep_largest_region(&mut self, passable: impl Fn(char) -> bool, fill: char) -> usize {
+        let regions = self.regions(passable);
+        let isolated: Vec<(i32, i32)> = regions.into_iter().skip(1).flatten().collect();
+        for pos in isolated.iter() {
+            self.set(*pos, fill);
+        }
+        isolated.len()
+    }
+
+    /// Picks up to `count` spawn points, at least `min_distance` cells apart.
+    ///
+    /// Spawns are only placed within the largest passable region, on cells
+    /// whose eight neighbours are all passable, so that nothing spawns
+    /// wedged into a corner. Fewer are returned if no more fit.
+    pub fn spawn_points(&self, count: usize, min_distance: f32, passable: impl Fn(char) -> bool, seed: u64)
+            -> Vec<(i32, i32)> {
+        let mut candidates: Vec<(i32, i32)> = match self.regions(&passable).into_iter().next() {
+            Some(region) => region.into_iter()
+                .filter(|pos| self.neighbours(*pos, false, &passable) == 8)
+                .collect(),
+            None => return vec![],
+        };
+        // Regions are found in a fixed order, so the shuffle alone decides the spawns
+        candidates.sort_unstable();
+        candidates.shuffle(&mut seeded(seed));
+
+        let mut spawns: Vec<(i32, i32)> = Vec::with_capacity(count);
+        for pos in candidates {
+            if spawns.len() == count { break; }
+            let far_enough = spawns.iter().all(|other| {
+                let (dx, dy) = ((pos.0 - other.0) as f32, (pos.1 - other.1) as f32);
+                dx * dx + dy * dy >= min_distance * min_distance
+            });
+            if far_enough { spawns.push(pos); }
+        }
+        spawns
+    }
+
+    /// Writes the grid out as a text level, the top row first.
+    pub fn to_level_string(&self) -> String {
+        (0..self.height).rev()
+            .map(|y| self.cells[y * self.width..(y + 1) * self.width].iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Places the grid's tiles into a map through a legend, with its bottom
+    /// left cell at `origin`.
+    ///
+    /// As with `Tilemap::populate_from_string`, nothing is changed if a cell
+    /// isn't in the legend, the error giving its line and column.
+    pub fn populate(&self, tilemap: &mut Tilemap, origin: (i32, i32), legend: &Legend) -> Result<(), EngineError> {
+        let mut resolved: HashMap<char, Vec<(&str, Arc<SpriteSchema>)>> = HashMap::new();
+        for (pos, cell) in self.cells() {
+            if resolved.contains_key(&cell) { continue; }
+            let error = |reason: String| EngineError::LevelParseError {
+                line: self.height - pos.1 as usize, column: pos.0 as usize + 1, reason
+            };
+            let defs = legend.get(&cell).ok_or_else(|| error(format!("'{}' is not in the legend", cell)))?;
+            let tiles = defs.iter()
+                .map(|def| tilemap.resolve(def).map(|schema| (&def.layer[..], schema)))
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(|reason| error(format!("'{}': {}", cell, reason)))?;
+            resolved.insert(cell, tiles);
+        }
+        self.populate_with(tilemap, origin, |_, cell| resolved[&cell].clone());
+        Ok(())
+    }
+
+    /// Places the grid's tiles into a map, with its bottom left cell at `origin`.
+    ///
+    /// `mapping` gives the (layer, sprite) of each tile to place at a cell,
+    /// from its position within the grid and its character, so that cells
+    /// can vary their sprites.
+    pub fn populate_with<'a, F>(&self, tilemap: &mut Tilemap, origin: (i32, i32), mut mapping: F)
+            where F: FnMut((i32, i32), char) -> Vec<(&'a str, Arc<SpriteSchema>)> {
+        for (pos, cell) in self.cells() {
+            for (layer, schema) in mapping(pos, cell) {
+                tilemap.set_tile((origin.0 + pos.0, origin.1 + pos.1), layer, schema);
+            }
+        }
+    }
+}
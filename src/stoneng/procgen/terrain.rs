@@ -0,0 +1,98 @@
+use super::{Generator, Grid};
+
+/// Generates open terrain, such as water, beaches, grassland and mountains,
+/// from layered value noise.
+///
+/// The noise is summed over `octaves`, each half the size and `persistence`
+/// times the strength of the last, the first having features about `scale`
+/// cells across. Each cell's noise, from 0 to 1, picks the first band whose
+/// height it's beneath.
+///
+/// # Example
+/// ```
+/// # use stoneng::procgen::*;
+/// let islands = TerrainGenerator::default()
+///     .with_bands(vec![(0.45, '~'), (0.5, ','), (1.0, '.')])
+///     .generate(64, 64, 7);
+/// assert!(islands.cells().all(|(_, cell)| "~,.".contains(cell)));
+/// assert!(islands.count('~') > 0 && islands.count('.') > 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TerrainGenerator {
+    pub scale:          f32,
+    pub octaves:        u32,
+    pub persistence:    f32,
+    /// The (height, cell) of each band, from lowest to highest
+    pub bands:          Vec<(f32, char)>,
+}
+impl Default for TerrainGenerator {
+    fn default() -> Self {
+        Self {
+            scale: 16.0, octaves: 4, persistence: 0.5,
+            bands: vec![(0.35, '~'), (0.42, ','), (0.7, '.'), (0.85, 'T'), (1.0, '^')],
+        }
+    }
+}
+impl TerrainGenerator {
+    pub fn with_scale(self, scale: f32) -> Self { Self { scale, ..self } }
+    pub fn with_octaves(self, octaves: u32, persistence: f32) -> Self { Self { octaves, persistence, ..self } }
+    pub fn with_bands(self, bands: Vec<(f32, char)>) -> Self { Self { bands, ..self } }
+
+    /// The layered noise at a point, from 0 to 1
+    pub fn noise(&self, x: f32, y: f32, seed: u64) -> f32 {
+        let (mut total, mut strength, mut max) = (0.0, 1.0, 0.0);
+        let mut frequency = 1.0 / self.scale.max(f32::EPSILON);
+        for octave in 0..self.octaves.max(1) {
+            total += value_noise(x * frequency, y * frequency, seed.wrapping_add(octave as u64)) * strength;
+            max += strength;
+            strength *= self.persistence;
+            frequency *= 2.0;
+        }
+        total / max
+    }
+
+    /// The band of the cell at a position, which is seamless however far the
+    /// position is from the origin, for generating endless worlds a piece at a time
+    pub fn cell(&self, (x, y): (i32, i32), seed: u64) -> char {
+        let height = self.noise(x as f32, y as f32, seed);
+        self.bands.iter()
+            .find(|(top, _)| height < *top)
+            .or(self.bands.last())
+            .map_or(' ', |(_, cell)| *cell)
+    }
+}
+impl Generator for TerrainGenerator {
+    fn generate(&self, width: usize, height: usize, seed: u64) -> Grid {
+        let mut grid = Grid::new(width, height, ' ');
+        for x in 0..width as i32 {
+            for y in 0..height as i32 {
+                grid.set((x, y), self.cell((x, y), seed));
+            }
+        }
+        grid
+    }
+}
+
+/// Smoothly blends the random values of the lattice points around a point
+fn value_noise(x: f32, y: f32, seed: u64) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    // Ease in and out, hiding the lattice
+    let (sx, sy) = (fx * fx * (3.0 - 2.0 * fx), fy * fy * (3.0 - 2.0 * fy));
+
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let bottom = lerp(lattice(ix, iy, seed), lattice(ix + 1, iy, seed), sx);
+    let top = lerp(lattice(ix, iy + 1, seed), lattice(ix + 1, iy + 1, seed), sx);
+    lerp(bottom, top, sy)
+}
+
+/// The random value, from 0 to 1, of a lattice point
+fn lattice(x: i64, y: i64, seed: u64) -> f32 {
+    // SplitMix64's finalizer, over the point and seed
+    let mut hash = seed ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F);
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    hash ^= hash >> 31;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
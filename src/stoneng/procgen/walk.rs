@@ -0,0 +1,55 @@
+use rand::Rng;
+
+use super::{Generator, Grid, seeded, WALL, FLOOR};
+
+/// Generates winding, open caverns with a drunkard's walk.
+///
+/// Each of the `walkers` sets off from the middle of the grid, stumbling in
+/// a random direction each step and carving out floor, until `coverage` of
+/// the cells within the grid's border are floor. As every walker starts in
+/// the same place the caverns are always connected.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkGenerator {
+    pub coverage:   f32,
+    pub walkers:    usize,
+}
+impl Default for WalkGenerator {
+    fn default() -> Self { Self { coverage: 0.4, walkers: 4 } }
+}
+impl WalkGenerator {
+    pub fn with_coverage(self, coverage: f32) -> Self { Self { coverage, ..self } }
+    pub fn with_walkers(self, walkers: usize) -> Self { Self { walkers, ..self } }
+}
+impl Generator for WalkGenerator {
+    fn generate(&self, width: usize, height: usize, seed: u64) -> Grid {
+        let mut rng = seeded(seed);
+        let mut grid = Grid::new(width, height, WALL);
+        if width < 3 || height < 3 { return grid; }
+
+        let (w, h) = (width as i32, height as i32);
+        let inner = (width - 2) * (height - 2);
+        let target = ((inner as f32 * self.coverage.clamp(0.0, 1.0)) as usize).max(1);
+        // Walkers could wander forever on a grid they can barely move in
+        let max_steps = inner * 100;
+
+        let start = (w / 2, h / 2);
+        let mut walkers = vec![start; self.walkers.max(1)];
+        grid.set(start, FLOOR);
+        let mut carved = 1;
+        for _ in 0..max_steps {
+            if carved >= target { break; }
+            for walker in walkers.iter_mut() {
+                let (dx, dy) = [(0, 1), (1, 0), (0, -1), (-1, 0)][rng.gen_range(0..4)];
+                let next = (walker.0 + dx, walker.1 + dy);
+                // Stay within the border
+                if next.0 < 1 || next.1 < 1 || next.0 > w - 2 || next.1 > h - 2 { continue; }
+                *walker = next;
+                if grid.get(next) == Some(WALL) {
+                    grid.set(next, FLOOR);
+                    carved += 1;
+                }
+            }
+        }
+        grid
+    }
+}
@@ -3,11 +3,13 @@
 use crate::EngineError;
 use crate::shader;
 use crate::ecs::component;
+use crate::model::layer::DEPTH_RANGE;
+use crate::model::spritesheet::{AnimationSchema, AnimMode};
 
 use stb::image::LoadResult;
 use std::{
     path::Path,
-    mem::size_of,
+    mem::{size_of, size_of_val},
 };
 use glm::{Vec2, Vec3, Vec4, Mat4};
 use gl::types::*;
@@ -23,7 +25,13 @@ pub struct RenderSprite {
     pub sprite_id:      u32,
     pub sprite_dims:    u8,
     pub sprite_flags:   u8,
-    pub reserved:       u16,
+    /// How many frames the sprite is animated through on the GPU, 0 if it isn't
+    pub anim_frames:    u8,
+    /// The AnimMode of a GPU animation (low 3 bits), and the phase it starts
+    /// at in 32nds of the animation (high 5 bits)
+    pub anim_data:      u8,
+    /// How many seconds each frame of a GPU animation is shown for
+    pub frame_time:     f32,
 }
 impl Default for RenderSprite {
     fn default() -> Self {
@@ -37,11 +45,65 @@ impl Default for RenderSprite {
             sprite_id:    0,
             sprite_dims:  0,
             sprite_flags: 0,
-            reserved:     0,
+            anim_frames:  0,
+            anim_data:    0,
+            frame_time:   0.0,
+        }
+    }
+}
+impl RenderSprite {
+    /// Animates the sprite on the GPU, by the clock of the batch it's drawn in,
+    /// starting `phase` 32nds of the way through the animation.
+    ///
+    /// Each frame is the sprite's width along from the last. As every sprite
+    /// shares the clock, modes which don't loop hold their final frame.
+    pub fn with_animation(self, anim: &AnimationSchema, phase: u8) -> Self {
+        let mode = match anim.mode {
+            AnimMode::Once        => 0,
+            AnimMode::OncePersist => 1,
+            AnimMode::Loop        => 2,
+            AnimMode::LoopReverse => 3,
+            AnimMode::Reverse     => 4,
+        };
+        Self {
+            sprite_id:  anim.root,
+            anim_frames: anim.frames,
+            anim_data:  mode | (phase & 0x1F) << 3,
+            frame_time: anim.frame_time,
+            ..self
         }
     }
 }
 
+/// A set of RenderSprites kept on the GPU, for sprites which rarely change.
+///
+/// Batches are created, updated and drawn by a SpriteRenderer, and must be
+/// deleted by it when no longer needed.
+#[derive(Debug, Default)]
+pub struct SpriteBatch {
+    vao:    GLuint,
+    vbo:    GLuint,
+    len:    usize,
+}
+impl SpriteBatch {
+    pub fn len(&self) -> usize { self.len }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+}
+
+/// Adjustments made to every sprite of a batch as it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchStyle {
+    /// Multiplies the sprites' colour
+    pub tint:   (f32, f32, f32, f32),
+    /// Moves the sprites, in world units, the z moving them in depth
+    pub offset: (f32, f32, f32),
+    /// The clock, in seconds, the sprites' GPU animations are played by
+    pub time:   f32,
+}
+impl Default for BatchStyle {
+    fn default() -> Self { Self { tint: (1.0, 1.0, 1.0, 1.0), offset: (0.0, 0.0, 0.0), time: 0.0 } }
+}
+
 /// The SpriteRenderer is used to draw RenderSprites to the screen.
 ///
 /// It operates by loading an atlas image into a texture on the GPU. It later 
@@ -57,7 +119,7 @@ pub struct SpriteRenderer {
     vao:        GLuint,
     abo:        GLuint,
     tex:        GLuint,
-    uniform_locations:   [GLint; 4],
+    uniform_locations:   [GLint; 6],
 }
 
 impl SpriteRenderer {
@@ -126,40 +188,8 @@ impl SpriteRenderer {
             
 
             // Set up the attribute pointers
-            let stride = size_of::<RenderSprite>() as i32;
-            // Transform
-                // Translation
             gl::BindBuffer(gl::ARRAY_BUFFER, self.abo);
-            gl::EnableVertexAttribArray(0);
-            gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, 0 as *const GLvoid); 
-
-                // Scale
-            let scale_offset = size_of::<f32>() as i32 * 3;
-            gl::EnableVertexAttribArray(1);
-            gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, 
-                                    scale_offset as *const GLvoid); 
-                // Rotation    
-            let rotation_offset = scale_offset + (size_of::<f32>() as i32) * 2;
-            gl::EnableVertexAttribArray(2);
-            gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, stride, 
-                                    rotation_offset as *const GLvoid); 
-            
-            // Color 
-            let color_offset = rotation_offset + (size_of::<f32>() as i32);
-            gl::EnableVertexAttribArray(3);
-            gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, stride, 
-                                    color_offset as *const GLvoid);  
-
-            // Sprite ID
-            let id_offset = color_offset + (size_of::<f32>() as i32) * 4;
-            gl::EnableVertexAttribArray(4);
-            gl::VertexAttribIPointer(4, 1, gl::UNSIGNED_INT, stride, 
-                                     id_offset as *const GLvoid); 
-            // Sprite Data
-            let data_offset = id_offset + (size_of::<u32>() as i32);
-            gl::EnableVertexAttribArray(5);
-            gl::VertexAttribIPointer(5, 1, gl::UNSIGNED_INT, stride, 
-                                     data_offset as *const GLvoid); 
+            Self::set_attributes();
 
             // Find and store the uniform locations
             self.uniform_locations[0] = shader::get_uniform_location(
@@ -168,6 +198,12 @@ impl SpriteRenderer {
                 self.shader, "sheet_width");                       
             self.uniform_locations[2] = shader::get_uniform_location(
                 self.shader, "sheet_tile_w");           
+            self.uniform_locations[3] = shader::get_uniform_location(
+                self.shader, "batch_tint");
+            self.uniform_locations[4] = shader::get_uniform_location(
+                self.shader, "batch_offset");
+            self.uniform_locations[5] = shader::get_uniform_location(
+                self.shader, "time");
 
             // Unbinding
             gl::BindTexture(gl::TEXTURE_2D, 0);
@@ -180,39 +216,163 @@ impl SpriteRenderer {
         Ok(())
     }
     
+    /// Points the bound vertex array's attributes at RenderSprites in the bound array buffer.
+    unsafe fn set_attributes() {
+        let stride = size_of::<RenderSprite>() as i32;
+        // Transform
+            // Translation
+        gl::EnableVertexAttribArray(0);
+        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, stride, 0 as *const GLvoid); 
+
+            // Scale
+        let scale_offset = size_of::<f32>() as i32 * 3;
+        gl::EnableVertexAttribArray(1);
+        gl::VertexAttribPointer(1, 2, gl::FLOAT, gl::FALSE, stride, 
+                                scale_offset as *const GLvoid); 
+            // Rotation    
+        let rotation_offset = scale_offset + (size_of::<f32>() as i32) * 2;
+        gl::EnableVertexAttribArray(2);
+        gl::VertexAttribPointer(2, 2, gl::FLOAT, gl::FALSE, stride, 
+                                rotation_offset as *const GLvoid); 
+        
+        // Color 
+        let color_offset = rotation_offset + (size_of::<f32>() as i32);
+        gl::EnableVertexAttribArray(3);
+        gl::VertexAttribPointer(3, 4, gl::FLOAT, gl::FALSE, stride, 
+                                color_offset as *const GLvoid);  
+
+        // Sprite ID
+        let id_offset = color_offset + (size_of::<f32>() as i32) * 4;
+        gl::EnableVertexAttribArray(4);
+        gl::VertexAttribIPointer(4, 1, gl::UNSIGNED_INT, stride, 
+                                 id_offset as *const GLvoid); 
+        // Sprite Data
+        let data_offset = id_offset + (size_of::<u32>() as i32);
+        gl::EnableVertexAttribArray(5);
+        gl::VertexAttribIPointer(5, 1, gl::UNSIGNED_INT, stride, 
+                                 data_offset as *const GLvoid);
+        // Frame Time
+        let frame_time_offset = data_offset + (size_of::<u32>() as i32);
+        gl::EnableVertexAttribArray(6);
+        gl::VertexAttribPointer(6, 1, gl::FLOAT, gl::FALSE, stride, 
+                                frame_time_offset as *const GLvoid);
+    }
+
+    /// Uploads a set of RenderSprites into a new batch, to be drawn with `render_batches`.
+    pub fn create_batch(&self, sprites: &[RenderSprite]) -> SpriteBatch {
+        let mut batch = SpriteBatch::default();
+        if !self.initialized { return batch; }
+        unsafe {
+            gl::GenVertexArrays(1, &mut batch.vao as *mut GLuint);
+            gl::GenBuffers(1, &mut batch.vbo as *mut GLuint);
+            gl::BindVertexArray(batch.vao);
+            gl::BindBuffer(gl::ARRAY_BUFFER, batch.vbo);
+            Self::set_attributes();
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+            gl::BindVertexArray(0);
+        }
+        self.update_batch(&mut batch, sprites);
+        batch
+    }
+
+    /// Replaces the sprites held by a batch.
+    pub fn update_batch(&self, batch: &mut SpriteBatch, sprites: &[RenderSprite]) {
+        if !self.initialized || batch.vbo == 0 { return; }
+        unsafe {
+            gl::BindBuffer(gl::ARRAY_BUFFER, batch.vbo);
+            gl::BufferData(
+                gl::ARRAY_BUFFER, 
+                size_of_val(sprites) as GLsizeiptr,
+                sprites.as_ptr() as *const GLvoid,
+                gl::STATIC_DRAW
+            );
+            gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+        }
+        batch.len = sprites.len();
+    }
+
+    /// Frees a batch's GPU buffers.
+    pub fn delete_batch(&self, batch: SpriteBatch) {
+        if batch.vao == 0 { return; }
+        unsafe {
+            gl::DeleteBuffers(1, &batch.vbo as *const GLuint);
+            gl::DeleteVertexArrays(1, &batch.vao as *const GLuint);
+        }
+    }
+
+    /// Binds the renderer's program and texture, and sets the uniforms for the camera.
+    unsafe fn begin(&self, window_size: (f32, f32), cam: (f32, f32, f32)) {
+        gl::Enable(gl::BLEND);
+        gl::Enable(gl::DEPTH_TEST);
+
+        gl::UseProgram(self.shader);
+        gl::BindTexture(gl::TEXTURE_2D, self.tex);
+        
+        let (winx, winy) = window_size;
+        gl::Viewport(0, 0, winx as i32, winy as i32);
+        
+        // Set uniforms
+            // view_projection
+        let (near, far) = DEPTH_RANGE;
+        let projection = glm::ortho(0.0, winx, 0.0, winy, near, far);
+        let view: Mat4 = glm::translation(&Vec3::new(-cam.0, -cam.1, -cam.2));
+        let view_projection = projection * view;
+        gl::UniformMatrix4fv(self.uniform_locations[0], 1, gl::FALSE, 
+                             view_projection.as_ptr());
+            
+            // sheet_width
+        gl::Uniform1i(self.uniform_locations[1], 250);
+            // sheet_tile_w
+        gl::Uniform1i(self.uniform_locations[2], 10);
+
+        self.set_style(&BatchStyle::default());
+    }
+
+    /// Sets the uniforms applied to every sprite drawn
+    unsafe fn set_style(&self, style: &BatchStyle) {
+        let (r, g, b, a) = style.tint;
+        gl::Uniform4f(self.uniform_locations[3], r, g, b, a);
+        let (x, y, z) = style.offset;
+        gl::Uniform3f(self.uniform_locations[4], x, y, z);
+        gl::Uniform1f(self.uniform_locations[5], style.time);
+    }
+
+    /// Unbinds everything bound by `begin`.
+    unsafe fn end(&self) {
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::BindVertexArray(0);
+        gl::UseProgram(0);
+    }
+
+    /// Draws a set of previously uploaded batches to the screen, each with its style.
+    pub fn render_batches<'b, I>(&self, batches: I, window_size: (f32, f32), cam: (f32, f32, f32))
+            where I: IntoIterator<Item = (&'b SpriteBatch, BatchStyle)> {
+        if !self.initialized { return; }
+        unsafe {
+            self.begin(window_size, cam);
+            for (batch, style) in batches {
+                if batch.is_empty() { continue; }
+                self.set_style(&style);
+                gl::BindVertexArray(batch.vao);
+                gl::DrawArrays(gl::POINTS, 0, batch.len as i32);
+            }
+            self.end();
+        }
+    }
+
     /// Loads a passed set of RenderSprites to the screen. 
     pub fn render(&self, sprites: &[RenderSprite], window_size: (f32, f32), cam: (f32, f32, f32)){
 
         if !self.initialized { return; }
         unsafe {
-            gl::Enable(gl::BLEND);
-            gl::Enable(gl::DEPTH_TEST);
-
-            gl::UseProgram(self.shader);
+            self.begin(window_size, cam);
             gl::BindVertexArray(self.vao);
-            gl::BindTexture(gl::TEXTURE_2D, self.tex);
-            
-            let (winx, winy) = window_size;
-            gl::Viewport(0, 0, winx as i32, winy as i32);
-            
-            // Set uniforms
-                // view_projection
-            let projection = glm::ortho(0.0, winx, 0.0, winy, -25.0, 25.0);
-            let view: Mat4 = glm::translation(&Vec3::new(-cam.0, -cam.1, -cam.2));
-            let view_projection = projection * view;
-            gl::UniformMatrix4fv(self.uniform_locations[0], 1, gl::FALSE, 
-                                 view_projection.as_ptr());
-                
-                // sheet_width
-            gl::Uniform1i(self.uniform_locations[1], 250);
-                // sheet_tile_w
-            gl::Uniform1i(self.uniform_locations[2], 10);
 
             // Transfer sprite data
             gl::BindBuffer(gl::ARRAY_BUFFER, self.abo);
             gl::BufferData(
                 gl::ARRAY_BUFFER, 
-                (size_of::<RenderSprite>() * sprites.len()) as GLsizeiptr,
+                size_of_val(sprites) as GLsizeiptr,
                 sprites.as_ptr() as *const GLvoid,
                 gl::DYNAMIC_DRAW
             );
@@ -221,10 +381,7 @@ impl SpriteRenderer {
             // Render to main framebuffer
             gl::DrawArrays(gl::POINTS, 0, sprites.len() as i32);
          
-            // Unbind
-            gl::BindTexture(gl::TEXTURE_2D, 0);
-            gl::BindVertexArray(0);
-            gl::UseProgram(0);
+            self.end();
         }
  
     }
@@ -2,6 +2,7 @@
 
 use crate::EngineError;
 use crate::shader;
+use crate::model::layer::DEPTH_RANGE;
 
 use stb::image::LoadResult;
 use std::{
@@ -241,7 +242,8 @@ impl TextRenderer {
 
             // Uniforms
             let view: glm::Mat4 = glm::translation(&glm::Vec3::new(-cam.0, -cam.1, -cam.2));
-            let projection = glm::ortho(0.0, winx, 0.0, winy, -25.0, 25.0);
+            let (near, far) = DEPTH_RANGE;
+            let projection = glm::ortho(0.0, winx, 0.0, winy, near, far);
             let view_projection = projection * view;
             gl::UniformMatrix4fv(self.uniform_locations[0], 1, gl::FALSE, 
                                  view_projection.as_ptr());